@@ -0,0 +1,333 @@
+use anyhow::Context as _;
+use hmac::Mac as _;
+
+/// 署名したリクエストで端末名，時刻，署名を送るヘッダー
+pub const CLIENT_HEADER: &str = "x-kawauso-client";
+pub const TIMESTAMP_HEADER: &str = "x-kawauso-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-kawauso-signature";
+
+type HmacSha256 = hmac::Hmac<sha2::Sha256>;
+
+/// 端末の役割
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// レジ（注文と支払い）
+    Register,
+    /// キッチン（調理待ちの注文）
+    Kitchen,
+    /// 管理者（すべてのエンドポイント）
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Register => "register",
+            Role::Kitchen => "kitchen",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+/// エンドポイントを呼べる端末
+pub enum Access {
+    /// 認証なしで呼べる
+    Public,
+    /// これらの役割と`admin`だけが呼べる
+    Roles(&'static [Role]),
+}
+
+impl Access {
+    pub fn allows(&self, role: Role) -> bool {
+        match self {
+            Access::Public => true,
+            Access::Roles(roles) => role == Role::Admin || roles.contains(&role),
+        }
+    }
+}
+
+/// 認証した端末
+pub struct Client {
+    pub name: String,
+    pub role: Role,
+}
+
+/// リクエストのHMAC署名（`X-Kawauso-*`ヘッダー）
+pub struct Signature {
+    client: String,
+    timestamp: i64,
+    signature: Vec<u8>,
+}
+
+impl Signature {
+    /// ヘッダーから読む（`X-Kawauso-Signature`がなければ`None`）
+    pub fn from_headers(
+        headers: &actix_web::http::header::HeaderMap,
+    ) -> anyhow::Result<Option<Self>> {
+        let Some(signature) = headers.get(SIGNATURE_HEADER) else {
+            return Ok(None);
+        };
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .context(format!("missing {} header", name))
+        };
+
+        Ok(Some(Self {
+            client: header(CLIENT_HEADER)?.to_string(),
+            timestamp: header(TIMESTAMP_HEADER)?
+                .parse()
+                .context(format!("invalid {} header", TIMESTAMP_HEADER))?,
+            signature: hex::decode(signature.as_bytes())
+                .context(format!("invalid {} header", SIGNATURE_HEADER))?,
+        }))
+    }
+}
+
+/// `Authorization: Bearer`のAPIキー
+///
+/// `query`はヘッダーを付けられない`EventSource`で開くルートでだけ渡し，その`?access_token=`も読む
+pub fn api_key(
+    headers: &actix_web::http::header::HeaderMap,
+    query: Option<&str>,
+) -> Option<String> {
+    if let Some(value) = headers.get(actix_web::http::header::AUTHORIZATION) {
+        return value
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|key| key.trim().to_string());
+    }
+
+    #[derive(serde::Deserialize)]
+    struct KeyQuery {
+        access_token: Option<String>,
+    }
+    actix_web::web::Query::<KeyQuery>::from_query(query?)
+        .ok()
+        .and_then(|query| query.into_inner().access_token)
+}
+
+/// APIキーで認証する（署名を必須にした端末のキーは受け付けない）
+pub fn check_key(
+    clients: &std::collections::BTreeMap<String, crate::config::ClientConfig>,
+    key: &str,
+) -> anyhow::Result<Client> {
+    let (name, client) = clients
+        .iter()
+        .find(|(_, client)| {
+            // 途中で比較をやめると一致した長さが時間から分かるので，最後まで比べる
+            bool::from(subtle::ConstantTimeEq::ct_eq(
+                client.key.as_bytes(),
+                key.as_bytes(),
+            ))
+        })
+        .context("unknown API key")?;
+    if client.require_signature {
+        anyhow::bail!("client {} must sign requests", name);
+    }
+
+    Ok(Client {
+        name: name.clone(),
+        role: client.role,
+    })
+}
+
+/// 署名の検証と，同じ署名の使い回し（リプレイ）の検出
+#[derive(Default)]
+pub struct Authenticator {
+    /// 有効期間内に使った署名と，その時刻
+    seen: std::sync::Mutex<std::collections::HashMap<Vec<u8>, i64>>,
+}
+
+impl Authenticator {
+    /// 署名を確かめる
+    ///
+    /// 署名するのは`{時刻}\n{メソッド}\n{パスとクエリ}\n{ボディ}`．時刻が`signature_max_age_secs`よりずれた署名と，一度使った署名は拒否する
+    pub fn check_signature(
+        &self,
+        config: &crate::config::Config,
+        signature: &Signature,
+        method: &str,
+        path_and_query: &str,
+        body: &[u8],
+    ) -> anyhow::Result<Client> {
+        let client = config
+            .clients
+            .get(&signature.client)
+            .context(format!("unknown client: {}", signature.client))?;
+
+        let max_age = config.signature_max_age_secs;
+        let now = chrono::Utc::now().timestamp();
+        if now.abs_diff(signature.timestamp) > max_age {
+            anyhow::bail!(
+                "signature timestamp {} is more than {}s away from server time {}",
+                signature.timestamp,
+                max_age,
+                now
+            );
+        }
+
+        let mut mac = HmacSha256::new_from_slice(client.key.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(format!("{}\n{}\n{}\n", signature.timestamp, method, path_and_query).as_bytes());
+        mac.update(body);
+        mac.verify_slice(&signature.signature)
+            .map_err(|_| anyhow::anyhow!("invalid signature for client {}", signature.client))?;
+
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, timestamp| now.abs_diff(*timestamp) <= max_age);
+        if seen
+            .insert(signature.signature.clone(), signature.timestamp)
+            .is_some()
+        {
+            anyhow::bail!("signature already used by client {}", signature.client);
+        }
+
+        Ok(Client {
+            name: signature.client.clone(),
+            role: client.role,
+        })
+    }
+}
+
+/// CORSで許可するオリジンか（`*`ならすべて）
+pub fn allows_origin(allowed: &[String], origin: &[u8]) -> bool {
+    allowed
+        .iter()
+        .any(|allowed| allowed == "*" || allowed.as_bytes() == origin)
+}
+
+#[cfg(test)]
+mod tests {
+    use hmac::Mac as _;
+
+    fn config() -> crate::config::Config {
+        let mut config = crate::config::Config::default();
+        for (name, role, require_signature) in [
+            ("register", super::Role::Register, true),
+            ("kitchen", super::Role::Kitchen, false),
+        ] {
+            config.clients.insert(
+                name.to_string(),
+                crate::config::ClientConfig {
+                    role,
+                    key: format!("{}-key-0123456789", name),
+                    require_signature,
+                },
+            );
+        }
+        config
+    }
+
+    fn sign(key: &str, timestamp: i64, body: &[u8]) -> super::Signature {
+        let mut mac = super::HmacSha256::new_from_slice(key.as_bytes()).unwrap();
+        mac.update(format!("{}\nPOST\n/print/tag\n", timestamp).as_bytes());
+        mac.update(body);
+        super::Signature {
+            client: "register".to_string(),
+            timestamp,
+            signature: mac.finalize().into_bytes().to_vec(),
+        }
+    }
+
+    fn check(
+        authenticator: &super::Authenticator,
+        signature: &super::Signature,
+        body: &[u8],
+    ) -> anyhow::Result<super::Client> {
+        authenticator.check_signature(&config(), signature, "POST", "/print/tag", body)
+    }
+
+    #[test]
+    fn check_key_finds_the_client() {
+        let config = config();
+        let client = super::check_key(&config.clients, "kitchen-key-0123456789").unwrap();
+        assert_eq!(client.name, "kitchen");
+        assert_eq!(client.role, super::Role::Kitchen);
+
+        assert!(super::check_key(&config.clients, "kitchen-key").is_err());
+        // 署名を必須にした端末の鍵はAPIキーとして使えない
+        assert!(super::check_key(&config.clients, "register-key-0123456789").is_err());
+    }
+
+    #[test]
+    fn check_signature_accepts_a_fresh_signature_once() {
+        let authenticator = super::Authenticator::default();
+        let now = chrono::Utc::now().timestamp();
+        let signature = sign("register-key-0123456789", now, b"{}");
+
+        let client = check(&authenticator, &signature, b"{}").unwrap();
+        assert_eq!(client.name, "register");
+        assert_eq!(client.role, super::Role::Register);
+        assert!(check(&authenticator, &signature, b"{}").is_err());
+    }
+
+    #[test]
+    fn check_signature_rejects_bad_macs() {
+        let authenticator = super::Authenticator::default();
+        let now = chrono::Utc::now().timestamp();
+
+        let signature = sign("register-key-0123456789", now, b"{}");
+        assert!(check(&authenticator, &signature, b"{\"items\":[]}").is_err());
+        let signature = sign("kitchen-key-0123456789", now, b"{}");
+        assert!(check(&authenticator, &signature, b"{}").is_err());
+        let mut signature = sign("register-key-0123456789", now, b"{}");
+        signature.client = "unknown".to_string();
+        assert!(check(&authenticator, &signature, b"{}").is_err());
+    }
+
+    #[test]
+    fn check_signature_rejects_timestamps_out_of_range() {
+        let authenticator = super::Authenticator::default();
+        let max_age = config().signature_max_age_secs as i64;
+        let now = chrono::Utc::now().timestamp();
+
+        for timestamp in [now - max_age - 10, now + max_age + 10, i64::MIN, i64::MAX] {
+            let signature = sign("register-key-0123456789", timestamp, b"{}");
+            assert!(check(&authenticator, &signature, b"{}").is_err());
+        }
+    }
+
+    #[test]
+    fn admin_is_allowed_everywhere() {
+        let staff = super::Access::Roles(&[super::Role::Register, super::Role::Kitchen]);
+        assert!(staff.allows(super::Role::Register));
+        assert!(staff.allows(super::Role::Admin));
+
+        let admin_only = super::Access::Roles(&[]);
+        assert!(!admin_only.allows(super::Role::Kitchen));
+        assert!(admin_only.allows(super::Role::Admin));
+        assert!(super::Access::Public.allows(super::Role::Kitchen));
+    }
+
+    #[test]
+    fn allows_listed_origins_or_any_with_wildcard() {
+        let allowed = ["https://register.example".to_string()];
+        assert!(super::allows_origin(&allowed, b"https://register.example"));
+        assert!(!super::allows_origin(
+            &allowed,
+            b"https://register.example.evil"
+        ));
+        assert!(!super::allows_origin(&[], b"https://register.example"));
+        assert!(super::allows_origin(&["*".to_string()], b"http://anything"));
+    }
+
+    #[test]
+    fn api_key_reads_the_query_only_when_given() {
+        let mut headers = actix_web::http::header::HeaderMap::new();
+        assert_eq!(
+            super::api_key(&headers, Some("access_token=abc")),
+            Some("abc".to_string())
+        );
+        assert_eq!(super::api_key(&headers, None), None);
+
+        headers.insert(
+            actix_web::http::header::AUTHORIZATION,
+            actix_web::http::header::HeaderValue::from_static("Bearer xyz"),
+        );
+        assert_eq!(super::api_key(&headers, None), Some("xyz".to_string()));
+    }
+}
@@ -0,0 +1,254 @@
+use anyhow::Context as _;
+
+/// `BlobStore`の非同期メソッドの戻り値
+pub type BlobFuture<'a, T> =
+    std::pin::Pin<Box<dyn std::future::Future<Output = anyhow::Result<T>> + Send + 'a>>;
+
+/// 保存したオブジェクトの中身
+pub struct Blob {
+    pub data: Vec<u8>,
+    pub content_type: String,
+}
+
+/// 署名済みPDFなどを置くオブジェクトストレージ
+pub trait BlobStore: Send + Sync {
+    /// ログ表示用の名前
+    fn name(&self) -> String;
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>, content_type: &'a str) -> BlobFuture<'a, ()>;
+
+    /// 存在しなければ`None`
+    fn get<'a>(&'a self, key: &'a str) -> BlobFuture<'a, Option<Blob>>;
+
+    /// `expires_in`だけ有効なダウンロード用URL
+    ///
+    /// 発行できないストレージは`None`を返す（kawausoが`get`した中身をそのまま返す）
+    fn presigned_url<'a>(
+        &'a self,
+        _key: &'a str,
+        _expires_in: std::time::Duration,
+    ) -> BlobFuture<'a, Option<String>> {
+        Box::pin(async { Ok(None) })
+    }
+}
+
+/// 設定からストレージを選ぶ
+///
+/// - `s3`: S3互換ストレージ（R2）のバケット`bucket_name`に置く（既定）
+/// - `local`: `dir`以下に保存する
+/// - `memory`: メモリ上に置く（再起動で消える．開発・テスト用）
+pub async fn from_config(
+    config: &crate::config::StorageConfig,
+) -> anyhow::Result<std::sync::Arc<dyn BlobStore>> {
+    let store: std::sync::Arc<dyn BlobStore> = match config.backend.as_str() {
+        "s3" => {
+            let bucket_name = config
+                .bucket_name
+                .clone()
+                .context("R2_BUCKET_NAME is required for s3 storage")?;
+
+            let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+            std::sync::Arc::new(crate::r2_client::R2Client::new(
+                aws_sdk_s3::Client::new(&aws_config),
+                bucket_name,
+            ))
+        }
+        "local" => std::sync::Arc::new(LocalStore::new(config.dir.clone().into())),
+        "memory" => std::sync::Arc::new(MemoryStore::new()),
+        other => anyhow::bail!("unknown storage backend: {}", other),
+    };
+
+    Ok(store)
+}
+
+/// ローカルのディレクトリに保存する
+pub struct LocalStore {
+    dir: std::path::PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(dir: std::path::PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// キーをディレクトリ内のパスにする（`..`などでディレクトリの外を指すキーは拒否する）
+    fn path(&self, key: &str) -> anyhow::Result<std::path::PathBuf> {
+        let relative = std::path::Path::new(key);
+        if key.is_empty()
+            || !relative
+                .components()
+                .all(|c| matches!(c, std::path::Component::Normal(_)))
+        {
+            anyhow::bail!("invalid object key: {}", key);
+        }
+        Ok(self.dir.join(relative))
+    }
+}
+
+impl BlobStore for LocalStore {
+    fn name(&self) -> String {
+        format!("local ({})", self.dir.display())
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        data: Vec<u8>,
+        _content_type: &'a str,
+    ) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .context(format!("failed to create directory: {}", parent.display()))?;
+            }
+            tokio::fs::write(&path, data)
+                .await
+                .context(format!("failed to write {}", path.display()))?;
+            tracing::debug!("Stored {} in {}", key, self.dir.display());
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BlobFuture<'a, Option<Blob>> {
+        Box::pin(async move {
+            // ディレクトリの外を指すキーは存在しないものとして扱う
+            let Ok(path) = self.path(key) else {
+                return Ok(None);
+            };
+            match tokio::fs::read(&path).await {
+                Ok(data) => Ok(Some(Blob {
+                    data,
+                    content_type: content_type_for(key).to_string(),
+                })),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e).context(format!("failed to read {}", path.display())),
+            }
+        })
+    }
+}
+
+/// ローカルのファイルは拡張子からContent-Typeを決める
+fn content_type_for(key: &str) -> &'static str {
+    match std::path::Path::new(key)
+        .extension()
+        .and_then(|ext| ext.to_str())
+    {
+        Some("pdf") => "application/pdf",
+        Some("png") => "image/png",
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+/// メモリ上に保存する（再起動で消える）
+pub struct MemoryStore {
+    objects: std::sync::Mutex<std::collections::HashMap<String, (Vec<u8>, String)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            objects: Default::default(),
+        }
+    }
+}
+
+impl BlobStore for MemoryStore {
+    fn name(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>, content_type: &'a str) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            self.objects
+                .lock()
+                .map_err(|_| anyhow::anyhow!("memory store mutex poisoned"))?
+                .insert(key.to_string(), (data, content_type.to_string()));
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BlobFuture<'a, Option<Blob>> {
+        Box::pin(async move {
+            let objects = self
+                .objects
+                .lock()
+                .map_err(|_| anyhow::anyhow!("memory store mutex poisoned"))?;
+            Ok(objects.get(key).map(|(data, content_type)| Blob {
+                data: data.clone(),
+                content_type: content_type.clone(),
+            }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlobStore as _;
+
+    /// テストごとに別の一時ディレクトリ（終わったら消す）
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            Self(std::env::temp_dir().join(format!("kawauso-test-{}", uuid::Uuid::new_v4())))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    async fn round_trip(store: &dyn super::BlobStore) {
+        store
+            .put("signed/a.pdf", b"%PDF".to_vec(), "application/pdf")
+            .await
+            .unwrap();
+        let blob = store.get("signed/a.pdf").await.unwrap().unwrap();
+        assert_eq!(blob.data, b"%PDF");
+        assert_eq!(blob.content_type, "application/pdf");
+        assert!(store.get("signed/b.pdf").await.unwrap().is_none());
+        assert!(
+            store
+                .presigned_url("signed/a.pdf", std::time::Duration::from_secs(60))
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_store_round_trip() {
+        round_trip(&super::MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn local_store_round_trip() {
+        let dir = TempDir::new();
+        round_trip(&super::LocalStore::new(dir.0.clone())).await;
+        assert!(dir.0.join("signed").join("a.pdf").is_file());
+    }
+
+    #[tokio::test]
+    async fn local_store_rejects_keys_outside_its_directory() {
+        let dir = TempDir::new();
+        let store = super::LocalStore::new(dir.0.join("store"));
+
+        for key in [
+            "",
+            "../a.pdf",
+            "signed/../../a.pdf",
+            "/tmp/a.pdf",
+            "./a.pdf",
+        ] {
+            assert!(store.path(key).is_err(), "{:?}", key);
+            assert!(store.put(key, Vec::new(), "application/pdf").await.is_err());
+            assert!(store.get(key).await.unwrap().is_none());
+        }
+        assert!(!dir.0.join("a.pdf").exists());
+    }
+}
@@ -0,0 +1,507 @@
+use rusqlite::OptionalExtension as _;
+
+/// 自動で割り当てる呼び出し番号の上限（超えたら1に戻る）
+const MAX_SEQUENTIAL: u32 = 999;
+
+/// 呼び出し番号の状態（この順に進む）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CallStatus {
+    /// 調理中
+    Cooking,
+    /// できあがって受け取り待ち
+    Ready,
+    /// 番号を呼んだ
+    Called,
+    /// お客さんに渡した
+    PickedUp,
+    /// 受け取りに来なかったなどで取り消した
+    Cancelled,
+}
+
+impl CallStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CallStatus::Cooking => "cooking",
+            CallStatus::Ready => "ready",
+            CallStatus::Called => "called",
+            CallStatus::PickedUp => "picked_up",
+            CallStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "cooking" => Some(CallStatus::Cooking),
+            "ready" => Some(CallStatus::Ready),
+            "called" => Some(CallStatus::Called),
+            "picked_up" => Some(CallStatus::PickedUp),
+            "cancelled" => Some(CallStatus::Cancelled),
+            _ => None,
+        }
+    }
+
+    /// 受け渡しが終わっていない（同じ番号を使えない）
+    pub fn is_active(&self) -> bool {
+        !matches!(self, CallStatus::PickedUp | CallStatus::Cancelled)
+    }
+}
+
+/// 注文1件分の呼び出し番号
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CallNumber {
+    pub id: i64,
+    pub tag: String,
+    pub status: CallStatus,
+    /// 売上台帳の注文
+    #[serde(rename = "saleId")]
+    pub sale_id: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: i64,
+    #[serde(rename = "readyAt")]
+    pub ready_at: Option<i64>,
+    #[serde(rename = "calledAt")]
+    pub called_at: Option<i64>,
+    /// 受け渡しまたは取り消しの日時
+    #[serde(rename = "finishedAt")]
+    pub finished_at: Option<i64>,
+}
+
+impl CallNumber {
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        let status: String = row.get(2)?;
+        Ok(Self {
+            id: row.get(0)?,
+            tag: row.get(1)?,
+            status: CallStatus::parse(&status).ok_or_else(|| {
+                rusqlite::Error::InvalidColumnType(
+                    2,
+                    format!("unknown call status: {}", status),
+                    rusqlite::types::Type::Text,
+                )
+            })?,
+            sale_id: row.get(3)?,
+            created_at: row.get(4)?,
+            ready_at: row.get(5)?,
+            called_at: row.get(6)?,
+            finished_at: row.get(7)?,
+        })
+    }
+}
+
+const COLUMNS: &str =
+    "id, tag, status, sale_id, created_at, ready_at, called_at, finished_at FROM call_numbers";
+
+/// 呼び出し番号を発行した結果
+pub enum Issue {
+    Issued(CallNumber),
+    /// 同じ番号の注文がまだ受け渡されていない
+    Duplicate(CallNumber),
+    /// 連番がすべて使用中
+    Exhausted,
+}
+
+/// 状態を進めた結果
+pub enum Advance {
+    Advanced(CallNumber),
+    /// すでにその状態だった
+    Unchanged(CallNumber),
+    /// 前の状態には戻せない（受け渡しや取り消しのあとは変えられない）
+    Backward(CallNumber),
+    NotFound,
+}
+
+/// 店頭ディスプレイに出す番号
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Board {
+    /// 調理中
+    pub cooking: Vec<String>,
+    /// 受け取りに来てほしい（できあがった，または呼び出した）
+    pub ready: Vec<String>,
+}
+
+/// 呼び出し番号の発行から受け渡しまでを管理する
+pub struct CallNumbers {
+    db: std::sync::Arc<crate::db::Database>,
+    /// 番号の状態が変わるたびに増える
+    changes: tokio::sync::watch::Sender<u64>,
+}
+
+impl CallNumbers {
+    pub fn new(db: std::sync::Arc<crate::db::Database>) -> Self {
+        let (changes, _) = tokio::sync::watch::channel(0);
+        Self { db, changes }
+    }
+
+    /// 番号の状態が変わったことを受け取る
+    pub fn subscribe(&self) -> tokio::sync::watch::Receiver<u64> {
+        self.changes.subscribe()
+    }
+
+    fn notify(&self) {
+        self.changes.send_modify(|version| *version += 1);
+    }
+
+    /// 受け渡し前の番号を店頭ディスプレイ向けに分ける
+    pub async fn board(&self) -> anyhow::Result<Board> {
+        let (ready, cooking): (Vec<_>, Vec<_>) = self
+            .list(None)
+            .await?
+            .into_iter()
+            .partition(|call| call.status != CallStatus::Cooking);
+
+        Ok(Board {
+            cooking: cooking.into_iter().map(|call| call.tag).collect(),
+            ready: ready.into_iter().map(|call| call.tag).collect(),
+        })
+    }
+
+    /// 呼び出し番号を発行する
+    ///
+    /// `tag`を省略すると，その営業日（日本時間）の連番から使われていない番号を割り当てる
+    pub async fn issue(&self, tag: Option<&str>) -> anyhow::Result<Issue> {
+        let tag = tag.map(|t| t.to_string());
+        let issue = self
+            .db
+            .call(move |conn| {
+                let now = crate::db::now();
+                let tx = conn.transaction()?;
+
+                let tag = match tag {
+                    Some(tag) => {
+                        if let Some(active) = find_active(&tx, &tag)? {
+                            return Ok(Issue::Duplicate(active));
+                        }
+                        tag
+                    }
+                    None => match next_sequential(&tx, now)? {
+                        Some(tag) => tag,
+                        None => return Ok(Issue::Exhausted),
+                    },
+                };
+
+                tx.execute(
+                    "INSERT INTO call_numbers (tag, status, created_at, updated_at)
+                     VALUES (?1, ?2, ?3, ?3)",
+                    rusqlite::params![tag, CallStatus::Cooking.as_str(), now],
+                )?;
+                let issued = tx.query_row(
+                    &format!("SELECT {} WHERE id = ?1", COLUMNS),
+                    [tx.last_insert_rowid()],
+                    CallNumber::from_row,
+                )?;
+
+                tx.commit()?;
+                Ok(Issue::Issued(issued))
+            })
+            .await?;
+
+        if matches!(issue, Issue::Issued(_)) {
+            self.notify();
+        }
+        Ok(issue)
+    }
+
+    /// 売上台帳に記録した注文と結びつける（キッチンの画面に品目が出るようになる）
+    pub async fn attach_sale(&self, id: i64, sale_id: &str) -> anyhow::Result<()> {
+        let sale_id = sale_id.to_string();
+        self.db
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE call_numbers SET sale_id = ?2, updated_at = ?3 WHERE id = ?1",
+                    rusqlite::params![id, sale_id, crate::db::now()],
+                )
+            })
+            .await?;
+
+        self.notify();
+        Ok(())
+    }
+
+    /// 調理中の番号と，売上台帳に記録した品目を古い順に返す
+    pub async fn cooking_orders(
+        &self,
+    ) -> anyhow::Result<Vec<(CallNumber, Vec<crate::kitchen::KitchenItem>)>> {
+        self.db
+            .call(|conn| {
+                let calls = conn
+                    .prepare(&format!("SELECT {} WHERE status = ?1 ORDER BY id", COLUMNS))?
+                    .query_map([CallStatus::Cooking.as_str()], CallNumber::from_row)?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+
+                let mut stmt = conn.prepare(
+                    "SELECT sku, name, qty FROM sale_items WHERE sale_id = ?1 ORDER BY line_no",
+                )?;
+                calls
+                    .into_iter()
+                    .map(|call| {
+                        let items = match &call.sale_id {
+                            Some(sale_id) => stmt
+                                .query_map([sale_id], |row| {
+                                    Ok(crate::kitchen::KitchenItem {
+                                        sku: row.get(0)?,
+                                        name: row.get(1)?,
+                                        qty: row.get(2)?,
+                                    })
+                                })?
+                                .collect::<rusqlite::Result<Vec<_>>>()?,
+                            None => Vec::new(),
+                        };
+                        Ok((call, items))
+                    })
+                    .collect()
+            })
+            .await
+    }
+
+    /// 番号の状態を進める（取り消しは受け渡し前ならいつでもできる）
+    pub async fn advance(&self, tag: &str, status: CallStatus) -> anyhow::Result<Advance> {
+        let tag = tag.to_string();
+        let advance = self
+            .db
+            .call(move |conn| {
+                let now = crate::db::now();
+                let tx = conn.transaction()?;
+
+                let Some(current) = latest(&tx, &tag)? else {
+                    return Ok(Advance::NotFound);
+                };
+                if current.status == status {
+                    return Ok(Advance::Unchanged(current));
+                }
+                if !current.status.is_active() || status < current.status {
+                    return Ok(Advance::Backward(current));
+                }
+
+                let column = match status {
+                    CallStatus::Cooking => unreachable!("cooking is the first status"),
+                    CallStatus::Ready => "ready_at",
+                    CallStatus::Called => "called_at",
+                    CallStatus::PickedUp | CallStatus::Cancelled => "finished_at",
+                };
+                tx.execute(
+                    &format!(
+                        "UPDATE call_numbers SET status = ?2, {} = ?3, updated_at = ?3 WHERE id = ?1",
+                        column
+                    ),
+                    rusqlite::params![current.id, status.as_str(), now],
+                )?;
+                let advanced = tx.query_row(
+                    &format!("SELECT {} WHERE id = ?1", COLUMNS),
+                    [current.id],
+                    CallNumber::from_row,
+                )?;
+
+                tx.commit()?;
+                Ok(Advance::Advanced(advanced))
+            })
+            .await?;
+
+        if matches!(advance, Advance::Advanced(_)) {
+            self.notify();
+        }
+        Ok(advance)
+    }
+
+    /// 番号の最新の注文
+    pub async fn get(&self, tag: &str) -> anyhow::Result<Option<CallNumber>> {
+        let tag = tag.to_string();
+        self.db.call(move |conn| latest(conn, &tag)).await
+    }
+
+    /// 状態を指定しなければ受け渡し前の番号を，古い順に返す
+    pub async fn list(&self, status: Option<CallStatus>) -> anyhow::Result<Vec<CallNumber>> {
+        self.db
+            .call(move |conn| {
+                let mut stmt = match status {
+                    Some(_) => {
+                        conn.prepare(&format!("SELECT {} WHERE status = ?1 ORDER BY id", COLUMNS))?
+                    }
+                    None => conn.prepare(&format!(
+                        "SELECT {} WHERE status NOT IN ('picked_up', 'cancelled') ORDER BY id",
+                        COLUMNS
+                    ))?,
+                };
+                match status {
+                    Some(status) => stmt
+                        .query_map([status.as_str()], CallNumber::from_row)?
+                        .collect(),
+                    None => stmt.query_map([], CallNumber::from_row)?.collect(),
+                }
+            })
+            .await
+    }
+}
+
+fn find_active(conn: &rusqlite::Connection, tag: &str) -> rusqlite::Result<Option<CallNumber>> {
+    conn.query_row(
+        &format!(
+            "SELECT {} WHERE tag = ?1 AND status NOT IN ('picked_up', 'cancelled')",
+            COLUMNS
+        ),
+        [tag],
+        CallNumber::from_row,
+    )
+    .optional()
+}
+
+fn latest(conn: &rusqlite::Connection, tag: &str) -> rusqlite::Result<Option<CallNumber>> {
+    conn.query_row(
+        &format!("SELECT {} WHERE tag = ?1 ORDER BY id DESC LIMIT 1", COLUMNS),
+        [tag],
+        CallNumber::from_row,
+    )
+    .optional()
+}
+
+/// 営業日ごとの連番の次で，使われていない番号（すべて使用中ならNone）
+fn next_sequential(tx: &rusqlite::Transaction<'_>, now: i64) -> rusqlite::Result<Option<String>> {
+    let business_date = (chrono::DateTime::from_timestamp(now, 0).unwrap_or_default()
+        + chrono::Duration::hours(9))
+    .format("%Y-%m-%d")
+    .to_string();
+
+    let mut last: u32 = tx
+        .query_row(
+            "SELECT last FROM call_number_sequences WHERE business_date = ?1",
+            [&business_date],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(0);
+
+    for _ in 0..MAX_SEQUENTIAL {
+        last = last % MAX_SEQUENTIAL + 1;
+        let tag = last.to_string();
+        if find_active(tx, &tag)?.is_none() {
+            tx.execute(
+                "INSERT INTO call_number_sequences (business_date, last) VALUES (?1, ?2)
+                 ON CONFLICT (business_date) DO UPDATE SET last = excluded.last",
+                rusqlite::params![business_date, last],
+            )?;
+            return Ok(Some(tag));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    fn call_numbers() -> super::CallNumbers {
+        let db = crate::db::Database::open(":memory:").unwrap();
+        super::CallNumbers::new(std::sync::Arc::new(db))
+    }
+
+    async fn issue(calls: &super::CallNumbers, tag: Option<&str>) -> super::CallNumber {
+        match calls.issue(tag).await.unwrap() {
+            super::Issue::Issued(call) => call,
+            _ => panic!("call number was not issued"),
+        }
+    }
+
+    async fn advance(
+        calls: &super::CallNumbers,
+        tag: &str,
+        status: super::CallStatus,
+    ) -> super::Advance {
+        calls.advance(tag, status).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn assigns_sequential_numbers_that_are_not_in_use() {
+        let calls = call_numbers();
+        issue(&calls, Some("2")).await;
+
+        let tags = [
+            issue(&calls, None).await.tag,
+            issue(&calls, None).await.tag,
+            issue(&calls, None).await.tag,
+        ];
+        assert_eq!(tags, ["1", "3", "4"]);
+    }
+
+    #[tokio::test]
+    async fn rejects_active_duplicates_until_finished() {
+        let calls = call_numbers();
+        let first = issue(&calls, Some("7")).await;
+        assert!(matches!(
+            calls.issue(Some("7")).await.unwrap(),
+            super::Issue::Duplicate(call) if call.id == first.id
+        ));
+
+        // 取り消した番号はもう一度使える
+        advance(&calls, "7", super::CallStatus::Cancelled).await;
+        let second = issue(&calls, Some("7")).await;
+        assert_ne!(second.id, first.id);
+        assert_eq!(
+            calls.get("7").await.unwrap().unwrap().status,
+            super::CallStatus::Cooking
+        );
+    }
+
+    #[tokio::test]
+    async fn advances_forward_and_records_when() {
+        let calls = call_numbers();
+        issue(&calls, Some("1")).await;
+        let changes = calls.subscribe();
+
+        let super::Advance::Advanced(call) = advance(&calls, "1", super::CallStatus::Ready).await
+        else {
+            panic!("not advanced");
+        };
+        assert_eq!(call.status, super::CallStatus::Ready);
+        assert!(call.ready_at.is_some());
+        assert!(changes.has_changed().unwrap());
+
+        // 呼び出しを飛ばして渡してもよい
+        let super::Advance::Advanced(call) =
+            advance(&calls, "1", super::CallStatus::PickedUp).await
+        else {
+            panic!("not advanced");
+        };
+        assert!(call.called_at.is_none());
+        assert!(call.finished_at.is_some());
+        assert!(calls.list(None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn does_not_go_back_or_change_finished_numbers() {
+        let calls = call_numbers();
+        issue(&calls, Some("1")).await;
+        advance(&calls, "1", super::CallStatus::Called).await;
+
+        assert!(matches!(
+            advance(&calls, "1", super::CallStatus::Called).await,
+            super::Advance::Unchanged(_)
+        ));
+        assert!(matches!(
+            advance(&calls, "1", super::CallStatus::Ready).await,
+            super::Advance::Backward(_)
+        ));
+
+        advance(&calls, "1", super::CallStatus::PickedUp).await;
+        assert!(matches!(
+            advance(&calls, "1", super::CallStatus::Cancelled).await,
+            super::Advance::Backward(_)
+        ));
+        assert!(matches!(
+            advance(&calls, "2", super::CallStatus::Ready).await,
+            super::Advance::NotFound
+        ));
+    }
+
+    #[tokio::test]
+    async fn board_splits_cooking_and_ready() {
+        let calls = call_numbers();
+        for tag in ["1", "2", "3"] {
+            issue(&calls, Some(tag)).await;
+        }
+        advance(&calls, "2", super::CallStatus::Called).await;
+        advance(&calls, "3", super::CallStatus::Cancelled).await;
+
+        let board = calls.board().await.unwrap();
+        assert_eq!(board.cooking, ["1"]);
+        assert_eq!(board.ready, ["2"]);
+    }
+}
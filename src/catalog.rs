@@ -0,0 +1,332 @@
+use anyhow::Context as _;
+
+/// 商品カタログ（学園祭ごとにメニューが変わるのでファイルから読み込む）
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Catalog {
+    items: Vec<CatalogItem>,
+    #[serde(default)]
+    discounts: Vec<SetDiscount>,
+    /// 1回の注文で受け付ける1品目あたりの数量の上限
+    #[serde(default = "default_max_qty")]
+    max_qty: u32,
+    /// `/print/pdf`で売るPDF版の品目（支払いの金額はこの単価×枚数）
+    #[serde(default)]
+    pdf_sku: Option<String>,
+}
+
+fn default_max_qty() -> u32 {
+    99
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct CatalogItem {
+    pub sku: String,
+    /// レシートに印字する品名
+    pub name: String,
+    /// 単価（円）
+    pub price: u32,
+    /// レシート上の並び順（小さいほど上）
+    #[serde(default)]
+    pub sort_order: i32,
+    #[serde(default)]
+    pub label: ItemLabel,
+}
+
+/// レシート上での品目の表示方法
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ItemLabel {
+    /// 品名をフォントで描画する（`FONT_PATH`が必要）
+    #[default]
+    Text,
+    /// 切り出し済みの`{dir}/line_{数量}.png`を使う
+    ///
+    /// 1枚の画像で表せる数量が`per_image`を超える場合は次のディレクトリの画像に続ける
+    Images {
+        dirs: Vec<String>,
+        #[serde(default = "default_per_image")]
+        per_image: u32,
+    },
+}
+
+fn default_per_image() -> u32 {
+    10
+}
+
+/// セット割引（すべての構成要素がそろうごとに`amount`円引く）
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SetDiscount {
+    pub name: String,
+    pub components: Vec<SetComponent>,
+    /// 1セットあたりの値引き額（円）
+    pub amount: u32,
+}
+
+/// セットの構成要素（`skus`のいずれかを合わせて`qty`個）
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SetComponent {
+    pub skus: Vec<String>,
+    #[serde(default = "default_component_qty")]
+    pub qty: u32,
+}
+
+fn default_component_qty() -> u32 {
+    1
+}
+
+/// リクエストで受け取る注文の1行
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct OrderItem {
+    pub sku: String,
+    pub qty: u32,
+}
+
+/// カタログと照合済みの注文の1行
+#[derive(Debug, Clone)]
+pub struct OrderLine<'a> {
+    pub item: &'a CatalogItem,
+    pub qty: u32,
+}
+
+impl OrderLine<'_> {
+    /// 割引前の小計（円）
+    ///
+    /// 数量は`Catalog::resolve`で`max_qty`以下に限り，単価と`max_qty`の積は`Catalog::load`で確かめているので桁あふれしない
+    pub fn subtotal(&self) -> u32 {
+        self.item.price * self.qty
+    }
+}
+
+impl Catalog {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let text =
+            std::fs::read_to_string(path).context(format!("failed to read catalog: {}", path))?;
+        Self::parse(&text).context(format!("failed to parse catalog: {}", path))
+    }
+
+    /// TOMLのカタログを読み，SKUの重複や割引の参照先を確かめる
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut catalog: Catalog = toml::from_str(text)?;
+
+        if catalog.max_qty == 0 {
+            anyhow::bail!("max_qty must be at least 1");
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        for item in &catalog.items {
+            if !seen.insert(item.sku.as_str()) {
+                anyhow::bail!("duplicate SKU in catalog: {}", item.sku);
+            }
+            if item.price.checked_mul(catalog.max_qty).is_none() {
+                anyhow::bail!("price of {} is too large for max_qty", item.sku);
+            }
+        }
+
+        if let Some(sku) = &catalog.pdf_sku
+            && !seen.contains(sku.as_str())
+        {
+            anyhow::bail!("pdf_sku refers to unknown SKU: {}", sku);
+        }
+
+        for discount in &catalog.discounts {
+            for component in &discount.components {
+                if component.qty == 0 {
+                    anyhow::bail!("discount {} has a component with qty 0", discount.name);
+                }
+                if discount.amount.checked_mul(catalog.max_qty).is_none() {
+                    anyhow::bail!("amount of discount {} is too large", discount.name);
+                }
+                for sku in &component.skus {
+                    if !seen.contains(sku.as_str()) {
+                        anyhow::bail!("discount {} refers to unknown SKU: {}", discount.name, sku);
+                    }
+                }
+            }
+        }
+
+        catalog.items.sort_by_key(|item| item.sort_order);
+
+        Ok(catalog)
+    }
+
+    /// 並び順に整列した全品目
+    pub fn items(&self) -> &[CatalogItem] {
+        &self.items
+    }
+
+    pub fn discounts(&self) -> &[SetDiscount] {
+        &self.discounts
+    }
+
+    pub fn get(&self, sku: &str) -> Option<&CatalogItem> {
+        self.items.iter().find(|item| item.sku == sku)
+    }
+
+    /// 1回の注文で受け付ける1品目あたりの数量の上限
+    pub fn max_qty(&self) -> u32 {
+        self.max_qty
+    }
+
+    /// PDF版の品目のSKU（設定していなければ`None`）
+    pub fn pdf_sku(&self) -> Option<&str> {
+        self.pdf_sku.as_deref()
+    }
+
+    /// PDFを`count`枚発行する支払いの金額（`pdf_sku`を設定していなければ`None`）
+    pub fn pdf_total(&self, count: u32) -> anyhow::Result<Option<u32>> {
+        let Some(item) = self.pdf_sku().and_then(|sku| self.get(sku)) else {
+            return Ok(None);
+        };
+        item.price
+            .checked_mul(count)
+            .map(Some)
+            .context(format!("too many {} ({} copies)", item.sku, count))
+    }
+
+    /// フォントで描画する品目があるか
+    pub fn has_text_labels(&self) -> bool {
+        self.items
+            .iter()
+            .any(|item| matches!(item.label, ItemLabel::Text))
+    }
+
+    /// 注文をカタログと照合し，並び順に整列した明細を返す
+    ///
+    /// 同じSKUが複数回現れた場合は数量を合算し，0個の品目は除く．合算した数量が`max_qty`を超える品目があればエラー
+    pub fn resolve(&self, items: &[OrderItem]) -> anyhow::Result<Vec<OrderLine<'_>>> {
+        let mut quantities: std::collections::HashMap<&str, u32> = std::collections::HashMap::new();
+        for order_item in items {
+            if self.get(&order_item.sku).is_none() {
+                anyhow::bail!("unknown SKU: {}", order_item.sku);
+            }
+            let qty = quantities.entry(order_item.sku.as_str()).or_default();
+            *qty = qty
+                .checked_add(order_item.qty)
+                .filter(|qty| *qty <= self.max_qty)
+                .context(format!(
+                    "too many {} (at most {} per order)",
+                    order_item.sku, self.max_qty
+                ))?;
+        }
+
+        Ok(self
+            .items
+            .iter()
+            .filter_map(|item| {
+                quantities
+                    .get(item.sku.as_str())
+                    .filter(|qty| **qty > 0)
+                    .map(|qty| OrderLine { item, qty: *qty })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    const CATALOG: &str = r#"
+        [[items]]
+        sku = "drink"
+        name = "ドリンク"
+        price = 100
+        sort_order = 20
+
+        [[items]]
+        sku = "ff"
+        name = "フランクフルト"
+        price = 300
+        sort_order = 10
+        label = { type = "images", dirs = ["img/ff"] }
+    "#;
+
+    fn order(items: &[(&str, u32)]) -> Vec<super::OrderItem> {
+        items
+            .iter()
+            .map(|(sku, qty)| super::OrderItem {
+                sku: sku.to_string(),
+                qty: *qty,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sorts_items_and_defaults_to_text_labels() {
+        let catalog = super::Catalog::parse(CATALOG).unwrap();
+        let skus: Vec<&str> = catalog
+            .items()
+            .iter()
+            .map(|item| item.sku.as_str())
+            .collect();
+        assert_eq!(skus, ["ff", "drink"]);
+        assert!(matches!(
+            catalog.get("ff").unwrap().label,
+            super::ItemLabel::Images { per_image: 10, .. }
+        ));
+        assert!(matches!(
+            catalog.get("drink").unwrap().label,
+            super::ItemLabel::Text
+        ));
+        assert!(catalog.has_text_labels());
+    }
+
+    #[test]
+    fn rejects_duplicate_skus() {
+        let text = format!(
+            "{}\n[[items]]\nsku = \"ff\"\nname = \"もう一つ\"\nprice = 1\n",
+            CATALOG
+        );
+        assert!(super::Catalog::parse(&text).is_err());
+    }
+
+    #[test]
+    fn rejects_discounts_on_unknown_skus() {
+        let text = format!(
+            "{}\n[[discounts]]\nname = \"セット\"\namount = 50\ncomponents = [{{ skus = [\"cola\"] }}]\n",
+            CATALOG
+        );
+        assert!(super::Catalog::parse(&text).is_err());
+    }
+
+    #[test]
+    fn rejects_prices_that_overflow_at_max_qty() {
+        let text = "max_qty = 3\n[[items]]\nsku = \"a\"\nname = \"A\"\nprice = 2000000000\n";
+        assert!(super::Catalog::parse(text).is_err());
+    }
+
+    #[test]
+    fn resolve_merges_skus_in_catalog_order() {
+        let catalog = super::Catalog::parse(CATALOG).unwrap();
+        let lines = catalog
+            .resolve(&order(&[("drink", 1), ("ff", 2), ("drink", 2)]))
+            .unwrap();
+        let resolved: Vec<(&str, u32)> = lines
+            .iter()
+            .map(|line| (line.item.sku.as_str(), line.qty))
+            .collect();
+        assert_eq!(resolved, [("ff", 2), ("drink", 3)]);
+    }
+
+    #[test]
+    fn resolve_drops_zero_quantities_and_rejects_unknown_skus() {
+        let catalog = super::Catalog::parse(CATALOG).unwrap();
+        let lines = catalog.resolve(&order(&[("ff", 0), ("drink", 1)])).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].item.sku, "drink");
+        assert!(catalog.resolve(&order(&[("cola", 1)])).is_err());
+    }
+
+    #[test]
+    fn pdf_total_uses_the_pdf_sku_price() {
+        let catalog = super::Catalog::parse(&format!("pdf_sku = \"drink\"\n{}", CATALOG)).unwrap();
+        assert_eq!(catalog.pdf_total(3).unwrap(), Some(300));
+        assert!(catalog.pdf_total(u32::MAX).is_err());
+        assert_eq!(
+            super::Catalog::parse(CATALOG)
+                .unwrap()
+                .pdf_total(3)
+                .unwrap(),
+            None
+        );
+        assert!(super::Catalog::parse(&format!("pdf_sku = \"cola\"\n{}", CATALOG)).is_err());
+    }
+}
@@ -1,19 +1,569 @@
-#[derive(Debug, Clone)]
+use anyhow::Context as _;
+
+/// 設定（既定値 < 設定ファイル < 環境変数 < コマンドライン引数の順に上書きする）
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
+    /// 待ち受けるアドレス
+    pub bind_address: String,
+    /// 署名するPDF
     pub base_pdf_path: String,
-    pub r2_bucket_name: String,
-    pub r2_public_url: String,
-    pub printer_name: String,
+    /// 署名に使う証明書
+    pub cert_path: String,
+    /// 署名に使うPKCS#8形式の秘密鍵
+    pub key_path: String,
+    /// 署名済みPDFの保存先
+    pub storage: StorageConfig,
+    /// 署名済みPDFの控えを保存するディレクトリ（`/verify?pdfId=`で使う）
+    pub signed_pdf_dir: String,
+    /// 印刷したESC/POSデータを保存するディレクトリ
+    pub receipts_dir: String,
+    /// お客さんの端末から見たkawausoのURL（レシートのQRコードの`/download/{pdf_id}`に使う）
+    pub public_base_url: String,
+    /// PDF 1つあたりのダウンロード回数の上限（0なら無制限）
+    pub download_max_count: u32,
+    /// 発行からダウンロードできる日数（0なら無制限）
+    pub download_valid_days: u32,
+    /// `/download/{pdf_id}`がリダイレクトする署名付きURLの有効秒数
+    pub download_url_ttl_secs: u64,
+    /// 名前付きのプリンター（設定ファイルに`[printers]`がなければ環境変数の1台を`default`とする）
+    pub printers: std::collections::BTreeMap<String, PrinterConfig>,
+    pub routing: RoutingConfig,
+    pub printer_status_interval_secs: u64,
+    pub catalog_path: String,
+    /// レシートのテンプレートを置くディレクトリ
+    pub templates_dir: String,
+    pub database_path: String,
+    pub font_path: Option<String>,
+    pub printer_dot_width: u32,
+    /// 呼び出し番号タグ（`isOrder: true`）を紙に印刷するか（しない場合はキッチンの画面だけで注文を受ける）
+    pub kitchen_tickets: bool,
+    /// ログの出力形式（`text`，`pretty`，`json`）
+    pub log_format: String,
+    /// 出力するログのレベル（`info`や`info,kawauso=debug`のように書く）
+    pub log_level: String,
+    /// 端末の認証を求めるか（falseならだれでもすべてのエンドポイントを呼べる．手元での確認用）
+    pub require_auth: bool,
+    /// APIを呼ぶ端末（レジ，キッチン，管理者）
+    pub clients: std::collections::BTreeMap<String, ClientConfig>,
+    /// 署名の時刻とサーバーの時刻のずれの上限（この間は同じ署名を受け付けない）
+    pub signature_max_age_secs: u64,
+    /// ブラウザーからのリクエストを許可するオリジン（`https://register.example`の形．`*`ならすべて）
+    pub cors_allowed_origins: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind_address: "0.0.0.0:8080".to_string(),
+            base_pdf_path: String::new(),
+            cert_path: "cert/cert.crt".to_string(),
+            key_path: "cert/key.pem".to_string(),
+            storage: StorageConfig::default(),
+            signed_pdf_dir: "signed_pdf".to_string(),
+            receipts_dir: "receipts".to_string(),
+            public_base_url: "http://localhost:8080".to_string(),
+            download_max_count: 10,
+            download_valid_days: 30,
+            download_url_ttl_secs: 300,
+            printers: std::collections::BTreeMap::new(),
+            routing: RoutingConfig::default(),
+            printer_status_interval_secs: 5,
+            catalog_path: "catalog.toml".to_string(),
+            templates_dir: "templates".to_string(),
+            database_path: "kawauso.db".to_string(),
+            font_path: None,
+            printer_dot_width: 576,
+            kitchen_tickets: true,
+            log_format: "text".to_string(),
+            log_level: "info".to_string(),
+            require_auth: true,
+            clients: std::collections::BTreeMap::new(),
+            signature_max_age_secs: 300,
+            cors_allowed_origins: Vec::new(),
+        }
+    }
+}
+
+/// プリンター1台分の設定
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrinterConfig {
+    /// `lpr`，`tcp`，`device`，`file`のいずれか
+    #[serde(default = "default_transport")]
+    pub transport: String,
+    /// `lpr`で送るCUPSのキュー名（省略時はプリンター名）
+    pub cups_queue: Option<String>,
+    /// `tcp`のIPアドレス，`device`のデバイスファイル，`file`の保存先
+    pub address: Option<String>,
+}
+
+fn default_transport() -> String {
+    "lpr".to_string()
+}
+
+/// APIを呼ぶ端末1台分の設定
+#[derive(Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientConfig {
+    /// `register`，`kitchen`，`admin`のいずれか
+    pub role: crate::auth::Role,
+    /// APIキー兼HMAC署名の鍵
+    pub key: String,
+    /// trueならHMAC署名したリクエストだけを受け付ける（APIキーをそのまま送ると拒否する）
+    #[serde(default)]
+    pub require_signature: bool,
+}
+
+impl std::fmt::Debug for ClientConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientConfig")
+            .field("role", &self.role)
+            .field("key", &"(redacted)")
+            .field("require_signature", &self.require_signature)
+            .finish()
+    }
+}
+
+/// 署名済みPDFを置くストレージの設定
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// `s3`，`local`，`memory`のいずれか
+    pub backend: String,
+    /// `s3`のバケット名
+    pub bucket_name: Option<String>,
+    /// `local`の保存先ディレクトリ
+    pub dir: String,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: "s3".to_string(),
+            bucket_name: None,
+            dir: "storage".to_string(),
+        }
+    }
+}
+
+/// ジョブの種類ごとの送り先プリンター
+///
+/// 種類ごとの指定がなければ`default`に送る
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoutingConfig {
+    pub default: Option<String>,
+    /// 呼び出し番号タグ
+    pub tag: Option<String>,
+    /// 注文レシート
+    pub order_receipt: Option<String>,
+    /// PDFのQRコード付きレシート
+    pub pdf_receipt: Option<String>,
+    /// 売上レポート
+    pub report: Option<String>,
+}
+
+/// コマンドライン引数（設定ファイルと環境変数より優先する）
+#[derive(Debug, Clone, clap::Parser)]
+#[command(version, about = "レシートを印刷するサービス")]
+pub struct Args {
+    /// 設定ファイル（既定は`CONFIG_PATH`，なければ`kawauso.toml`）
+    #[arg(long)]
+    pub config: Option<String>,
+    /// 自己診断の結果を表示して終了する
+    #[arg(long)]
+    pub check: bool,
+    /// 待ち受けるアドレス
+    #[arg(long)]
+    pub bind: Option<String>,
+    /// 署名するPDF
+    #[arg(long)]
+    pub base_pdf: Option<String>,
+    /// 署名に使う証明書
+    #[arg(long)]
+    pub cert: Option<String>,
+    /// 署名に使う秘密鍵
+    #[arg(long)]
+    pub key: Option<String>,
+    /// 署名済みPDFの保存先（`s3`，`local`，`memory`）
+    #[arg(long)]
+    pub storage_backend: Option<String>,
+    /// `s3`のバケット名
+    #[arg(long)]
+    pub bucket_name: Option<String>,
+    /// `local`の保存先ディレクトリ
+    #[arg(long)]
+    pub storage_dir: Option<String>,
+    /// 署名済みPDFの控えを保存するディレクトリ
+    #[arg(long)]
+    pub signed_pdf_dir: Option<String>,
+    /// 印刷したESC/POSデータを保存するディレクトリ
+    #[arg(long)]
+    pub receipts_dir: Option<String>,
+    /// お客さんの端末から見たkawausoのURL
+    #[arg(long)]
+    pub public_base_url: Option<String>,
+    /// PDF 1つあたりのダウンロード回数の上限（0なら無制限）
+    #[arg(long)]
+    pub download_max_count: Option<u32>,
+    /// 発行からダウンロードできる日数（0なら無制限）
+    #[arg(long)]
+    pub download_valid_days: Option<u32>,
+    /// 署名付きダウンロードURLの有効秒数
+    #[arg(long)]
+    pub download_url_ttl_secs: Option<u64>,
+    /// プリンターの状態を問い合わせる間隔（秒）
+    #[arg(long)]
+    pub printer_status_interval_secs: Option<u64>,
+    /// 商品カタログ
+    #[arg(long)]
+    pub catalog: Option<String>,
+    /// レシートのテンプレートを置くディレクトリ
+    #[arg(long)]
+    pub templates_dir: Option<String>,
+    /// SQLiteデータベース
+    #[arg(long)]
+    pub database: Option<String>,
+    /// 品名などを描画するフォント
+    #[arg(long)]
+    pub font: Option<String>,
+    /// プリンターの印字幅（ドット）
+    #[arg(long)]
+    pub printer_dot_width: Option<u32>,
+    /// 呼び出し番号タグを紙に印刷するか
+    #[arg(long)]
+    pub kitchen_tickets: Option<bool>,
+    /// ログの出力形式（`text`，`pretty`，`json`）
+    #[arg(long)]
+    pub log_format: Option<String>,
+    /// 出力するログのレベル
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// 端末の認証を求めるか
+    #[arg(long)]
+    pub require_auth: Option<bool>,
+    /// 署名の時刻のずれの上限（秒）
+    #[arg(long)]
+    pub signature_max_age_secs: Option<u64>,
+    /// CORSで許可するオリジン（カンマ区切り）
+    #[arg(long, value_delimiter = ',')]
+    pub cors_allowed_origins: Option<Vec<String>>,
+}
+
+/// 環境変数を名前で引く関数（テストでは`std::env::var`の代わりに渡す）
+type Vars<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+fn process_vars(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+impl Args {
+    /// 読む設定ファイル（`--config`，`CONFIG_PATH`，`kawauso.toml`の順）
+    pub fn config_path(&self) -> String {
+        self.config_path_with(&process_vars)
+    }
+
+    fn config_path_with(&self, vars: Vars<'_>) -> String {
+        self.config
+            .clone()
+            .or_else(|| env_value(vars, "CONFIG_PATH"))
+            .unwrap_or_else(|| "kawauso.toml".to_string())
+    }
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, std::env::VarError> {
-        Ok(Config {
-            base_pdf_path: std::env::var("BASE_PDF_PATH")?,
-            r2_bucket_name: std::env::var("R2_BUCKET_NAME")?,
-            r2_public_url: std::env::var("R2_PUBLIC_URL")?,
-            printer_name: std::env::var("PRINTER_NAME")
-                .unwrap_or_else(|_| "kyogaku-printer".to_string()),
-        })
+    /// 設定ファイル，環境変数，コマンドライン引数を順に重ねて読む
+    pub fn load(args: &Args) -> anyhow::Result<Self> {
+        Self::load_with(args, &process_vars)
+    }
+
+    fn load_with(args: &Args, vars: Vars<'_>) -> anyhow::Result<Self> {
+        let path = args.config_path_with(vars);
+
+        let mut config = if std::path::Path::new(&path).exists() {
+            let content = std::fs::read_to_string(&path)
+                .context(format!("failed to read config file: {}", path))?;
+            toml::from_str(&content).context(format!("failed to parse config file: {}", path))?
+        } else if args.config.is_some() {
+            anyhow::bail!("config file not found: {}", path);
+        } else {
+            Config::default()
+        };
+
+        config
+            .apply_env(vars)
+            .context("invalid environment variable")?;
+        config.apply_args(args);
+
+        // `[printers]`がなければ環境変数の1台を使う
+        if config.printers.is_empty() {
+            config
+                .printers
+                .insert("default".to_string(), env_printer(vars));
+        }
+
+        Ok(config)
+    }
+
+    fn apply_env(&mut self, vars: Vars<'_>) -> anyhow::Result<()> {
+        env(vars, "BIND_ADDRESS", &mut self.bind_address)?;
+        env(vars, "BASE_PDF_PATH", &mut self.base_pdf_path)?;
+        env(vars, "CERT_PATH", &mut self.cert_path)?;
+        env(vars, "KEY_PATH", &mut self.key_path)?;
+        env(vars, "STORAGE_BACKEND", &mut self.storage.backend)?;
+        env_opt(vars, "R2_BUCKET_NAME", &mut self.storage.bucket_name);
+        env(vars, "STORAGE_DIR", &mut self.storage.dir)?;
+        env(vars, "SIGNED_PDF_DIR", &mut self.signed_pdf_dir)?;
+        env(vars, "RECEIPTS_DIR", &mut self.receipts_dir)?;
+        env(vars, "PUBLIC_BASE_URL", &mut self.public_base_url)?;
+        env(vars, "DOWNLOAD_MAX_COUNT", &mut self.download_max_count)?;
+        env(vars, "DOWNLOAD_VALID_DAYS", &mut self.download_valid_days)?;
+        env(
+            vars,
+            "DOWNLOAD_URL_TTL_SECS",
+            &mut self.download_url_ttl_secs,
+        )?;
+        env(
+            vars,
+            "PRINTER_STATUS_INTERVAL_SECS",
+            &mut self.printer_status_interval_secs,
+        )?;
+        env(vars, "CATALOG_PATH", &mut self.catalog_path)?;
+        env(vars, "TEMPLATES_DIR", &mut self.templates_dir)?;
+        env(vars, "DATABASE_PATH", &mut self.database_path)?;
+        env_opt(vars, "FONT_PATH", &mut self.font_path);
+        env(vars, "PRINTER_DOT_WIDTH", &mut self.printer_dot_width)?;
+        env(vars, "KITCHEN_TICKETS", &mut self.kitchen_tickets)?;
+        env(vars, "LOG_FORMAT", &mut self.log_format)?;
+        env(vars, "LOG_LEVEL", &mut self.log_level)?;
+        env(vars, "REQUIRE_AUTH", &mut self.require_auth)?;
+        env(
+            vars,
+            "SIGNATURE_MAX_AGE_SECS",
+            &mut self.signature_max_age_secs,
+        )?;
+        if let Some(origins) = env_value(vars, "CORS_ALLOWED_ORIGINS") {
+            self.cors_allowed_origins = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        Ok(())
+    }
+
+    fn apply_args(&mut self, args: &Args) {
+        arg(&args.bind, &mut self.bind_address);
+        arg(&args.base_pdf, &mut self.base_pdf_path);
+        arg(&args.cert, &mut self.cert_path);
+        arg(&args.key, &mut self.key_path);
+        arg(&args.storage_backend, &mut self.storage.backend);
+        if args.bucket_name.is_some() {
+            self.storage.bucket_name = args.bucket_name.clone();
+        }
+        arg(&args.storage_dir, &mut self.storage.dir);
+        arg(&args.signed_pdf_dir, &mut self.signed_pdf_dir);
+        arg(&args.receipts_dir, &mut self.receipts_dir);
+        arg(&args.public_base_url, &mut self.public_base_url);
+        arg(&args.download_max_count, &mut self.download_max_count);
+        arg(&args.download_valid_days, &mut self.download_valid_days);
+        arg(&args.download_url_ttl_secs, &mut self.download_url_ttl_secs);
+        arg(
+            &args.printer_status_interval_secs,
+            &mut self.printer_status_interval_secs,
+        );
+        arg(&args.catalog, &mut self.catalog_path);
+        arg(&args.templates_dir, &mut self.templates_dir);
+        arg(&args.database, &mut self.database_path);
+        if args.font.is_some() {
+            self.font_path = args.font.clone();
+        }
+        arg(&args.printer_dot_width, &mut self.printer_dot_width);
+        arg(&args.kitchen_tickets, &mut self.kitchen_tickets);
+        arg(&args.log_format, &mut self.log_format);
+        arg(&args.log_level, &mut self.log_level);
+        arg(&args.require_auth, &mut self.require_auth);
+        arg(
+            &args.signature_max_age_secs,
+            &mut self.signature_max_age_secs,
+        );
+        arg(&args.cors_allowed_origins, &mut self.cors_allowed_origins);
+    }
+}
+
+/// `PRINTER_TRANSPORT`，`PRINTER_NAME`，`PRINTER_ADDRESS`で設定する1台
+fn env_printer(vars: Vars<'_>) -> PrinterConfig {
+    PrinterConfig {
+        transport: env_value(vars, "PRINTER_TRANSPORT").unwrap_or_else(default_transport),
+        cups_queue: Some(
+            env_value(vars, "PRINTER_NAME").unwrap_or_else(|| "kyogaku-printer".to_string()),
+        ),
+        address: env_value(vars, "PRINTER_ADDRESS"),
+    }
+}
+
+/// 空でない環境変数の値
+fn env_value(vars: Vars<'_>, name: &str) -> Option<String> {
+    vars(name).filter(|value| !value.is_empty())
+}
+
+/// 環境変数があれば`target`を上書きする
+fn env<T>(vars: Vars<'_>, name: &str, target: &mut T) -> anyhow::Result<()>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    if let Some(value) = env_value(vars, name) {
+        *target = value
+            .parse()
+            .map_err(|e| anyhow::anyhow!("invalid {}={:?}: {}", name, value, e))?;
+    }
+    Ok(())
+}
+
+fn env_opt(vars: Vars<'_>, name: &str, target: &mut Option<String>) {
+    if let Some(value) = env_value(vars, name) {
+        *target = Some(value);
+    }
+}
+
+/// コマンドライン引数があれば`target`を上書きする
+fn arg<T: Clone>(value: &Option<T>, target: &mut T) {
+    if let Some(value) = value {
+        *target = value.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    /// 一時ディレクトリに書いた設定ファイル（終わったら消す）
+    struct ConfigFile(std::path::PathBuf);
+
+    impl ConfigFile {
+        fn new(text: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("kawauso-config-{}.toml", uuid::Uuid::new_v4()));
+            std::fs::write(&path, text).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> String {
+            self.0.to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn args(args: &[&str]) -> super::Args {
+        <super::Args as clap::Parser>::parse_from(
+            std::iter::once("kawauso").chain(args.iter().copied()),
+        )
+    }
+
+    fn load(args: &super::Args, vars: &[(&str, &str)]) -> anyhow::Result<super::Config> {
+        let vars: std::collections::HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        super::Config::load_with(args, &|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let file = ConfigFile::new(
+            "bind_address = \"0.0.0.0:9000\"\nlog_level = \"debug\"\ndownload_max_count = 3\n",
+        );
+        let path = file.path();
+
+        // 設定ファイルは既定値を上書きし，書いていない項目は既定値のまま
+        let config = load(&args(&["--config", &path]), &[]).unwrap();
+        assert_eq!(config.bind_address, "0.0.0.0:9000");
+        assert_eq!(config.download_max_count, 3);
+        assert_eq!(
+            config.printer_dot_width,
+            super::Config::default().printer_dot_width
+        );
+
+        // 環境変数は設定ファイルを上書きする（空の値は無視する）
+        let vars = [
+            ("BIND_ADDRESS", "127.0.0.1:9001"),
+            ("LOG_LEVEL", ""),
+            (
+                "CORS_ALLOWED_ORIGINS",
+                "https://a.example, https://b.example",
+            ),
+        ];
+        let config = load(&args(&["--config", &path]), &vars).unwrap();
+        assert_eq!(config.bind_address, "127.0.0.1:9001");
+        assert_eq!(config.log_level, "debug");
+        assert_eq!(
+            config.cors_allowed_origins,
+            ["https://a.example", "https://b.example"]
+        );
+
+        // コマンドライン引数は環境変数を上書きする
+        let config = load(
+            &args(&["--config", &path, "--bind", "127.0.0.1:9002"]),
+            &vars,
+        )
+        .unwrap();
+        assert_eq!(config.bind_address, "127.0.0.1:9002");
+    }
+
+    #[test]
+    fn finds_the_config_file_through_config_path() {
+        let file = ConfigFile::new("download_max_count = 7\n");
+        let config = load(&args(&[]), &[("CONFIG_PATH", &file.path())]).unwrap();
+        assert_eq!(config.download_max_count, 7);
+
+        // `--config`で指定したファイルがなければエラー
+        assert!(load(&args(&["--config", "/nonexistent/kawauso.toml"]), &[]).is_err());
+    }
+
+    #[test]
+    fn uses_the_env_printer_without_printers_table() {
+        let file = ConfigFile::new("");
+        let config = load(
+            &args(&["--config", &file.path()]),
+            &[
+                ("PRINTER_TRANSPORT", "tcp"),
+                ("PRINTER_ADDRESS", "10.0.0.2:9100"),
+            ],
+        )
+        .unwrap();
+        let printer = &config.printers["default"];
+        assert_eq!(printer.transport, "tcp");
+        assert_eq!(printer.address.as_deref(), Some("10.0.0.2:9100"));
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_invalid_values() {
+        for text in [
+            "bind_adress = \"0.0.0.0:8080\"\n",
+            "[storage]\nbakend = \"local\"\n",
+            "[routing]\nreceipt = \"default\"\n",
+        ] {
+            let file = ConfigFile::new(text);
+            assert!(
+                load(&args(&["--config", &file.path()]), &[]).is_err(),
+                "{}",
+                text
+            );
+        }
+
+        let file = ConfigFile::new("");
+        assert!(
+            load(
+                &args(&["--config", &file.path()]),
+                &[("PRINTER_DOT_WIDTH", "wide")]
+            )
+            .is_err()
+        );
     }
 }
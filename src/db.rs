@@ -0,0 +1,202 @@
+use anyhow::Context as _;
+
+/// マイグレーション（`PRAGMA user_version`に適用済みの数を記録する）
+///
+/// 既存のマイグレーションは書き換えず，末尾に追加すること
+const MIGRATIONS: &[&str] = &[
+    // 1: 印刷ジョブキュー
+    "CREATE TABLE print_jobs (
+        id TEXT PRIMARY KEY,
+        kind TEXT NOT NULL,
+        reference TEXT NOT NULL,
+        data BLOB NOT NULL,
+        status TEXT NOT NULL,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        last_error TEXT,
+        next_attempt_at INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE INDEX print_jobs_status ON print_jobs (status, next_attempt_at);",
+    // 2: 印刷ジョブの送り先プリンター
+    "ALTER TABLE print_jobs ADD COLUMN printer TEXT NOT NULL DEFAULT 'default';
+    CREATE INDEX print_jobs_printer ON print_jobs (printer, status, next_attempt_at);",
+    // 3: 発行した署名付きPDF
+    "CREATE TABLE issued_pdfs (
+        pdf_id TEXT PRIMARY KEY,
+        payment_id TEXT NOT NULL,
+        paid_at INTEGER NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX issued_pdfs_payment ON issued_pdfs (payment_id);",
+    // 4: 支払いごとのPDF発行状況（/print/pdfの再送を見分ける）
+    "CREATE TABLE payments (
+        payment_id TEXT PRIMARY KEY,
+        count INTEGER NOT NULL,
+        paid_at INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );",
+    // 5: 売上台帳（/print/tagの注文と/print/pdfの発行）
+    "CREATE TABLE sales (
+        id TEXT PRIMARY KEY,
+        kind TEXT NOT NULL,
+        tag TEXT,
+        payment_id TEXT,
+        total INTEGER,
+        job_id TEXT,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX sales_created ON sales (created_at);
+    CREATE UNIQUE INDEX sales_payment ON sales (payment_id) WHERE payment_id IS NOT NULL;
+    CREATE TABLE sale_items (
+        sale_id TEXT NOT NULL REFERENCES sales (id),
+        line_no INTEGER NOT NULL,
+        sku TEXT NOT NULL,
+        name TEXT NOT NULL,
+        qty INTEGER NOT NULL,
+        unit_price INTEGER NOT NULL,
+        subtotal INTEGER NOT NULL,
+        PRIMARY KEY (sale_id, line_no)
+    );
+    CREATE TABLE sale_discounts (
+        sale_id TEXT NOT NULL REFERENCES sales (id),
+        name TEXT NOT NULL,
+        sets INTEGER NOT NULL,
+        amount INTEGER NOT NULL
+    );",
+    // 6: Zレポートで締めた営業日
+    "CREATE TABLE day_closures (
+        business_date TEXT PRIMARY KEY,
+        closed_at INTEGER NOT NULL,
+        report TEXT NOT NULL
+    );",
+    // 7: 呼び出し番号（受け渡し前の番号は重複させない）
+    "CREATE TABLE call_numbers (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        tag TEXT NOT NULL,
+        status TEXT NOT NULL,
+        sale_id TEXT REFERENCES sales (id),
+        created_at INTEGER NOT NULL,
+        ready_at INTEGER,
+        called_at INTEGER,
+        finished_at INTEGER,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE UNIQUE INDEX call_numbers_active ON call_numbers (tag)
+        WHERE status NOT IN ('picked_up', 'cancelled');
+    CREATE INDEX call_numbers_status ON call_numbers (status);
+    CREATE TABLE call_number_sequences (
+        business_date TEXT PRIMARY KEY,
+        last INTEGER NOT NULL
+    );",
+    // 8: 署名付きPDFのダウンロード回数（/download/{pdf_id}の回数制限）
+    "ALTER TABLE issued_pdfs ADD COLUMN download_count INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE issued_pdfs ADD COLUMN last_downloaded_at INTEGER;",
+    // 9: 売上台帳の結果（印刷ジョブを登録できなかった注文や発行に失敗した支払いは集計しない）
+    "ALTER TABLE sales ADD COLUMN status TEXT NOT NULL DEFAULT 'completed';",
+];
+
+/// SQLiteデータベース
+///
+/// rusqliteはブロッキングなので，クエリは`call`経由でブロッキングスレッドで実行する
+pub struct Database {
+    conn: std::sync::Arc<std::sync::Mutex<rusqlite::Connection>>,
+}
+
+impl Database {
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let mut conn = rusqlite::Connection::open(path)
+            .context(format!("failed to open database: {}", path))?;
+
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .context("failed to enable WAL")?;
+        conn.pragma_update(None, "foreign_keys", true)
+            .context("failed to enable foreign keys")?;
+
+        Self::migrate(&mut conn)?;
+
+        Ok(Self {
+            conn: std::sync::Arc::new(std::sync::Mutex::new(conn)),
+        })
+    }
+
+    fn migrate(conn: &mut rusqlite::Connection) -> anyhow::Result<()> {
+        let applied: usize = conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .context("failed to read schema version")?;
+
+        for (i, sql) in MIGRATIONS.iter().enumerate().skip(applied) {
+            let version = i + 1;
+            tracing::info!("Applying database migration {}", version);
+
+            let tx = conn.transaction()?;
+            tx.execute_batch(sql)
+                .context(format!("failed to apply migration {}", version))?;
+            tx.pragma_update(None, "user_version", version)?;
+            tx.commit()?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn call<F, R>(&self, f: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&mut rusqlite::Connection) -> rusqlite::Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|_| anyhow::anyhow!("database mutex poisoned"))?;
+            f(&mut conn).context("database query failed")
+        })
+        .await
+        .context("database task panicked")?
+    }
+}
+
+/// 現在時刻（UNIX秒）
+pub fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    fn user_version(conn: &rusqlite::Connection) -> usize {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn applies_every_migration_once() {
+        let db = super::Database::open(":memory:").unwrap();
+        let mut conn = db.conn.lock().unwrap();
+        assert_eq!(user_version(&conn), super::MIGRATIONS.len());
+
+        conn.execute(
+            "INSERT INTO day_closures (business_date, closed_at, report) VALUES ('2026-10-17', 0, '')",
+            [],
+        )
+        .unwrap();
+        // 適用済みのマイグレーションは実行し直さない
+        super::Database::migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), super::MIGRATIONS.len());
+        let closures: u32 = conn
+            .query_row("SELECT COUNT(*) FROM day_closures", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(closures, 1);
+    }
+
+    #[test]
+    fn migrates_from_an_older_version() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(super::MIGRATIONS[0]).unwrap();
+        conn.pragma_update(None, "user_version", 1).unwrap();
+
+        super::Database::migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), super::MIGRATIONS.len());
+    }
+}
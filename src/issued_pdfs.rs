@@ -0,0 +1,507 @@
+use rusqlite::OptionalExtension as _;
+
+/// 支払いごとのPDF発行状況
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PaymentStatus {
+    /// 発行中（同じ支払いIDの要求は受け付けない）
+    Issuing,
+    Issued,
+    /// 途中で失敗した（再送されたら残りを発行する）
+    Failed,
+}
+
+impl PaymentStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PaymentStatus::Issuing => "issuing",
+            PaymentStatus::Issued => "issued",
+            PaymentStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(s: &str) -> rusqlite::Result<Self> {
+        match s {
+            "issuing" => Ok(PaymentStatus::Issuing),
+            "issued" => Ok(PaymentStatus::Issued),
+            "failed" => Ok(PaymentStatus::Failed),
+            _ => Err(rusqlite::Error::InvalidColumnType(
+                0,
+                format!("unknown payment status: {}", s),
+                rusqlite::types::Type::Text,
+            )),
+        }
+    }
+}
+
+/// `/print/pdf`の要求を受け付けてよいかの判定結果
+pub enum PaymentClaim {
+    /// 発行してよい（途中まで発行済みなら，そのPDFが入っている）
+    Issue(Vec<IssuedPdf>),
+    /// すべて発行済み
+    Issued(Vec<IssuedPdf>),
+    /// 別の要求が発行中
+    InProgress,
+    /// 同じ支払いIDが別の内容で使われている
+    Mismatch { count: u32, paid_at: u64 },
+}
+
+/// `/download/{pdf_id}`のダウンロードを許すかの判定結果
+pub enum DownloadClaim {
+    /// ダウンロードしてよい（回数は数えた）
+    Allowed,
+    /// 発行していないPDF ID
+    NotIssued,
+    /// 発行から`download_valid_days`日を過ぎた
+    Expired,
+    /// `download_max_count`回ダウンロード済み
+    LimitReached,
+}
+
+/// ダウンロードの回数と期限の制限（0なら無制限）
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadPolicy {
+    pub max_count: u32,
+    pub valid_secs: i64,
+}
+
+/// 発行した署名付きPDFの記録
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct IssuedPdf {
+    #[serde(rename = "pdfId")]
+    pub pdf_id: String,
+    #[serde(rename = "paymentId")]
+    pub payment_id: String,
+    #[serde(rename = "paidAt")]
+    pub paid_at: u64,
+    #[serde(rename = "createdAt")]
+    pub created_at: i64,
+}
+
+impl IssuedPdf {
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            pdf_id: row.get(0)?,
+            payment_id: row.get(1)?,
+            paid_at: row.get(2)?,
+            created_at: row.get(3)?,
+        })
+    }
+}
+
+/// どのPDF IDがどの取引で発行されたかを記録する台帳
+///
+/// `/verify`で持ち込まれたPDFの出どころを調べるのに使う
+pub struct IssuedPdfs {
+    db: std::sync::Arc<crate::db::Database>,
+}
+
+impl IssuedPdfs {
+    pub fn new(db: std::sync::Arc<crate::db::Database>) -> Self {
+        Self { db }
+    }
+
+    pub async fn record(
+        &self,
+        pdf_id: &uuid::Uuid,
+        payment_id: &uuid::Uuid,
+        paid_at: u64,
+    ) -> anyhow::Result<()> {
+        let pdf_id = pdf_id.to_string();
+        let payment_id = payment_id.to_string();
+        self.db
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO issued_pdfs (pdf_id, payment_id, paid_at, created_at)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    rusqlite::params![pdf_id, payment_id, paid_at, crate::db::now()],
+                )
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// 支払いIDに対するPDF発行を始める
+    ///
+    /// 初めての支払いIDなら発行中として登録する．再送なら発行済みのPDFを返す
+    pub async fn claim_payment(
+        &self,
+        payment_id: &uuid::Uuid,
+        count: u32,
+        paid_at: u64,
+    ) -> anyhow::Result<PaymentClaim> {
+        let payment_id = payment_id.to_string();
+        self.db
+            .call(move |conn| {
+                let now = crate::db::now();
+                let tx = conn.transaction()?;
+                let existing = tx
+                    .query_row(
+                        "SELECT count, paid_at, status FROM payments WHERE payment_id = ?1",
+                        [&payment_id],
+                        |row| {
+                            let status: String = row.get(2)?;
+                            Ok((row.get::<_, u32>(0)?, row.get::<_, u64>(1)?, status))
+                        },
+                    )
+                    .optional()?;
+
+                let claim = match existing {
+                    None => {
+                        tx.execute(
+                            "INSERT INTO payments (payment_id, count, paid_at, status, created_at, updated_at)
+                             VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+                            rusqlite::params![
+                                payment_id,
+                                count,
+                                paid_at,
+                                PaymentStatus::Issuing.as_str(),
+                                now
+                            ],
+                        )?;
+                        PaymentClaim::Issue(Vec::new())
+                    }
+                    Some((existing_count, existing_paid_at, _))
+                        if existing_count != count || existing_paid_at != paid_at =>
+                    {
+                        PaymentClaim::Mismatch {
+                            count: existing_count,
+                            paid_at: existing_paid_at,
+                        }
+                    }
+                    Some((_, _, status)) => match PaymentStatus::parse(&status)? {
+                        PaymentStatus::Issuing => PaymentClaim::InProgress,
+                        PaymentStatus::Issued => {
+                            PaymentClaim::Issued(list_for_payment(&tx, &payment_id)?)
+                        }
+                        PaymentStatus::Failed => {
+                            set_payment_status(&tx, &payment_id, PaymentStatus::Issuing)?;
+                            PaymentClaim::Issue(list_for_payment(&tx, &payment_id)?)
+                        }
+                    },
+                };
+
+                tx.commit()?;
+                Ok(claim)
+            })
+            .await
+    }
+
+    /// すべてのPDFを発行し終えた
+    pub async fn finish_payment(&self, payment_id: &uuid::Uuid) -> anyhow::Result<()> {
+        self.set_payment_status(payment_id, PaymentStatus::Issued)
+            .await
+    }
+
+    /// 発行に失敗した（再送されたら残りを発行する）
+    pub async fn fail_payment(&self, payment_id: &uuid::Uuid) -> anyhow::Result<()> {
+        self.set_payment_status(payment_id, PaymentStatus::Failed)
+            .await
+    }
+
+    async fn set_payment_status(
+        &self,
+        payment_id: &uuid::Uuid,
+        status: PaymentStatus,
+    ) -> anyhow::Result<()> {
+        let payment_id = payment_id.to_string();
+        self.db
+            .call(move |conn| set_payment_status(conn, &payment_id, status))
+            .await
+    }
+
+    /// 起動時に，前回の実行で発行中のまま止まった支払いを失敗扱いにする
+    pub async fn recover_payments(&self) -> anyhow::Result<usize> {
+        self.db
+            .call(|conn| {
+                conn.execute(
+                    "UPDATE payments SET status = ?1, updated_at = ?3 WHERE status = ?2",
+                    rusqlite::params![
+                        PaymentStatus::Failed.as_str(),
+                        PaymentStatus::Issuing.as_str(),
+                        crate::db::now()
+                    ],
+                )
+            })
+            .await
+    }
+
+    /// 制限内ならダウンロード回数を1増やす
+    pub async fn claim_download(
+        &self,
+        pdf_id: &uuid::Uuid,
+        policy: DownloadPolicy,
+    ) -> anyhow::Result<DownloadClaim> {
+        let pdf_id = pdf_id.to_string();
+        self.db
+            .call(move |conn| {
+                let now = crate::db::now();
+                let tx = conn.transaction()?;
+                let existing = tx
+                    .query_row(
+                        "SELECT created_at, download_count FROM issued_pdfs WHERE pdf_id = ?1",
+                        [&pdf_id],
+                        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, u32>(1)?)),
+                    )
+                    .optional()?;
+
+                let claim = match existing {
+                    None => DownloadClaim::NotIssued,
+                    Some((created_at, _))
+                        if policy.valid_secs > 0 && now - created_at > policy.valid_secs =>
+                    {
+                        DownloadClaim::Expired
+                    }
+                    Some((_, count)) if policy.max_count > 0 && count >= policy.max_count => {
+                        DownloadClaim::LimitReached
+                    }
+                    Some(_) => {
+                        tx.execute(
+                            "UPDATE issued_pdfs
+                             SET download_count = download_count + 1, last_downloaded_at = ?2
+                             WHERE pdf_id = ?1",
+                            rusqlite::params![pdf_id, now],
+                        )?;
+                        DownloadClaim::Allowed
+                    }
+                };
+
+                tx.commit()?;
+                Ok(claim)
+            })
+            .await
+    }
+
+    /// `claim_download`で増やした回数を1戻す（PDFを渡せなかったとき）
+    pub async fn release_download(&self, pdf_id: &uuid::Uuid) -> anyhow::Result<()> {
+        let pdf_id = pdf_id.to_string();
+        self.db
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE issued_pdfs SET download_count = download_count - 1
+                     WHERE pdf_id = ?1 AND download_count > 0",
+                    [pdf_id],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn get(&self, pdf_id: &uuid::Uuid) -> anyhow::Result<Option<IssuedPdf>> {
+        let pdf_id = pdf_id.to_string();
+        self.db
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT pdf_id, payment_id, paid_at, created_at FROM issued_pdfs WHERE pdf_id = ?1",
+                    [pdf_id],
+                    IssuedPdf::from_row,
+                )
+                .optional()
+            })
+            .await
+    }
+}
+
+fn set_payment_status(
+    conn: &rusqlite::Connection,
+    payment_id: &str,
+    status: PaymentStatus,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE payments SET status = ?2, updated_at = ?3 WHERE payment_id = ?1",
+        rusqlite::params![payment_id, status.as_str(), crate::db::now()],
+    )?;
+    Ok(())
+}
+
+fn list_for_payment(
+    conn: &rusqlite::Connection,
+    payment_id: &str,
+) -> rusqlite::Result<Vec<IssuedPdf>> {
+    let mut stmt = conn.prepare(
+        "SELECT pdf_id, payment_id, paid_at, created_at FROM issued_pdfs
+         WHERE payment_id = ?1 ORDER BY rowid",
+    )?;
+    stmt.query_map([payment_id], IssuedPdf::from_row)?.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    fn issued_pdfs() -> (std::sync::Arc<crate::db::Database>, super::IssuedPdfs) {
+        let db = std::sync::Arc::new(crate::db::Database::open(":memory:").unwrap());
+        (db.clone(), super::IssuedPdfs::new(db))
+    }
+
+    #[tokio::test]
+    async fn claim_payment_follows_the_issuing_state() {
+        let (_, pdfs) = issued_pdfs();
+        let payment_id = uuid::Uuid::new_v4();
+        let pdf_id = uuid::Uuid::new_v4();
+
+        assert!(matches!(
+            pdfs.claim_payment(&payment_id, 2, 100).await.unwrap(),
+            super::PaymentClaim::Issue(issued) if issued.is_empty()
+        ));
+        assert!(matches!(
+            pdfs.claim_payment(&payment_id, 2, 100).await.unwrap(),
+            super::PaymentClaim::InProgress
+        ));
+        assert!(matches!(
+            pdfs.claim_payment(&payment_id, 3, 100).await.unwrap(),
+            super::PaymentClaim::Mismatch {
+                count: 2,
+                paid_at: 100
+            }
+        ));
+
+        // 1枚目を発行したところで失敗し，再送で残りを発行する
+        pdfs.record(&pdf_id, &payment_id, 100).await.unwrap();
+        pdfs.fail_payment(&payment_id).await.unwrap();
+        let super::PaymentClaim::Issue(issued) =
+            pdfs.claim_payment(&payment_id, 2, 100).await.unwrap()
+        else {
+            panic!("failed payment was not reissued");
+        };
+        assert_eq!(issued.len(), 1);
+        assert_eq!(issued[0].pdf_id, pdf_id.to_string());
+
+        pdfs.record(&uuid::Uuid::new_v4(), &payment_id, 100)
+            .await
+            .unwrap();
+        pdfs.finish_payment(&payment_id).await.unwrap();
+        assert!(matches!(
+            pdfs.claim_payment(&payment_id, 2, 100).await.unwrap(),
+            super::PaymentClaim::Issued(issued) if issued.len() == 2
+        ));
+    }
+
+    #[tokio::test]
+    async fn recover_payments_fails_interrupted_issuing() {
+        let (_, pdfs) = issued_pdfs();
+        let payment_id = uuid::Uuid::new_v4();
+        pdfs.claim_payment(&payment_id, 1, 100).await.unwrap();
+
+        assert_eq!(pdfs.recover_payments().await.unwrap(), 1);
+        assert!(matches!(
+            pdfs.claim_payment(&payment_id, 1, 100).await.unwrap(),
+            super::PaymentClaim::Issue(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn claim_download_counts_up_to_the_limit() {
+        let (_, pdfs) = issued_pdfs();
+        let pdf_id = uuid::Uuid::new_v4();
+        pdfs.record(&pdf_id, &uuid::Uuid::new_v4(), 100)
+            .await
+            .unwrap();
+        let policy = super::DownloadPolicy {
+            max_count: 2,
+            valid_secs: 0,
+        };
+
+        for _ in 0..2 {
+            assert!(matches!(
+                pdfs.claim_download(&pdf_id, policy).await.unwrap(),
+                super::DownloadClaim::Allowed
+            ));
+        }
+        assert!(matches!(
+            pdfs.claim_download(&pdf_id, policy).await.unwrap(),
+            super::DownloadClaim::LimitReached
+        ));
+        assert!(matches!(
+            pdfs.claim_download(&uuid::Uuid::new_v4(), policy)
+                .await
+                .unwrap(),
+            super::DownloadClaim::NotIssued
+        ));
+
+        // 0なら無制限
+        let unlimited = super::DownloadPolicy {
+            max_count: 0,
+            valid_secs: 0,
+        };
+        assert!(matches!(
+            pdfs.claim_download(&pdf_id, unlimited).await.unwrap(),
+            super::DownloadClaim::Allowed
+        ));
+    }
+
+    #[tokio::test]
+    async fn release_download_gives_the_claim_back() {
+        let (db, pdfs) = issued_pdfs();
+        let pdf_id = uuid::Uuid::new_v4();
+        pdfs.record(&pdf_id, &uuid::Uuid::new_v4(), 100)
+            .await
+            .unwrap();
+        let policy = super::DownloadPolicy {
+            max_count: 1,
+            valid_secs: 0,
+        };
+
+        assert!(matches!(
+            pdfs.claim_download(&pdf_id, policy).await.unwrap(),
+            super::DownloadClaim::Allowed
+        ));
+        pdfs.release_download(&pdf_id).await.unwrap();
+        assert!(matches!(
+            pdfs.claim_download(&pdf_id, policy).await.unwrap(),
+            super::DownloadClaim::Allowed
+        ));
+        assert!(matches!(
+            pdfs.claim_download(&pdf_id, policy).await.unwrap(),
+            super::DownloadClaim::LimitReached
+        ));
+
+        // 回数は0未満にならない
+        let other = uuid::Uuid::new_v4();
+        pdfs.record(&other, &uuid::Uuid::new_v4(), 100)
+            .await
+            .unwrap();
+        pdfs.release_download(&other).await.unwrap();
+        let key = other.to_string();
+        let count: u32 = db
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT download_count FROM issued_pdfs WHERE pdf_id = ?1",
+                    [key],
+                    |row| row.get(0),
+                )
+            })
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn claim_download_expires() {
+        let (db, pdfs) = issued_pdfs();
+        let pdf_id = uuid::Uuid::new_v4();
+        pdfs.record(&pdf_id, &uuid::Uuid::new_v4(), 100)
+            .await
+            .unwrap();
+        let policy = super::DownloadPolicy {
+            max_count: 0,
+            valid_secs: 60,
+        };
+        assert!(matches!(
+            pdfs.claim_download(&pdf_id, policy).await.unwrap(),
+            super::DownloadClaim::Allowed
+        ));
+
+        let key = pdf_id.to_string();
+        db.call(move |conn| {
+            conn.execute(
+                "UPDATE issued_pdfs SET created_at = created_at - 61 WHERE pdf_id = ?1",
+                [key],
+            )
+        })
+        .await
+        .unwrap();
+        assert!(matches!(
+            pdfs.claim_download(&pdf_id, policy).await.unwrap(),
+            super::DownloadClaim::Expired
+        ));
+    }
+}
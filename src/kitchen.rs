@@ -0,0 +1,82 @@
+/// 品目ごとの数量
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct KitchenItem {
+    pub sku: String,
+    pub name: String,
+    pub qty: u32,
+}
+
+/// 調理待ちの注文1件
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct KitchenOrder {
+    pub tag: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: i64,
+    /// 注文からの経過秒数
+    #[serde(rename = "elapsedSecs")]
+    pub elapsed_secs: i64,
+    /// カタログの並び順に整列した品目
+    pub items: Vec<KitchenItem>,
+}
+
+/// キッチンの画面に出す調理待ちの注文の一覧
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct KitchenQueue {
+    #[serde(rename = "generatedAt")]
+    pub generated_at: i64,
+    /// カタログの全品目について，調理待ちの注文に含まれる数量の合計（0も含む）
+    pub totals: Vec<KitchenItem>,
+    /// 古い順
+    pub orders: Vec<KitchenOrder>,
+}
+
+impl KitchenQueue {
+    pub fn new(
+        catalog: &crate::catalog::Catalog,
+        orders: Vec<(crate::call_numbers::CallNumber, Vec<KitchenItem>)>,
+        now: i64,
+    ) -> Self {
+        let mut totals: Vec<KitchenItem> = catalog
+            .items()
+            .iter()
+            .map(|item| KitchenItem {
+                sku: item.sku.clone(),
+                name: item.name.clone(),
+                qty: 0,
+            })
+            .collect();
+
+        let orders = orders
+            .into_iter()
+            .map(|(call, mut items)| {
+                // カタログから消えた品目は後ろに回す
+                items.sort_by_key(|line| {
+                    catalog
+                        .items()
+                        .iter()
+                        .position(|item| item.sku == line.sku)
+                        .unwrap_or(usize::MAX)
+                });
+                for line in &items {
+                    match totals.iter_mut().find(|total| total.sku == line.sku) {
+                        Some(total) => total.qty += line.qty,
+                        None => totals.push(line.clone()),
+                    }
+                }
+
+                KitchenOrder {
+                    tag: call.tag,
+                    created_at: call.created_at,
+                    elapsed_secs: (now - call.created_at).max(0),
+                    items,
+                }
+            })
+            .collect();
+
+        Self {
+            generated_at: now,
+            totals,
+            orders,
+        }
+    }
+}
@@ -0,0 +1,606 @@
+use rusqlite::OptionalExtension as _;
+
+/// 売上台帳の1件（`/print/tag`の注文1回，または`/print/pdf`の支払い1回）
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Sale {
+    pub id: String,
+    pub kind: crate::print_queue::JobKind,
+    pub tag: Option<String>,
+    #[serde(rename = "paymentId")]
+    pub payment_id: Option<String>,
+    /// カタログに`pdf_sku`がない場合のPDFの支払いでは`None`
+    pub total: Option<u32>,
+    pub items: Vec<crate::pricing::PricedLine>,
+    pub discounts: Vec<crate::pricing::AppliedDiscount>,
+    #[serde(rename = "pdfIds")]
+    pub pdf_ids: Vec<String>,
+    pub status: SaleStatus,
+    /// このレコードに対応する印刷ジョブとその状態
+    pub jobs: Vec<SaleJob>,
+    #[serde(rename = "createdAt")]
+    pub created_at: i64,
+}
+
+/// 台帳の1件の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SaleStatus {
+    /// 印刷ジョブを登録できた注文，またはPDFをすべて発行できた支払い
+    Completed,
+    /// 途中で失敗した（レジは再送するので集計には含めない）
+    Failed,
+}
+
+impl SaleStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SaleStatus::Completed => "completed",
+            SaleStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "completed" => Some(SaleStatus::Completed),
+            "failed" => Some(SaleStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SaleJob {
+    pub id: String,
+    pub status: Option<crate::print_queue::JobStatus>,
+}
+
+/// 期間内の売上の集計（注文は`isOrder: true`の呼び出し番号タグ1枚を1件と数え，失敗した件は数えない）
+#[derive(Debug, Clone, Default)]
+pub struct SalesSummary {
+    pub orders: u32,
+    pub items: Vec<crate::pricing::PricedLine>,
+    pub discounts: Vec<crate::pricing::AppliedDiscount>,
+    /// 注文とPDFの支払いの合計
+    pub total: u32,
+    pub pdf_payments: u32,
+    /// PDFの支払いの合計
+    pub pdf_revenue: u32,
+    pub pdfs_issued: u32,
+}
+
+/// 何がいつ売れて，何を印刷したかを記録する台帳
+///
+/// 日次の締めで現金と突き合わせるのに使う
+pub struct Ledger {
+    db: std::sync::Arc<crate::db::Database>,
+}
+
+impl Ledger {
+    pub fn new(db: std::sync::Arc<crate::db::Database>) -> Self {
+        Self { db }
+    }
+
+    /// `/print/tag`の注文を記録する
+    ///
+    /// 印刷ジョブを登録できなかった注文も`Failed`として残す（`job_id`は`None`）．レジが再送した注文は別の件になる
+    pub async fn record_order(
+        &self,
+        kind: crate::print_queue::JobKind,
+        tag: &str,
+        breakdown: &crate::pricing::PriceBreakdown,
+        job_id: Option<&str>,
+        status: SaleStatus,
+    ) -> anyhow::Result<String> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let sale_id = id.clone();
+        let tag = tag.to_string();
+        let breakdown = breakdown.clone();
+        let job_id = job_id.map(|j| j.to_string());
+
+        self.db
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                tx.execute(
+                    "INSERT INTO sales (id, kind, tag, total, job_id, status, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    rusqlite::params![
+                        sale_id,
+                        kind.as_str(),
+                        tag,
+                        breakdown.total,
+                        job_id,
+                        status.as_str(),
+                        crate::db::now()
+                    ],
+                )?;
+                for (line_no, line) in breakdown.lines.iter().enumerate() {
+                    tx.execute(
+                        "INSERT INTO sale_items (sale_id, line_no, sku, name, qty, unit_price, subtotal)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                        rusqlite::params![
+                            sale_id,
+                            line_no,
+                            line.sku,
+                            line.name,
+                            line.qty,
+                            line.unit_price,
+                            line.subtotal
+                        ],
+                    )?;
+                }
+                for discount in &breakdown.discounts {
+                    tx.execute(
+                        "INSERT INTO sale_discounts (sale_id, name, sets, amount)
+                         VALUES (?1, ?2, ?3, ?4)",
+                        rusqlite::params![sale_id, discount.name, discount.sets, discount.amount],
+                    )?;
+                }
+                tx.commit()
+            })
+            .await?;
+
+        Ok(id)
+    }
+
+    /// `/print/pdf`の支払いを金額とともに記録する（再送されても1件のまま，結果だけを更新する）
+    ///
+    /// 発行したPDFとそのレシートの印刷ジョブは`issued_pdfs`と`print_jobs`から引く
+    pub async fn record_pdf_payment(
+        &self,
+        payment_id: &uuid::Uuid,
+        total: Option<u32>,
+        status: SaleStatus,
+    ) -> anyhow::Result<()> {
+        let payment_id = payment_id.to_string();
+        self.db
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO sales (id, kind, payment_id, total, status, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                     ON CONFLICT (payment_id) WHERE payment_id IS NOT NULL
+                     DO UPDATE SET total = excluded.total, status = excluded.status",
+                    rusqlite::params![
+                        uuid::Uuid::new_v4().to_string(),
+                        crate::print_queue::JobKind::PdfReceipt.as_str(),
+                        payment_id,
+                        total,
+                        status.as_str(),
+                        crate::db::now()
+                    ],
+                )
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// `[from, until)`の売上を品目ごとに集計する
+    ///
+    /// 注文レシート（`isOrder: false`）は同じ注文の控えなので数えない．失敗した注文と支払いも数えない．
+    /// PDF版（`pdf_sku`）は`/print/pdf`の支払いで数えるので，注文に含まれていても品目と合計から除く
+    pub async fn summarize(
+        &self,
+        from: i64,
+        until: i64,
+        pdf_sku: Option<&str>,
+    ) -> anyhow::Result<SalesSummary> {
+        let pdf_sku = pdf_sku.map(|sku| sku.to_string());
+        self.db
+            .call(move |conn| {
+                let order = crate::print_queue::JobKind::Tag.as_str();
+                let pdf = crate::print_queue::JobKind::PdfReceipt.as_str();
+                let completed = SaleStatus::Completed.as_str();
+
+                let (orders, order_total): (u32, u32) = conn.query_row(
+                    "SELECT COUNT(*), COALESCE(SUM(total), 0) FROM sales
+                     WHERE kind = ?1 AND status = ?4 AND created_at >= ?2 AND created_at < ?3",
+                    rusqlite::params![order, from, until, completed],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?;
+                let pdf_in_orders: u32 = conn.query_row(
+                    "SELECT COALESCE(SUM(i.subtotal), 0)
+                     FROM sale_items i JOIN sales s ON s.id = i.sale_id
+                     WHERE s.kind = ?1 AND s.status = ?4
+                       AND s.created_at >= ?2 AND s.created_at < ?3 AND i.sku IS ?5",
+                    rusqlite::params![order, from, until, completed, pdf_sku],
+                    |row| row.get(0),
+                )?;
+
+                let items = conn
+                    .prepare(
+                        "SELECT i.sku, MAX(i.name), SUM(i.qty), MAX(i.unit_price), SUM(i.subtotal)
+                         FROM sale_items i JOIN sales s ON s.id = i.sale_id
+                         WHERE s.kind = ?1 AND s.status = ?4
+                           AND s.created_at >= ?2 AND s.created_at < ?3 AND i.sku IS NOT ?5
+                         GROUP BY i.sku ORDER BY MIN(s.rowid), MIN(i.line_no)",
+                    )?
+                    .query_map(
+                        rusqlite::params![order, from, until, completed, pdf_sku],
+                        |row| {
+                            Ok(crate::pricing::PricedLine {
+                                sku: row.get(0)?,
+                                name: row.get(1)?,
+                                qty: row.get(2)?,
+                                unit_price: row.get(3)?,
+                                subtotal: row.get(4)?,
+                            })
+                        },
+                    )?
+                    .collect::<rusqlite::Result<_>>()?;
+
+                let discounts = conn
+                    .prepare(
+                        "SELECT d.name, SUM(d.sets), SUM(d.amount)
+                         FROM sale_discounts d JOIN sales s ON s.id = d.sale_id
+                         WHERE s.kind = ?1 AND s.status = ?4
+                           AND s.created_at >= ?2 AND s.created_at < ?3
+                         GROUP BY d.name ORDER BY MIN(d.rowid)",
+                    )?
+                    .query_map(rusqlite::params![order, from, until, completed], |row| {
+                        Ok(crate::pricing::AppliedDiscount {
+                            name: row.get(0)?,
+                            sets: row.get(1)?,
+                            amount: row.get(2)?,
+                        })
+                    })?
+                    .collect::<rusqlite::Result<_>>()?;
+
+                let (pdf_payments, pdf_revenue): (u32, u32) = conn.query_row(
+                    "SELECT COUNT(*), COALESCE(SUM(total), 0) FROM sales
+                     WHERE kind = ?1 AND status = ?4 AND created_at >= ?2 AND created_at < ?3",
+                    rusqlite::params![pdf, from, until, completed],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?;
+                // 失敗した支払いで途中まで発行したPDFは，支払いと同じく数えない
+                let pdfs_issued = conn.query_row(
+                    "SELECT COUNT(*) FROM issued_pdfs i JOIN sales s ON s.payment_id = i.payment_id
+                     WHERE s.kind = ?3 AND s.status = ?4 AND i.created_at >= ?1 AND i.created_at < ?2",
+                    rusqlite::params![from, until, pdf, completed],
+                    |row| row.get(0),
+                )?;
+
+                Ok(SalesSummary {
+                    orders,
+                    items,
+                    discounts,
+                    // 注文の合計は割引後なので，PDF版を含むセット割引の値引きは注文側に残る
+                    total: order_total
+                        .saturating_sub(pdf_in_orders)
+                        .saturating_add(pdf_revenue),
+                    pdf_payments,
+                    pdf_revenue,
+                    pdfs_issued,
+                })
+            })
+            .await
+    }
+
+    /// 営業日を締めた日時（まだ締めていなければ`None`）
+    pub async fn closed_at(&self, business_date: &str) -> anyhow::Result<Option<i64>> {
+        let business_date = business_date.to_string();
+        self.db
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT closed_at FROM day_closures WHERE business_date = ?1",
+                    [business_date],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await
+    }
+
+    /// Zレポートの内容とともに営業日を締める（締め済みなら`false`）
+    pub async fn close_day(
+        &self,
+        business_date: &str,
+        closed_at: i64,
+        report: &str,
+    ) -> anyhow::Result<bool> {
+        let business_date = business_date.to_string();
+        let report = report.to_string();
+        let inserted = self
+            .db
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT OR IGNORE INTO day_closures (business_date, closed_at, report)
+                     VALUES (?1, ?2, ?3)",
+                    rusqlite::params![business_date, closed_at, report],
+                )
+            })
+            .await?;
+
+        Ok(inserted > 0)
+    }
+
+    /// `[from, until)`に記録した売上を古い順に返す
+    pub async fn list(&self, from: i64, until: i64, limit: u32) -> anyhow::Result<Vec<Sale>> {
+        self.db
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, kind, tag, payment_id, total, job_id, created_at, status FROM sales
+                     WHERE created_at >= ?1 AND created_at < ?2
+                     ORDER BY created_at, rowid LIMIT ?3",
+                )?;
+                let rows = stmt
+                    .query_map(rusqlite::params![from, until, limit], |row| {
+                        let kind: String = row.get(1)?;
+                        let status: String = row.get(7)?;
+                        Ok((
+                            Sale {
+                                id: row.get(0)?,
+                                kind: crate::print_queue::JobKind::parse(&kind)?,
+                                tag: row.get(2)?,
+                                payment_id: row.get(3)?,
+                                total: row.get(4)?,
+                                items: Vec::new(),
+                                discounts: Vec::new(),
+                                pdf_ids: Vec::new(),
+                                status: SaleStatus::parse(&status).unwrap_or(SaleStatus::Completed),
+                                jobs: Vec::new(),
+                                created_at: row.get(6)?,
+                            },
+                            row.get::<_, Option<String>>(5)?,
+                        ))
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+
+                rows.into_iter()
+                    .map(|(mut sale, job_id)| {
+                        load_details(conn, &mut sale, job_id)?;
+                        Ok(sale)
+                    })
+                    .collect()
+            })
+            .await
+    }
+}
+
+/// 品目・割引・PDF ID・印刷ジョブを埋める
+fn load_details(
+    conn: &rusqlite::Connection,
+    sale: &mut Sale,
+    job_id: Option<String>,
+) -> rusqlite::Result<()> {
+    sale.items = conn
+        .prepare_cached(
+            "SELECT sku, name, qty, unit_price, subtotal FROM sale_items
+             WHERE sale_id = ?1 ORDER BY line_no",
+        )?
+        .query_map([&sale.id], |row| {
+            Ok(crate::pricing::PricedLine {
+                sku: row.get(0)?,
+                name: row.get(1)?,
+                qty: row.get(2)?,
+                unit_price: row.get(3)?,
+                subtotal: row.get(4)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+
+    sale.discounts = conn
+        .prepare_cached(
+            "SELECT name, sets, amount FROM sale_discounts WHERE sale_id = ?1 ORDER BY rowid",
+        )?
+        .query_map([&sale.id], |row| {
+            Ok(crate::pricing::AppliedDiscount {
+                name: row.get(0)?,
+                sets: row.get(1)?,
+                amount: row.get(2)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+
+    if let Some(payment_id) = &sale.payment_id {
+        sale.pdf_ids = conn
+            .prepare_cached("SELECT pdf_id FROM issued_pdfs WHERE payment_id = ?1 ORDER BY rowid")?
+            .query_map([payment_id], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+    }
+
+    let job_row = |row: &rusqlite::Row<'_>| {
+        let status: String = row.get(1)?;
+        Ok(SaleJob {
+            id: row.get(0)?,
+            status: crate::print_queue::JobStatus::parse(&status),
+        })
+    };
+    if let Some(job_id) = job_id {
+        let job = conn
+            .prepare_cached("SELECT id, status FROM print_jobs WHERE id = ?1")?
+            .query_map([&job_id], job_row)?
+            .next()
+            .transpose()?;
+        // ジョブが消えていても台帳には残す
+        sale.jobs.push(job.unwrap_or(SaleJob {
+            id: job_id,
+            status: None,
+        }));
+    }
+    for pdf_id in &sale.pdf_ids {
+        let jobs = conn
+            .prepare_cached(
+                "SELECT id, status FROM print_jobs WHERE kind = ?1 AND reference = ?2 ORDER BY rowid",
+            )?
+            .query_map(
+                rusqlite::params![crate::print_queue::JobKind::PdfReceipt.as_str(), pdf_id],
+                job_row,
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        sale.jobs.extend(jobs);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    fn ledger() -> super::Ledger {
+        let db = crate::db::Database::open(":memory:").unwrap();
+        super::Ledger::new(std::sync::Arc::new(db))
+    }
+
+    fn breakdown(total: u32) -> crate::pricing::PriceBreakdown {
+        crate::pricing::PriceBreakdown {
+            lines: vec![crate::pricing::PricedLine {
+                sku: "ff".to_string(),
+                name: "フランクフルト".to_string(),
+                qty: total / 300,
+                unit_price: 300,
+                subtotal: total,
+            }],
+            discounts: Vec::new(),
+            total,
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_orders_are_listed_but_not_summarized() {
+        let ledger = ledger();
+        let tag = crate::print_queue::JobKind::Tag;
+        // 印刷ジョブを登録できず，レジが同じ注文を再送して成功した
+        ledger
+            .record_order(tag, "1", &breakdown(600), None, super::SaleStatus::Failed)
+            .await
+            .unwrap();
+        ledger
+            .record_order(
+                tag,
+                "1",
+                &breakdown(600),
+                Some("job"),
+                super::SaleStatus::Completed,
+            )
+            .await
+            .unwrap();
+
+        let summary = ledger.summarize(0, i64::MAX, None).await.unwrap();
+        assert_eq!(summary.orders, 1);
+        assert_eq!(summary.total, 600);
+        assert_eq!(summary.items.len(), 1);
+        assert_eq!(summary.items[0].qty, 2);
+
+        let sales = ledger.list(0, i64::MAX, 10).await.unwrap();
+        let statuses: Vec<super::SaleStatus> = sales.iter().map(|sale| sale.status).collect();
+        assert_eq!(
+            statuses,
+            [super::SaleStatus::Failed, super::SaleStatus::Completed]
+        );
+    }
+
+    #[tokio::test]
+    async fn pdf_payment_is_one_sale_whose_status_follows_retries() {
+        let ledger = ledger();
+        let payment_id = uuid::Uuid::new_v4();
+
+        ledger
+            .record_pdf_payment(&payment_id, Some(500), super::SaleStatus::Failed)
+            .await
+            .unwrap();
+        assert_eq!(
+            ledger
+                .summarize(0, i64::MAX, None)
+                .await
+                .unwrap()
+                .pdf_payments,
+            0
+        );
+
+        ledger
+            .record_pdf_payment(&payment_id, Some(500), super::SaleStatus::Completed)
+            .await
+            .unwrap();
+        let sales = ledger.list(0, i64::MAX, 10).await.unwrap();
+        assert_eq!(sales.len(), 1);
+        assert_eq!(sales[0].status, super::SaleStatus::Completed);
+        assert_eq!(sales[0].payment_id, Some(payment_id.to_string()));
+        assert_eq!(
+            ledger
+                .summarize(0, i64::MAX, None)
+                .await
+                .unwrap()
+                .pdf_payments,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn pdf_sku_is_counted_from_pdf_payments() {
+        let ledger = ledger();
+        // 注文にPDF版が含まれていても，代金は`/print/pdf`の支払いで数える
+        let mut order = breakdown(300);
+        order.lines.push(crate::pricing::PricedLine {
+            sku: "pdf_book".to_string(),
+            name: "PDF版".to_string(),
+            qty: 2,
+            unit_price: 500,
+            subtotal: 1000,
+        });
+        order.total = 1300;
+        ledger
+            .record_order(
+                crate::print_queue::JobKind::Tag,
+                "1",
+                &order,
+                Some("job"),
+                super::SaleStatus::Completed,
+            )
+            .await
+            .unwrap();
+        ledger
+            .record_pdf_payment(
+                &uuid::Uuid::new_v4(),
+                Some(1000),
+                super::SaleStatus::Completed,
+            )
+            .await
+            .unwrap();
+        ledger
+            .record_pdf_payment(&uuid::Uuid::new_v4(), Some(500), super::SaleStatus::Failed)
+            .await
+            .unwrap();
+
+        let summary = ledger
+            .summarize(0, i64::MAX, Some("pdf_book"))
+            .await
+            .unwrap();
+        assert_eq!(summary.items.len(), 1);
+        assert_eq!(summary.items[0].sku, "ff");
+        assert_eq!(summary.pdf_payments, 1);
+        assert_eq!(summary.pdf_revenue, 1000);
+        assert_eq!(summary.total, 1300);
+
+        // `pdf_sku`がなければ注文はそのまま数える
+        let summary = ledger.summarize(0, i64::MAX, None).await.unwrap();
+        assert_eq!(summary.items.len(), 2);
+        assert_eq!(summary.total, 2300);
+    }
+
+    #[tokio::test]
+    async fn pdfs_of_failed_payments_are_not_counted() {
+        let db = std::sync::Arc::new(crate::db::Database::open(":memory:").unwrap());
+        let ledger = super::Ledger::new(db.clone());
+        let issued_pdfs = crate::issued_pdfs::IssuedPdfs::new(db);
+
+        let paid = uuid::Uuid::new_v4();
+        let failed = uuid::Uuid::new_v4();
+        for payment_id in [&paid, &paid, &failed] {
+            issued_pdfs
+                .record(&uuid::Uuid::new_v4(), payment_id, 100)
+                .await
+                .unwrap();
+        }
+        ledger
+            .record_pdf_payment(&paid, Some(1000), super::SaleStatus::Completed)
+            .await
+            .unwrap();
+        ledger
+            .record_pdf_payment(&failed, Some(1000), super::SaleStatus::Failed)
+            .await
+            .unwrap();
+
+        let summary = ledger.summarize(0, i64::MAX, None).await.unwrap();
+        assert_eq!(summary.pdf_payments, 1);
+        assert_eq!(summary.pdfs_issued, 2);
+    }
+}
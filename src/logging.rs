@@ -0,0 +1,24 @@
+use anyhow::Context as _;
+
+/// ログの出力先を設定する
+///
+/// - `text`: 1行に1件（既定）
+/// - `pretty`: 複数行で読みやすく
+/// - `json`: 1行に1件のJSON（リクエストのspanに付けた`payment_id`などもフィールドとして出る）
+///
+/// spanを閉じたときにもログを出し，処理にかかった時間（`time.busy`）がわかるようにする
+pub fn init(format: &str, level: &str) -> anyhow::Result<()> {
+    let filter = tracing_subscriber::EnvFilter::try_new(level)
+        .context(format!("invalid log level: {}", level))?;
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE);
+
+    let result = match format {
+        "text" => builder.try_init(),
+        "pretty" => builder.pretty().try_init(),
+        "json" => builder.json().try_init(),
+        other => anyhow::bail!("unknown log format: {}", other),
+    };
+    result.map_err(|e| anyhow::anyhow!("failed to set up logging: {}", e))
+}
@@ -1,14 +1,321 @@
+use actix_web::dev::Service as _;
+use anyhow::Context as _;
+use tracing::Instrument as _;
+
+mod auth;
+mod blob_store;
+mod call_numbers;
+mod catalog;
 mod config;
+mod db;
+mod issued_pdfs;
+mod kitchen;
+mod ledger;
+mod logging;
+mod metrics;
 mod pdf_handler;
+mod pdf_stamp;
+mod pricing;
+mod print_queue;
+mod printer_status;
+mod printer_transport;
+mod printers;
 mod r2_client;
+mod receipt_preview;
 mod receipt_printer;
+mod receipt_template;
+mod reload;
+mod reports;
+mod self_check;
+mod text_renderer;
+
+/// PDFに入れる購入者名の最大文字数
+const MAX_BUYER_NAME_CHARS: usize = 64;
+/// `/verify`で受け付けるPDFの最大サイズ（バイト）
+const MAX_VERIFY_PDF_BYTES: usize = 64 * 1024 * 1024;
+/// `/events`で変化がないときにコメントを送る間隔（接続が切れたことに気づくため）
+const EVENTS_KEEPALIVE: std::time::Duration = std::time::Duration::from_secs(15);
+/// 店頭のテレビに映す呼び出し番号のページ
+const DISPLAY_HTML: &str = include_str!("../static/display.html");
 
 #[derive(Clone)]
 struct AppState {
     config: std::sync::Arc<config::Config>,
+    catalog: std::sync::Arc<catalog::Catalog>,
     pdf_handler: std::sync::Arc<pdf_handler::PdfHandler>,
-    r2_client: std::sync::Arc<r2_client::R2Client>,
+    blob_store: std::sync::Arc<dyn blob_store::BlobStore>,
     receipt_printer: std::sync::Arc<receipt_printer::ReceiptPrinter>,
+    print_queue: std::sync::Arc<print_queue::PrintQueue>,
+    issued_pdfs: std::sync::Arc<issued_pdfs::IssuedPdfs>,
+    ledger: std::sync::Arc<ledger::Ledger>,
+    call_numbers: std::sync::Arc<call_numbers::CallNumbers>,
+    printers: std::sync::Arc<printers::Printers>,
+    metrics: std::sync::Arc<metrics::Metrics>,
+    authenticator: std::sync::Arc<auth::Authenticator>,
+}
+
+impl AppState {
+    /// 読み直した`config`から品目表やテンプレート，証明書，プリンターを作り直す
+    ///
+    /// ストレージとデータベースはそのまま引き継ぐ．設定が変わっていないプリンターも引き継ぐ
+    fn reconfigure(&self, config: config::Config) -> anyhow::Result<Self> {
+        let catalog =
+            catalog::Catalog::load(&config.catalog_path).context("failed to load catalog")?;
+        let templates = receipt_template::Templates::load(&config.templates_dir)
+            .context("failed to load receipt templates")?;
+        let pdf_handler = pdf_handler::PdfHandler::new(&config.cert_path, &config.key_path)
+            .context("failed to load signing certificate")?;
+        let text_renderer = config
+            .font_path
+            .as_ref()
+            .map(|path| text_renderer::TextRenderer::from_file(path, config.printer_dot_width))
+            .transpose()
+            .context("failed to load font")?;
+
+        // 状態を問い合わせる間隔が変わったら，監視を始め直すためにすべて作り直す
+        let previous = (config.printer_status_interval_secs
+            == self.config.printer_status_interval_secs)
+            .then_some(&*self.printers);
+        let printers = printers::Printers::from_config(&config, previous)
+            .context("failed to configure printers")?;
+
+        let receipt_printer = receipt_printer::ReceiptPrinter::new(
+            config.receipts_dir.clone().into(),
+            text_renderer,
+            templates,
+            self.print_queue.clone(),
+        );
+
+        Ok(Self {
+            config: std::sync::Arc::new(config),
+            catalog: std::sync::Arc::new(catalog),
+            pdf_handler: std::sync::Arc::new(pdf_handler),
+            receipt_printer: std::sync::Arc::new(receipt_printer),
+            printers: std::sync::Arc::new(printers),
+            ..self.clone()
+        })
+    }
+}
+
+/// 設定ファイルなどを読み直して`AppState`を入れ替える（SIGHUP，`POST /admin/reload`，ファイルの変更）
+struct Reloader {
+    args: config::Args,
+    live: actix_web::web::Data<reload::Live<AppState>>,
+    /// 読み直しを同時に1つしか走らせない
+    lock: tokio::sync::Mutex<()>,
+    /// 印刷ワーカーを起動済みのプリンター名
+    workers: std::sync::Mutex<std::collections::BTreeSet<String>>,
+    /// 読み直すたびに増える（監視するファイルを選び直す）
+    reloaded: tokio::sync::watch::Sender<u64>,
+}
+
+impl Reloader {
+    fn new(args: config::Args, live: actix_web::web::Data<reload::Live<AppState>>) -> Self {
+        Self {
+            args,
+            live,
+            lock: tokio::sync::Mutex::new(()),
+            workers: Default::default(),
+            reloaded: tokio::sync::watch::Sender::new(0),
+        }
+    }
+
+    /// 設定を読み直し，自己診断で問題がなければ`AppState`を入れ替える
+    ///
+    /// 問題が見つかったら今の設定のまま動き続ける．処理中のリクエストは読み直す前の`AppState`で最後まで処理する
+    async fn reload(&self, reason: &str) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().await;
+        tracing::info!("Reloading configuration ({})", reason);
+
+        let result = self.swap().await;
+        match &result {
+            Ok(()) => tracing::info!("Configuration reloaded"),
+            Err(e) => tracing::error!("Reload failed; keeping the current configuration: {:#}", e),
+        }
+        self.reloaded.send_modify(|generation| *generation += 1);
+        result
+    }
+
+    async fn swap(&self) -> anyhow::Result<()> {
+        let args = self.args.clone();
+        let config = tokio::task::spawn_blocking(move || {
+            let config = config::Config::load(&args)?;
+            let report = self_check::run(&config);
+            report.log();
+            if report.failures() > 0 {
+                anyhow::bail!("self-check found {} problem(s)", report.failures());
+            }
+            Ok(config)
+        })
+        .await
+        .context("reload task panicked")??;
+
+        let previous = self.live.current();
+        for (name, changed) in [
+            (
+                "bind_address",
+                config.bind_address != previous.config.bind_address,
+            ),
+            (
+                "database_path",
+                config.database_path != previous.config.database_path,
+            ),
+            ("storage", config.storage != previous.config.storage),
+            (
+                "log_format",
+                config.log_format != previous.config.log_format,
+            ),
+            ("log_level", config.log_level != previous.config.log_level),
+        ] {
+            if changed {
+                tracing::warn!("{} changed; restart kawauso to apply it", name);
+            }
+        }
+
+        let state = previous.reconfigure(config)?;
+        self.live.replace(state);
+        self.start_printers(Some(&previous.printers)).await;
+
+        Ok(())
+    }
+
+    /// 今の設定のプリンターで印刷を始める
+    ///
+    /// 消えたプリンター宛てのジョブを既定のプリンターに移し，新しく作ったプリンターの状態監視と，
+    /// まだワーカーのいないプリンター名の印刷ワーカーを起動する
+    async fn start_printers(&self, previous: Option<&printers::Printers>) {
+        let state = self.live.current();
+
+        match state
+            .print_queue
+            .reassign_orphans(state.printers.names(), state.printers.default_name())
+            .await
+        {
+            Ok(0) => {}
+            Ok(n) => tracing::info!(
+                "Moved {} print job(s) for removed printers to {}",
+                n,
+                state.printers.default_name()
+            ),
+            Err(e) => tracing::warn!("Failed to reassign print jobs: {}", e),
+        }
+
+        for printer in state.printers.iter() {
+            let monitored = previous.is_some_and(|previous| {
+                previous
+                    .iter()
+                    .any(|old| std::sync::Arc::ptr_eq(old, printer))
+            });
+            if !monitored {
+                actix_rt::spawn(printer_status::run_monitor(
+                    std::sync::Arc::downgrade(&printer.status),
+                    std::time::Duration::from_secs(state.config.printer_status_interval_secs),
+                ));
+            }
+
+            let started = self
+                .workers
+                .lock()
+                .map(|mut workers| workers.insert(printer.name.clone()))
+                .unwrap_or(false);
+            if started {
+                let live = self.live.clone();
+                let name = printer.name.clone();
+                actix_rt::spawn(print_queue::run_worker(
+                    state.print_queue.clone(),
+                    printer.name.clone(),
+                    move || live.current().printers.get(Some(&name)).ok().cloned(),
+                ));
+            }
+        }
+    }
+}
+
+/// 変更を監視するファイルとディレクトリ
+///
+/// 設定ファイル，品目表，元のPDF，証明書，フォントと，テンプレートと画像のディレクトリ
+fn watched_paths(args: &config::Args, state: &AppState) -> (Vec<String>, Vec<String>) {
+    let config = &state.config;
+    let files = [
+        Some(&args.config_path()),
+        Some(&config.catalog_path),
+        Some(&config.base_pdf_path),
+        Some(&config.cert_path),
+        Some(&config.key_path),
+        config.font_path.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|path| !path.is_empty())
+    .cloned()
+    .collect();
+
+    let mut dirs = vec![config.templates_dir.clone()];
+    for item in state.catalog.items() {
+        if let catalog::ItemLabel::Images {
+            dirs: label_dirs, ..
+        } = &item.label
+        {
+            dirs.extend(label_dirs.iter().cloned());
+        }
+    }
+    for path in state.receipt_printer.templates().image_paths() {
+        if let Some(dir) = std::path::Path::new(path).parent() {
+            dirs.push(dir.to_string_lossy().into_owned());
+        }
+    }
+    dirs.retain(|dir| !dir.is_empty());
+
+    (files, dirs)
+}
+
+/// SIGHUPを受けるたびに設定を読み直す
+async fn reload_on_signal(reloader: actix_web::web::Data<Reloader>) {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::warn!("Cannot listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        reloader.reload("SIGHUP").await.ok();
+    }
+}
+
+/// 設定ファイルや画像が変わったら読み直す
+async fn reload_on_change(reloader: actix_web::web::Data<Reloader>) {
+    let mut reloaded = reloader.reloaded.subscribe();
+    loop {
+        reloaded.borrow_and_update();
+        let (files, dirs) = watched_paths(&reloader.args, &reloader.live.current());
+        let mut watcher = match reload::FileWatcher::new(&files, &dirs) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                tracing::warn!("Not watching configuration files: {:#}", e);
+                return;
+            }
+        };
+
+        tokio::select! {
+            changed = watcher.changed() => {
+                let Some(path) = changed else {
+                    return;
+                };
+                reloader
+                    .reload(&format!("{} changed", path.display()))
+                    .await
+                    .ok();
+            }
+            // ほかのきっかけで読み直したら，監視するファイルを選び直す
+            changed = reloaded.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+        }
+    }
 }
 
 #[derive(serde::Deserialize)]
@@ -18,232 +325,1632 @@ struct PrintPdfRequest {
     payment_id: uuid::Uuid,
     #[serde(rename = "paidAt")]
     paid_at: u64,
+    /// PDFの各ページに入れる購入者名（任意）
+    #[serde(rename = "buyerName")]
+    buyer_name: Option<String>,
+    /// 省略した場合はルーティング規則で選ぶ
+    printer: Option<String>,
+    /// 発行済みの支払いIDが再送されたときに，レシートを印刷し直す
+    #[serde(default)]
+    reprint: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct PrintTagRequest {
+    /// 呼び出し番号．タグ（`isOrder: true`）で省略するとサーバーが連番を割り当てる
+    tag: Option<String>,
+    items: Vec<catalog::OrderItem>,
+    /// 省略した場合はサーバー側で計算した合計を使う
+    total: Option<u32>,
+    #[serde(rename = "isOrder")]
+    is_order: bool,
+    /// 省略した場合はルーティング規則で選ぶ
+    printer: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct ListJobsQuery {
+    status: Option<String>,
+    printer: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(serde::Deserialize)]
+struct ListSalesQuery {
+    /// 日本時間の日付（YYYY-MM-DD）．省略すると今日
+    date: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(serde::Deserialize)]
+struct DailyReportQuery {
+    /// `z`（既定）か`x`
+    kind: Option<String>,
+    /// 日本時間の日付（YYYY-MM-DD）．省略すると今日
+    date: Option<String>,
+    /// `json`（既定）か`csv`
+    format: Option<String>,
+    printer: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct PreviewQuery {
+    /// `report`のプレビューで使う日本時間の日付（YYYY-MM-DD）
+    date: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct PreviewOrderRequest {
+    tag: String,
+    items: Vec<catalog::OrderItem>,
+}
+
+#[derive(Default, serde::Deserialize)]
+struct PreviewPdfRequest {
+    #[serde(rename = "paidAt")]
+    paid_at: Option<u64>,
+    count: Option<u32>,
+}
+
+#[derive(serde::Deserialize)]
+struct ListCallsQuery {
+    /// 省略すると受け渡し前（`cooking`，`ready`，`called`）の番号
+    status: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct PrinterQuery {
+    printer: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct VerifyQuery {
+    #[serde(rename = "pdfId")]
+    pdf_id: Option<uuid::Uuid>,
+}
+
+#[derive(serde::Serialize)]
+struct VerifyResponse {
+    /// 本アプリの証明書で署名され，署名後に改変されていないか
+    genuine: bool,
+    #[serde(rename = "pdfId")]
+    pdf_id: Option<String>,
+    /// 発行記録（どの取引で発行したか）
+    issued: Option<issued_pdfs::IssuedPdf>,
+    error: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct PrintPdfResponse {
+    success: bool,
+    message: String,
+    #[serde(rename = "paymentId")]
+    payment_id: String,
+    /// 同じ支払いIDで発行済みだったPDFをそのまま返した
+    #[serde(rename = "alreadyIssued")]
+    already_issued: bool,
+    pdfs: Vec<PdfInfo>,
+}
+
+#[derive(serde::Serialize)]
+struct PdfInfo {
+    id: String,
+    url: String,
+}
+
+#[derive(serde::Serialize)]
+struct PrintTagResponse {
+    success: bool,
+    message: String,
+    /// 印刷した呼び出し番号
+    tag: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct TotalMismatchResponse {
+    success: bool,
+    message: String,
+    expected: pricing::PriceBreakdown,
+}
+
+/// リクエストごとのspan
+///
+/// ハンドラーが`record_request_field`で取引ID，PDF ID，呼び出し番号，印刷ジョブIDを記録し，その間のログにすべて付く
+fn request_span(req: &actix_web::dev::ServiceRequest) -> tracing::Span {
+    tracing::info_span!(
+        "request",
+        request_id = %uuid::Uuid::new_v4(),
+        method = %req.method(),
+        path = %req.path(),
+        status = tracing::field::Empty,
+        client = tracing::field::Empty,
+        payment_id = tracing::field::Empty,
+        pdf_id = tracing::field::Empty,
+        tag = tracing::field::Empty,
+        job_id = tracing::field::Empty,
+    )
+}
+
+/// ハンドラーで分かった値をリクエストのspanに記録する（`request_span`で宣言したフィールドのみ）
+fn record_request_field(field: &str, value: impl std::fmt::Display) {
+    tracing::Span::current().record(field, tracing::field::display(value));
+}
+
+/// `EventSource`で開くので，APIキーを`?access_token=`でも渡せるルート
+const EVENT_STREAMS: &[&str] = &["/events", "/kitchen/events"];
+
+/// エンドポイントごとに呼べる端末（ここにないエンドポイントは`admin`だけが呼べる）
+fn route_access(method: &actix_web::http::Method, pattern: &str) -> auth::Access {
+    const REGISTER: &[auth::Role] = &[auth::Role::Register];
+    const KITCHEN: &[auth::Role] = &[auth::Role::Kitchen];
+    const STAFF: &[auth::Role] = &[auth::Role::Register, auth::Role::Kitchen];
+
+    match (method.as_str(), pattern) {
+        // お客さんの端末と店頭のテレビ
+        ("GET", "/health" | "/download/{pdf_id}" | "/display" | "/events") => auth::Access::Public,
+        // お客さんに印刷内容を見せるためにレジも呼ぶ
+        ("POST", "/print/pdf" | "/print/tag" | "/preview/{kind}") => auth::Access::Roles(REGISTER),
+        ("GET", "/kitchen" | "/kitchen/events") | ("POST", "/kitchen/{tag}/done") => {
+            auth::Access::Roles(KITCHEN)
+        }
+        ("GET", "/printer/status" | "/calls" | "/calls/{tag}" | "/jobs" | "/jobs/{id}")
+        | ("POST", "/cut" | "/calls/{tag}/{status}" | "/jobs/{id}/reprint") => {
+            auth::Access::Roles(STAFF)
+        }
+        _ => auth::Access::Roles(&[]),
+    }
+}
+
+/// `route_access`に従って端末を認証する（認証できなければ401，役割が違えば403）
+async fn authorize(
+    mut req: actix_web::dev::ServiceRequest,
+    next: actix_web::middleware::Next<impl actix_web::body::MessageBody + 'static>,
+) -> actix_web::Result<actix_web::dev::ServiceResponse<impl actix_web::body::MessageBody>> {
+    let access = match req.match_pattern() {
+        Some(pattern) => route_access(req.method(), &pattern),
+        // 存在しないパスはそのまま404にする
+        None => auth::Access::Public,
+    };
+    let state = req
+        .app_data::<actix_web::web::Data<reload::Live<AppState>>>()
+        .map(|live| live.current());
+    let Some(state) = state else {
+        return Err(actix_web::error::ErrorInternalServerError(
+            "application state is not configured",
+        ));
+    };
+    if matches!(access, auth::Access::Public) || !state.config.require_auth {
+        return next
+            .call(req)
+            .await
+            .map(actix_web::dev::ServiceResponse::map_into_left_body);
+    }
+
+    let error = match authenticate(&state, &mut req).await {
+        Ok(client) => {
+            record_request_field("client", &client.name);
+            if access.allows(client.role) {
+                return next
+                    .call(req)
+                    .await
+                    .map(actix_web::dev::ServiceResponse::map_into_left_body);
+            }
+            tracing::warn!(
+                "Client {} ({}) is not allowed to call this endpoint",
+                client.name,
+                client.role.as_str()
+            );
+            actix_web::error::ErrorForbidden(format!(
+                "client {} is not allowed to call this endpoint",
+                client.name
+            ))
+        }
+        Err(e) => {
+            tracing::warn!("Unauthenticated request: {:#}", e);
+            actix_web::error::ErrorUnauthorized(format!("unauthenticated: {:#}", e))
+        }
+    };
+    Ok(req.error_response(error).map_into_right_body())
+}
+
+/// APIキーか署名で端末を特定する（署名するときはボディを読んでからハンドラーに渡し直す）
+async fn authenticate(
+    state: &AppState,
+    req: &mut actix_web::dev::ServiceRequest,
+) -> anyhow::Result<auth::Client> {
+    let Some(signature) = auth::Signature::from_headers(req.headers())? else {
+        let event_stream = req.method() == actix_web::http::Method::GET
+            && req
+                .match_pattern()
+                .is_some_and(|pattern| EVENT_STREAMS.contains(&pattern.as_str()));
+        let key = auth::api_key(req.headers(), event_stream.then(|| req.query_string()))
+            .context("missing API key or signature")?;
+        return auth::check_key(&state.config.clients, &key);
+    };
+
+    let mut payload = actix_web::HttpMessage::take_payload(req);
+    let mut body = actix_web::web::BytesMut::new();
+    while let Some(chunk) = futures_util::StreamExt::next(&mut payload).await {
+        let chunk = chunk.context("failed to read request body")?;
+        // 一番大きいボディを受け付ける`/verify`に合わせる
+        if body.len() + chunk.len() > MAX_VERIFY_PDF_BYTES {
+            anyhow::bail!("request body is too large");
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let body = body.freeze();
+
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map_or_else(|| req.path().to_string(), ToString::to_string);
+    let client = state.authenticator.check_signature(
+        &state.config,
+        &signature,
+        req.method().as_str(),
+        &path_and_query,
+        &body,
+    );
+    req.set_payload(body.into());
+    client
+}
+
+/// 送り先のプリンターを選ぶ（存在しないプリンターを指定された場合は400）
+fn select_printer<'a>(
+    state: &'a AppState,
+    kind: print_queue::JobKind,
+    requested: Option<&str>,
+) -> actix_web::Result<&'a std::sync::Arc<printers::Printer>> {
+    state
+        .printers
+        .route(kind, requested)
+        .map_err(|e| actix_web::error::ErrorBadRequest(e.to_string()))
+}
+
+/// 用紙切れの間は新しい印刷を受け付けない
+fn ensure_printer_ready(printer: &printers::Printer) -> actix_web::Result<()> {
+    if printer.status.is_paper_out() {
+        tracing::warn!(
+            "Rejecting print request: printer {} is out of paper",
+            printer.name
+        );
+        return Err(actix_web::error::ErrorServiceUnavailable(format!(
+            "printer {} is out of paper",
+            printer.name
+        )));
+    }
+
+    Ok(())
+}
+
+async fn print_pdf(
+    state: reload::Current<AppState>,
+    req: actix_web::web::Json<PrintPdfRequest>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    record_request_field("payment_id", req.payment_id);
+    tracing::info!(
+        "Print PDF request - Payment ID: {}, Count: {}, Paid at: {}",
+        req.payment_id,
+        req.count,
+        req.paid_at
+    );
+
+    let printer = select_printer(
+        &state,
+        print_queue::JobKind::PdfReceipt,
+        req.printer.as_deref(),
+    )?;
+
+    // 1回の支払いで署名・アップロードする枚数を注文と同じ上限に抑える
+    let max_copies = state.catalog.max_qty();
+    if req.count == 0 || req.count > max_copies {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "count must be between 1 and {}",
+            max_copies
+        )));
+    }
+    // SQLiteの整数に収まらない日時はデータベースに書けない
+    if i64::try_from(req.paid_at).is_err() {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "paidAt is out of range: {}",
+            req.paid_at
+        )));
+    }
+
+    let buyer_name = req
+        .buyer_name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+    if let Some(name) = buyer_name
+        && (name.chars().count() > MAX_BUYER_NAME_CHARS || name.chars().any(char::is_control))
+    {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "buyerName must be at most {} characters without control characters",
+            MAX_BUYER_NAME_CHARS
+        )));
+    }
+
+    let total = state
+        .catalog
+        .pdf_total(req.count)
+        .map_err(|e| actix_web::error::ErrorBadRequest(e.to_string()))?;
+
+    let claim = state
+        .issued_pdfs
+        .claim_payment(&req.payment_id, req.count, req.paid_at)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to look up payment {}: {}", req.payment_id, e);
+            actix_web::error::ErrorInternalServerError(format!("failed to look up payment: {}", e))
+        })?;
+
+    let issued = match claim {
+        issued_pdfs::PaymentClaim::Issue(issued) => issued,
+        issued_pdfs::PaymentClaim::Issued(issued) => {
+            tracing::info!(
+                "Payment {} already issued {} PDF(s)",
+                req.payment_id,
+                issued.len()
+            );
+            return reply_issued_pdfs(&state, &req, printer, issued).await;
+        }
+        issued_pdfs::PaymentClaim::InProgress => {
+            tracing::warn!("Payment {} is already being issued", req.payment_id);
+            return Err(actix_web::error::ErrorConflict(format!(
+                "PDFs for payment {} are being issued",
+                req.payment_id
+            )));
+        }
+        issued_pdfs::PaymentClaim::Mismatch { count, paid_at } => {
+            return Err(actix_web::error::ErrorConflict(format!(
+                "payment {} was already requested with count {} and paidAt {}",
+                req.payment_id, count, paid_at
+            )));
+        }
+    };
+
+    let result = issue_pdfs(&state, &req, printer, buyer_name, issued).await;
+    // 失敗した支払いも台帳に残す（再送して発行できれば`Completed`に変わる）
+    let status = match &result {
+        Ok(_) => ledger::SaleStatus::Completed,
+        Err(_) => ledger::SaleStatus::Failed,
+    };
+    if let Err(e) = state
+        .ledger
+        .record_pdf_payment(&req.payment_id, total, status)
+        .await
+    {
+        tracing::warn!(
+            "Failed to record payment {} in ledger: {}",
+            req.payment_id,
+            e
+        );
+    }
+    let finished = match &result {
+        Ok(_) => state.issued_pdfs.finish_payment(&req.payment_id).await,
+        Err(_) => state.issued_pdfs.fail_payment(&req.payment_id).await,
+    };
+    if let Err(e) = finished {
+        tracing::warn!("Failed to update payment status {}: {}", req.payment_id, e);
+    }
+    let pdfs = result?;
+
+    tracing::info!("{} QR code receipts printed", req.count);
+
+    Ok(actix_web::HttpResponse::Ok().json(PrintPdfResponse {
+        success: true,
+        message: format!(
+            "{} PDFs signed and uploaded. {} receipt(s) to be printed",
+            req.count, req.count
+        ),
+        payment_id: req.payment_id.to_string(),
+        already_issued: false,
+        pdfs,
+    }))
+}
+
+/// 署名済みPDFのダウンロード用URL（レシートのQRコードに載せる）
+fn pdf_url(state: &AppState, pdf_id: &str) -> String {
+    format!(
+        "{}/download/{}",
+        state.config.public_base_url.trim_end_matches('/'),
+        pdf_id
+    )
+}
+
+/// 署名済みPDFのストレージ上のキー
+fn pdf_object_key(pdf_id: &str) -> String {
+    format!("signed_pdfs/{}.pdf", pdf_id)
+}
+
+/// 発行済みの支払いIDが再送されたときは，同じPDFを返す（`reprint`ならレシートも印刷し直す）
+async fn reply_issued_pdfs(
+    state: &AppState,
+    req: &PrintPdfRequest,
+    printer: &printers::Printer,
+    issued: Vec<issued_pdfs::IssuedPdf>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    if req.reprint {
+        ensure_printer_ready(printer)?;
+    }
+
+    let mut pdfs = Vec::new();
+    for pdf in issued {
+        let url = pdf_url(state, &pdf.pdf_id);
+        if req.reprint
+            && let Err(e) = state
+                .receipt_printer
+                .print_pdf_receipt(&url, &pdf.pdf_id, pdf.paid_at, req.count, &printer.name)
+                .await
+        {
+            tracing::warn!("Failed to reprint receipt for PDF {}: {}", pdf.pdf_id, e);
+        }
+        pdfs.push(PdfInfo {
+            id: pdf.pdf_id,
+            url,
+        });
+    }
+
+    Ok(actix_web::HttpResponse::Ok().json(PrintPdfResponse {
+        success: true,
+        message: if req.reprint {
+            format!("{} PDFs already issued. Receipts reprinted", pdfs.len())
+        } else {
+            format!("{} PDFs already issued", pdfs.len())
+        },
+        payment_id: req.payment_id.to_string(),
+        already_issued: true,
+        pdfs,
+    }))
+}
+
+/// 足りない分のPDFを署名・アップロードし，レシートを印刷する
+///
+/// `issued`は前回の要求で発行済みのPDF（そのレシートは印刷済み）
+async fn issue_pdfs(
+    state: &AppState,
+    req: &PrintPdfRequest,
+    printer: &printers::Printer,
+    buyer_name: Option<&str>,
+    issued: Vec<issued_pdfs::IssuedPdf>,
+) -> actix_web::Result<Vec<PdfInfo>> {
+    ensure_printer_ready(printer)?;
+
+    let mut pdfs: Vec<PdfInfo> = issued
+        .into_iter()
+        .map(|pdf| PdfInfo {
+            url: pdf_url(state, &pdf.pdf_id),
+            id: pdf.pdf_id,
+        })
+        .collect();
+    if !pdfs.is_empty() {
+        tracing::info!(
+            "Resuming payment {}: {} PDF(s) already issued",
+            req.payment_id,
+            pdfs.len()
+        );
+    }
+
+    let base_pdf = state
+        .pdf_handler
+        .fetch_base_pdf(&state.config.base_pdf_path)
+        .instrument(tracing::info_span!("fetch_base_pdf"))
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch base PDF: {}", e);
+            actix_web::error::ErrorInternalServerError(format!("failed to fetch base PDF: {}", e))
+        })?;
+
+    for i in pdfs.len() as u32..req.count {
+        let pdf_id = uuid::Uuid::new_v4();
+        let span = tracing::info_span!("pdf", %pdf_id, n = i + 1, count = req.count);
+        let pdf = issue_pdf(state, req, printer, buyer_name, &base_pdf, pdf_id)
+            .instrument(span)
+            .await?;
+        pdfs.push(pdf);
+    }
+
+    Ok(pdfs)
+}
+
+/// PDFを1つ署名・保存・アップロードして発行済みとして記録し，そのレシートを印刷する
+async fn issue_pdf(
+    state: &AppState,
+    req: &PrintPdfRequest,
+    printer: &printers::Printer,
+    buyer_name: Option<&str>,
+    base_pdf: &[u8],
+    pdf_id: uuid::Uuid,
+) -> actix_web::Result<PdfInfo> {
+    tracing::info!("Processing PDF with ID: {}", pdf_id);
+
+    let started = std::time::Instant::now();
+    let pdf_handler = state.pdf_handler.clone();
+    let base_pdf = base_pdf.to_vec();
+    let base_pdf_path = state.config.base_pdf_path.clone();
+    let paid_at = req.paid_at;
+    let buyer_name = buyer_name.map(str::to_string);
+    let span = tracing::Span::current();
+    // 署名は重いので，ワーカーのスレッドを止めないようにブロッキングスレッドで行う
+    let signed_pdf = actix_web::web::block(move || {
+        let _entered = span.enter();
+        let stamp = pdf_stamp::CopyStamp {
+            pdf_id: &pdf_id,
+            paid_at,
+            buyer_name: buyer_name.as_deref(),
+        };
+        pdf_handler.sign_pdf(base_pdf, &stamp, &base_pdf_path)
+    })
+    .await?
+    .map_err(|e| {
+        tracing::error!("Failed to sign PDF {}: {}", pdf_id, e);
+        actix_web::error::ErrorInternalServerError(format!("failed to sign PDF {}: {}", pdf_id, e))
+    })?;
+    state
+        .metrics
+        .pdf_sign_seconds
+        .observe(started.elapsed().as_secs_f64());
+    state.metrics.pdfs_signed.inc();
+
+    let local_path =
+        std::path::Path::new(&state.config.signed_pdf_dir).join(format!("{}.pdf", pdf_id));
+    async {
+        tokio::fs::create_dir_all(&state.config.signed_pdf_dir).await?;
+        tracing::debug!("Saving signed PDF locally: {}", local_path.display());
+        tokio::fs::write(&local_path, &signed_pdf).await
+    }
+    .instrument(tracing::info_span!("save"))
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to save signed PDF locally: {}", e);
+        actix_web::error::ErrorInternalServerError(format!("failed to save signed PDF: {}", e))
+    })?;
+
+    let object_key = pdf_object_key(&pdf_id.to_string());
+    let started = std::time::Instant::now();
+    let uploaded = state
+        .blob_store
+        .put(&object_key, signed_pdf, "application/pdf")
+        .instrument(tracing::info_span!("upload", key = %object_key))
+        .await;
+    state
+        .metrics
+        .pdf_upload_seconds
+        .observe(started.elapsed().as_secs_f64());
+    uploaded.map_err(|e| {
+        tracing::error!("Failed to upload PDF: {}", e);
+        state.metrics.pdf_upload_failures.inc();
+        actix_web::error::ErrorInternalServerError(format!("failed to upload PDF: {}", e))
+    })?;
+    state.metrics.pdfs_uploaded.inc();
+
+    let pdf_url = pdf_url(state, &pdf_id.to_string());
+    tracing::info!("PDF uploaded: {}", pdf_url);
+
+    // アップロードまで済んだPDFだけを発行済みとして記録する（再送時はこれを返す）
+    state
+        .issued_pdfs
+        .record(&pdf_id, &req.payment_id, req.paid_at)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to record issued PDF {}: {}", pdf_id, e);
+            actix_web::error::ErrorInternalServerError(format!(
+                "failed to record PDF {}: {}",
+                pdf_id, e
+            ))
+        })?;
+
+    // レシートを印刷
+    if let Err(e) = state
+        .receipt_printer
+        .print_pdf_receipt(
+            &pdf_url,
+            &pdf_id.to_string(),
+            req.paid_at,
+            req.count,
+            &printer.name,
+        )
+        .await
+    {
+        tracing::warn!("Failed to print receipt for PDF {}: {}", pdf_id, e);
+        // レシートの印刷失敗はエラーを返さず続行
+    }
+
+    Ok(PdfInfo {
+        id: pdf_id.to_string(),
+        url: pdf_url,
+    })
+}
+
+/// 持ち込まれたPDF（またはPDF IDに対応する保存済みのPDF）の署名を検証し，どの取引で発行したものか返す
+async fn verify_pdf(
+    state: reload::Current<AppState>,
+    query: actix_web::web::Query<VerifyQuery>,
+    body: actix_web::web::Bytes,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    if let Some(pdf_id) = query.pdf_id {
+        record_request_field("pdf_id", pdf_id);
+    }
+    let pdf = if !body.is_empty() {
+        body.to_vec()
+    } else if let Some(pdf_id) = query.pdf_id {
+        let local_path =
+            std::path::Path::new(&state.config.signed_pdf_dir).join(format!("{}.pdf", pdf_id));
+        match tokio::fs::read(&local_path).await {
+            Ok(pdf) => pdf,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(actix_web::error::ErrorNotFound(format!(
+                    "signed PDF not found: {}",
+                    pdf_id
+                )));
+            }
+            Err(e) => {
+                tracing::error!("Failed to read signed PDF {}: {}", pdf_id, e);
+                return Err(actix_web::error::ErrorInternalServerError(format!(
+                    "failed to read signed PDF: {}",
+                    e
+                )));
+            }
+        }
+    } else {
+        return Err(actix_web::error::ErrorBadRequest(
+            "send the PDF as the request body or specify ?pdfId=",
+        ));
+    };
+
+    tracing::info!("Verify request - {} bytes", pdf.len());
+
+    let pdf_handler = state.pdf_handler.clone();
+    let verified = actix_web::web::block(move || pdf_handler.verify_pdf(&pdf))
+        .await?
+        .and_then(|pdf_id| match query.pdf_id {
+            Some(expected) if expected != pdf_id => {
+                anyhow::bail!("PDF ID {} does not match {}", pdf_id, expected)
+            }
+            _ => Ok(pdf_id),
+        });
+
+    let pdf_id = match verified {
+        Ok(pdf_id) => {
+            record_request_field("pdf_id", pdf_id);
+            pdf_id
+        }
+        Err(e) => {
+            tracing::warn!("Not a genuine copy: {:#}", e);
+            return Ok(actix_web::HttpResponse::Ok().json(VerifyResponse {
+                genuine: false,
+                pdf_id: None,
+                issued: None,
+                error: Some(format!("{:#}", e)),
+            }));
+        }
+    };
+
+    let issued = state.issued_pdfs.get(&pdf_id).await.map_err(|e| {
+        tracing::error!("Failed to look up issued PDF {}: {}", pdf_id, e);
+        actix_web::error::ErrorInternalServerError(format!("failed to look up PDF: {}", e))
+    })?;
+
+    match &issued {
+        Some(issued) => tracing::info!("Genuine copy {} (payment {})", pdf_id, issued.payment_id),
+        None => tracing::warn!("Genuine copy {} but no issue record", pdf_id),
+    }
+
+    Ok(actix_web::HttpResponse::Ok().json(VerifyResponse {
+        genuine: true,
+        pdf_id: Some(pdf_id.to_string()),
+        error: issued
+            .is_none()
+            .then(|| "no issue record for this PDF ID".to_string()),
+        issued,
+    }))
+}
+
+async fn print_tag(
+    state: reload::Current<AppState>,
+    req: actix_web::web::Json<PrintTagRequest>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    if let Some(tag) = &req.tag {
+        record_request_field("tag", tag);
+    }
+    tracing::info!(
+        "Print tag/receipt request - Tag: {}, isOrder: {}",
+        req.tag.as_deref().unwrap_or("(auto)"),
+        req.is_order
+    );
+
+    if req.tag.as_deref().is_some_and(|tag| tag.trim().is_empty()) {
+        return Err(actix_web::error::ErrorBadRequest("tag must not be empty"));
+    }
+    if !req.is_order && req.tag.is_none() {
+        return Err(actix_web::error::ErrorBadRequest(
+            "tag is required for order receipts",
+        ));
+    }
+
+    let kind = if req.is_order {
+        print_queue::JobKind::Tag
+    } else {
+        print_queue::JobKind::OrderReceipt
+    };
+    // キッチンの画面だけで注文を受ける設定ならタグは印刷しない
+    let print = !req.is_order || state.config.kitchen_tickets;
+    let printer = select_printer(&state, kind, req.printer.as_deref())?;
+    if print {
+        ensure_printer_ready(printer)?;
+    }
+
+    let lines = match state.catalog.resolve(&req.items) {
+        Ok(lines) => lines,
+        Err(e) => {
+            tracing::warn!("Invalid order items: {}", e);
+            return Ok(
+                actix_web::HttpResponse::BadRequest().json(PrintTagResponse {
+                    success: false,
+                    message: format!("Invalid order items: {}", e),
+                    tag: req.tag.clone(),
+                }),
+            );
+        }
+    };
+
+    for line in &lines {
+        tracing::debug!(
+            sku = %line.item.sku,
+            qty = line.qty,
+            "{} ({}): {} x {} yen",
+            line.item.name,
+            line.item.sku,
+            line.qty,
+            line.item.price
+        );
+    }
+
+    // クライアントが送ってきた合計は信用せず，単価表から計算し直す
+    let breakdown = match pricing::compute(&state.catalog, &lines) {
+        Ok(breakdown) => breakdown,
+        Err(e) => {
+            tracing::warn!("Invalid order items: {}", e);
+            return Ok(
+                actix_web::HttpResponse::BadRequest().json(PrintTagResponse {
+                    success: false,
+                    message: format!("Invalid order items: {}", e),
+                    tag: req.tag.clone(),
+                }),
+            );
+        }
+    };
+    tracing::info!(
+        total = breakdown.total,
+        "Total: {} (client: {:?})",
+        breakdown.total,
+        req.total
+    );
+
+    if let Some(total) = req.total
+        && total != breakdown.total
+    {
+        tracing::warn!(
+            "Total mismatch: client sent {}, expected {}",
+            total,
+            breakdown.total
+        );
+        return Ok(
+            actix_web::HttpResponse::UnprocessableEntity().json(TotalMismatchResponse {
+                success: false,
+                message: format!(
+                    "Total mismatch: client sent {} yen, expected {} yen",
+                    total, breakdown.total
+                ),
+                expected: breakdown,
+            }),
+        );
+    }
+
+    // タグは呼び出し番号を発行してから印刷する（受け渡し前の番号とは重複させない）
+    let call = if req.is_order {
+        let issued = state
+            .call_numbers
+            .issue(req.tag.as_deref())
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to issue call number: {}", e);
+                actix_web::error::ErrorInternalServerError(format!(
+                    "failed to issue call number: {}",
+                    e
+                ))
+            })?;
+        match issued {
+            call_numbers::Issue::Issued(call) => Some(call),
+            call_numbers::Issue::Duplicate(active) => {
+                tracing::warn!(
+                    "Call number {} is still {}",
+                    active.tag,
+                    active.status.as_str()
+                );
+                return Ok(actix_web::HttpResponse::Conflict().json(PrintTagResponse {
+                    success: false,
+                    message: format!(
+                        "Call number {} is still {}",
+                        active.tag,
+                        active.status.as_str()
+                    ),
+                    tag: Some(active.tag),
+                }));
+            }
+            call_numbers::Issue::Exhausted => {
+                return Err(actix_web::error::ErrorServiceUnavailable(
+                    "all call numbers are in use",
+                ));
+            }
+        }
+    } else {
+        None
+    };
+    let tag = match (&call, &req.tag) {
+        (Some(call), _) => call.tag.clone(),
+        (None, Some(tag)) => tag.clone(),
+        (None, None) => unreachable!("order receipts require a tag"),
+    };
+    record_request_field("tag", &tag);
+
+    let (queued, what) = if !print {
+        (Ok(None), "tag")
+    } else if req.is_order {
+        // タグを印刷（品目情報付き）
+        let queued = state
+            .receipt_printer
+            .print_tag_receipt(&tag, &lines, &printer.name)
+            .await;
+        (queued.map(Some), "tag")
+    } else {
+        // レシートを印刷（各品目の数量付き）
+        let queued = state
+            .receipt_printer
+            .print_order_receipt(&tag, &lines, &breakdown, &printer.name)
+            .await;
+        (queued.map(Some), "receipt")
+    };
+
+    // 印刷ジョブを登録できなかった注文も台帳には残すが，レジが再送するので集計には含めない
+    let job_id = queued.as_ref().ok().and_then(|job_id| job_id.as_deref());
+    let status = if queued.is_ok() {
+        ledger::SaleStatus::Completed
+    } else {
+        ledger::SaleStatus::Failed
+    };
+    match state
+        .ledger
+        .record_order(kind, &tag, &breakdown, job_id, status)
+        .await
+    {
+        Ok(sale_id) => {
+            // 台帳の集計と同じく，注文レシートは同じ注文の控えなので数えない
+            if kind == print_queue::JobKind::Tag && status == ledger::SaleStatus::Completed {
+                count_order(&state.metrics, &breakdown);
+            }
+            if let Some(call) = &call
+                && let Err(e) = state.call_numbers.attach_sale(call.id, &sale_id).await
+            {
+                tracing::warn!("Failed to link call number {} to sale: {}", tag, e);
+            }
+        }
+        Err(e) => tracing::warn!("Failed to record order {} in ledger: {}", tag, e),
+    }
+
+    match queued {
+        Ok(job_id) => Ok(actix_web::HttpResponse::Ok().json(PrintTagResponse {
+            success: true,
+            message: if job_id.is_none() {
+                format!("Order sent to kitchen: {}", tag)
+            } else if req.is_order {
+                format!("Tag print job queued: {}", tag)
+            } else {
+                format!("Receipt print job queued: {}", tag)
+            },
+            tag: Some(tag),
+        })),
+        Err(e) => {
+            tracing::warn!("Failed to print {}: {}", what, e);
+            // 印刷できなかった番号は再送で使えるように取り消す
+            if call.is_some()
+                && let Err(e) = state
+                    .call_numbers
+                    .advance(&tag, call_numbers::CallStatus::Cancelled)
+                    .await
+            {
+                tracing::warn!("Failed to cancel call number {}: {}", tag, e);
+            }
+            Ok(
+                actix_web::HttpResponse::InternalServerError().json(PrintTagResponse {
+                    success: false,
+                    message: format!("Failed to print {}: {}", what, e),
+                    tag: Some(tag),
+                }),
+            )
+        }
+    }
 }
 
-#[derive(serde::Deserialize)]
-struct PrintTagRequest {
-    tag: String,
-    #[serde(rename = "ffKetchup")]
-    ff_ketchup: u32,
-    #[serde(rename = "ffNoKetchup")]
-    ff_no_ketchup: u32,
-    book: u32,
-    #[serde(rename = "pdfBook")]
-    pdf_book: u32,
-    drink: u32,
-    total: u32,
-    #[serde(rename = "isOrder")]
-    is_order: bool,
+/// 受けた注文を品目ごとの数量と売上に数える
+fn count_order(metrics: &metrics::Metrics, breakdown: &pricing::PriceBreakdown) {
+    metrics.orders.inc();
+    metrics.revenue_yen.inc_by(u64::from(breakdown.total));
+    for line in &breakdown.lines {
+        metrics
+            .order_items
+            .with_label_values(&[line.sku.as_str()])
+            .inc_by(u64::from(line.qty));
+    }
 }
 
-#[derive(serde::Serialize)]
-struct PrintPdfResponse {
-    success: bool,
-    message: String,
-    #[serde(rename = "paymentId")]
-    payment_id: String,
-    pdfs: Vec<PdfInfo>,
+/// 日本時間の営業日（YYYY-MM-DD，省略すると今日）とその範囲`[from, until)`のUNIX時刻
+fn business_day(date: Option<&str>) -> actix_web::Result<(String, i64, i64)> {
+    let jst = chrono::Duration::hours(9);
+    let date = match date {
+        Some(s) => chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| {
+            actix_web::error::ErrorBadRequest(format!("invalid date (YYYY-MM-DD expected): {}", s))
+        })?,
+        None => (chrono::Utc::now() + jst).date_naive(),
+    };
+    let from = date.and_time(chrono::NaiveTime::MIN).and_utc() - jst;
+    let until = from + chrono::Duration::days(1);
+
+    Ok((
+        date.format("%Y-%m-%d").to_string(),
+        from.timestamp(),
+        until.timestamp(),
+    ))
 }
 
-#[derive(serde::Serialize)]
-struct PdfInfo {
-    id: String,
-    url: String,
+/// 売上台帳（日本時間の1日分）
+async fn list_sales(
+    state: reload::Current<AppState>,
+    query: actix_web::web::Query<ListSalesQuery>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    let (_, from, until) = business_day(query.date.as_deref())?;
+
+    let sales = state
+        .ledger
+        .list(from, until, query.limit.unwrap_or(1000))
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to list sales: {}", e);
+            actix_web::error::ErrorInternalServerError(format!("failed to list sales: {}", e))
+        })?;
+
+    Ok(actix_web::HttpResponse::Ok().json(sales))
 }
 
-#[derive(serde::Serialize)]
-struct PrintTagResponse {
-    success: bool,
-    message: String,
+/// 台帳を集計して売上レポートを作る（Zレポートは今を締めの日時とする）
+async fn build_report(
+    state: &AppState,
+    kind: reports::ReportKind,
+    date: &str,
+    from: i64,
+    until: i64,
+    closed_at: Option<i64>,
+) -> anyhow::Result<reports::DailyReport> {
+    let summary = state
+        .ledger
+        .summarize(from, until, state.catalog.pdf_sku())
+        .await?;
+    let now = db::now();
+
+    Ok(reports::DailyReport::new(
+        kind,
+        date,
+        now,
+        match kind {
+            reports::ReportKind::X => closed_at,
+            reports::ReportKind::Z => Some(now),
+        },
+        &state.catalog,
+        summary,
+    ))
 }
 
-async fn print_pdf(
-    state: actix_web::web::Data<AppState>,
-    req: actix_web::web::Json<PrintPdfRequest>,
+/// レシートを印刷せずにPNGで返す（`tag`，`order_receipt`，`pdf_receipt`，`report`）
+///
+/// `tag`と`order_receipt`は`/print/tag`と同じ`tag`と`items`を，`pdf_receipt`は任意の`paidAt`と`count`を受け取る．
+/// `report`はその日（`?date=`）のXレポートを描画する
+async fn preview_receipt(
+    state: reload::Current<AppState>,
+    path: actix_web::web::Path<String>,
+    query: actix_web::web::Query<PreviewQuery>,
+    body: actix_web::web::Bytes,
 ) -> actix_web::Result<actix_web::HttpResponse> {
-    println!(
-        "\nPrint PDF request - Payment ID: {}, Count: {}, Paid at: {}",
-        req.payment_id, req.count, req.paid_at
-    );
+    let kind = path.into_inner();
+    if !["tag", "order_receipt", "pdf_receipt", "report"].contains(&kind.as_str()) {
+        return Err(actix_web::error::ErrorNotFound(format!(
+            "unknown receipt kind: {}",
+            kind
+        )));
+    }
 
-    let base_pdf = state
-        .pdf_handler
-        .fetch_base_pdf(&state.config.base_pdf_path)
+    let report = if kind == "report" {
+        let (date, from, until) = business_day(query.date.as_deref())?;
+        let closed_at = state.ledger.closed_at(&date).await.ok().flatten();
+        let report = build_report(
+            &state,
+            reports::ReportKind::X,
+            &date,
+            from,
+            until,
+            closed_at,
+        )
         .await
         .map_err(|e| {
-            eprintln!("❌ Failed to fetch base PDF: {}", e);
-            actix_web::error::ErrorInternalServerError(format!("failed to fetch base PDF: {}", e))
+            tracing::error!("Failed to build report: {}", e);
+            actix_web::error::ErrorInternalServerError(format!("failed to build report: {}", e))
         })?;
+        Some(report)
+    } else {
+        None
+    };
 
-    let mut pdfs = Vec::new();
+    let app = state.clone();
+    // 外側のErrはリクエストの誤り，内側のErrは描画の失敗
+    let rendered = actix_web::web::block(move || -> Result<anyhow::Result<Vec<u8>>, String> {
+        let dot_width = app.config.printer_dot_width;
+        let invalid = |e: &dyn std::fmt::Display| format!("invalid preview request: {}", e);
 
-    for i in 0..req.count {
-        let pdf_id = uuid::Uuid::new_v4();
-        println!(
-            "\n[{}/{}] Processing PDF with ID: {}",
-            i + 1,
-            req.count,
-            pdf_id
-        );
+        let rendered = match kind.as_str() {
+            "tag" | "order_receipt" => {
+                let req: PreviewOrderRequest =
+                    serde_json::from_slice(&body).map_err(|e| invalid(&e))?;
+                let lines = app.catalog.resolve(&req.items).map_err(|e| invalid(&e))?;
+                if kind == "tag" {
+                    app.receipt_printer.preview_png(
+                        receipt_printer::Preview::Tag {
+                            tag: &req.tag,
+                            lines: &lines,
+                        },
+                        dot_width,
+                    )
+                } else {
+                    let breakdown =
+                        pricing::compute(&app.catalog, &lines).map_err(|e| invalid(&e))?;
+                    app.receipt_printer.preview_png(
+                        receipt_printer::Preview::OrderReceipt {
+                            tag: &req.tag,
+                            lines: &lines,
+                            breakdown: &breakdown,
+                        },
+                        dot_width,
+                    )
+                }
+            }
+            "pdf_receipt" => {
+                let req: PreviewPdfRequest = if body.is_empty() {
+                    PreviewPdfRequest::default()
+                } else {
+                    serde_json::from_slice(&body).map_err(|e| invalid(&e))?
+                };
+                let pdf_id = uuid::Uuid::new_v4().to_string();
+                app.receipt_printer.preview_png(
+                    receipt_printer::Preview::PdfReceipt {
+                        pdf_url: &pdf_url(&app, &pdf_id),
+                        pdf_id: &pdf_id,
+                        paid_at: req
+                            .paid_at
+                            .unwrap_or_else(|| chrono::Utc::now().timestamp() as u64),
+                        count: req.count.unwrap_or(1),
+                    },
+                    dot_width,
+                )
+            }
+            _ => app.receipt_printer.preview_png(
+                receipt_printer::Preview::Report(report.as_ref().expect("report is built above")),
+                dot_width,
+            ),
+        };
 
-        let signed_pdf = state
-            .pdf_handler
-            .sign_pdf(base_pdf.clone(), &pdf_id, &state.config.base_pdf_path)
-            .await
-            .map_err(|e| {
-                eprintln!("❌ Failed to sign PDF {}: {}", pdf_id, e);
-                actix_web::error::ErrorInternalServerError(format!(
-                    "failed to sign PDF {}: {}",
-                    pdf_id, e
-                ))
-            })?;
+        Ok(rendered)
+    })
+    .await?;
 
-        tokio::fs::create_dir_all("signed_pdf").await.map_err(|e| {
-            eprintln!("Failed to create signed_pdf directory: {}", e);
-            actix_web::error::ErrorInternalServerError(format!("failed to create directory: {}", e))
+    let png = rendered
+        .map_err(actix_web::error::ErrorBadRequest)?
+        .map_err(|e| {
+            tracing::error!("Failed to render preview: {:#}", e);
+            actix_web::error::ErrorInternalServerError(format!("failed to render preview: {:#}", e))
         })?;
 
-        let local_path = format!("signed_pdf/{}.pdf", pdf_id);
-        println!("Saving signed PDF locally: {}", local_path);
-        tokio::fs::write(&local_path, &signed_pdf)
-            .await
-            .map_err(|e| {
-                eprintln!("❌ Failed to save signed PDF locally: {}", e);
-                actix_web::error::ErrorInternalServerError(format!(
-                    "failed to save signed PDF: {}",
-                    e
-                ))
-            })?;
+    Ok(actix_web::HttpResponse::Ok()
+        .content_type("image/png")
+        .body(png))
+}
 
-        let object_key = format!("signed_pdfs/{}.pdf", pdf_id);
-        state
-            .r2_client
-            .upload_pdf(&object_key, signed_pdf)
-            .await
-            .map_err(|e| {
-                eprintln!("❌ Failed to upload PDF to R2: {}", e);
-                actix_web::error::ErrorInternalServerError(format!(
-                    "failed to upload PDF to R2: {}",
-                    e
-                ))
-            })?;
+/// 1営業日の売上を品目ごとに集計し，レシートに印刷してJSONかCSVで返す
+///
+/// Zレポート（既定）はその営業日を締める．Xレポートは締めずに途中経過を出す
+async fn daily_report(
+    state: reload::Current<AppState>,
+    query: actix_web::web::Query<DailyReportQuery>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    let kind = match query.kind.as_deref() {
+        Some(s) => reports::ReportKind::parse(s).ok_or_else(|| {
+            actix_web::error::ErrorBadRequest(format!("unknown report kind: {}", s))
+        })?,
+        None => reports::ReportKind::Z,
+    };
+    let csv = match query.format.as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(s) => {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "unknown format: {}",
+                s
+            )));
+        }
+    };
+    let (date, from, until) = business_day(query.date.as_deref())?;
+    let printer = select_printer(
+        &state,
+        print_queue::JobKind::Report,
+        query.printer.as_deref(),
+    )?;
 
-        let pdf_url = format!("{}/{}", state.config.r2_public_url, object_key);
-        println!("PDF {} uploaded: {}", i + 1, pdf_url);
+    tracing::info!("{} request - {}", kind.title(), date);
 
-        pdfs.push(PdfInfo {
-            id: pdf_id.to_string(),
-            url: pdf_url.clone(),
-        });
+    let ledger_error = |e: anyhow::Error| {
+        tracing::error!("Failed to build report: {}", e);
+        actix_web::error::ErrorInternalServerError(format!("failed to build report: {}", e))
+    };
 
-        // レシートを印刷
-        if let Err(e) = state
-            .receipt_printer
-            .print_pdf_receipt(
-                &pdf_url,
-                &pdf_id.to_string(),
-                &req.payment_id.to_string(),
-                req.paid_at,
-                req.count,
-            )
+    let closed_at = state.ledger.closed_at(&date).await.map_err(ledger_error)?;
+    if kind == reports::ReportKind::Z
+        && let Some(closed_at) = closed_at
+    {
+        return Err(actix_web::error::ErrorConflict(format!(
+            "{} was already closed at {}; use ?kind=x to print it again",
+            date, closed_at
+        )));
+    }
+
+    let report = build_report(&state, kind, &date, from, until, closed_at)
+        .await
+        .map_err(ledger_error)?;
+
+    if kind == reports::ReportKind::Z {
+        let json =
+            serde_json::to_string(&report).map_err(actix_web::error::ErrorInternalServerError)?;
+        if !state
+            .ledger
+            .close_day(&date, report.generated_at, &json)
             .await
+            .map_err(ledger_error)?
         {
-            eprintln!("⚠️ Failed to print receipt for PDF {}: {}", pdf_id, e);
-            // レシートの印刷失敗はエラーを返さず続行
+            return Err(actix_web::error::ErrorConflict(format!(
+                "{} was already closed",
+                date
+            )));
         }
+        tracing::info!(
+            "Closed {}: {} orders, {} yen",
+            date,
+            report.orders,
+            report.total
+        );
     }
 
-    println!("\n✓ {} QR code receipts printed", req.count);
+    if let Err(e) = state
+        .receipt_printer
+        .print_daily_report(&report, &printer.name)
+        .await
+    {
+        // 締めは記録済みなので，印刷できなくてもレポートは返す（Xレポートで印刷し直せる）
+        tracing::warn!("Failed to print {}: {}", kind.title(), e);
+    }
 
-    Ok(actix_web::HttpResponse::Ok().json(PrintPdfResponse {
-        success: true,
-        message: format!(
-            "{} PDFs signed and uploaded. {} receipt(s) to be printed",
-            req.count, req.count
-        ),
-        payment_id: req.payment_id.to_string(),
-        pdfs,
-    }))
+    if csv {
+        Ok(actix_web::HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"{}-{}.csv\"",
+                    kind.title().to_lowercase(),
+                    date
+                ),
+            ))
+            .body(report.to_csv()))
+    } else {
+        Ok(actix_web::HttpResponse::Ok().json(report))
+    }
 }
 
-async fn print_tag(
-    state: actix_web::web::Data<AppState>,
-    req: actix_web::web::Json<PrintTagRequest>,
+async fn list_calls(
+    state: reload::Current<AppState>,
+    query: actix_web::web::Query<ListCallsQuery>,
 ) -> actix_web::Result<actix_web::HttpResponse> {
-    println!(
-        "\nPrint tag/receipt request - Tag: {}, isOrder: {}",
-        req.tag, req.is_order
-    );
-    println!(
-        "  ffKetchup: {}, ffNoKetchup: {}, book: {}, pdfBook: {}, drink: {}, total: {}",
-        req.ff_ketchup, req.ff_no_ketchup, req.book, req.pdf_book, req.drink, req.total
-    );
+    let status = match query.status.as_deref() {
+        Some(s) => Some(call_numbers::CallStatus::parse(s).ok_or_else(|| {
+            actix_web::error::ErrorBadRequest(format!("unknown call status: {}", s))
+        })?),
+        None => None,
+    };
 
-    if req.is_order {
-        // タグを印刷（品目情報付き）
-        if let Err(e) = state
-            .receipt_printer
-            .print_tag_receipt(
-                &req.tag,
-                req.ff_ketchup,
-                req.ff_no_ketchup,
-                req.book,
-                req.pdf_book,
-                req.drink,
-            )
-            .await
-        {
-            eprintln!("⚠️ Failed to print tag: {}", e);
-            return Ok(
-                actix_web::HttpResponse::InternalServerError().json(PrintTagResponse {
-                    success: false,
-                    message: format!("Failed to print tag: {}", e),
-                }),
-            );
+    let calls = state.call_numbers.list(status).await.map_err(|e| {
+        tracing::error!("Failed to list call numbers: {}", e);
+        actix_web::error::ErrorInternalServerError(format!("failed to list call numbers: {}", e))
+    })?;
+
+    Ok(actix_web::HttpResponse::Ok().json(calls))
+}
+
+async fn get_call(
+    state: reload::Current<AppState>,
+    path: actix_web::web::Path<String>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    record_request_field("tag", &*path);
+    let call = state.call_numbers.get(&path).await.map_err(|e| {
+        tracing::error!("Failed to get call number {}: {}", path, e);
+        actix_web::error::ErrorInternalServerError(format!("failed to get call number: {}", e))
+    })?;
+
+    match call {
+        Some(call) => Ok(actix_web::HttpResponse::Ok().json(call)),
+        None => Err(actix_web::error::ErrorNotFound(format!(
+            "call number not found: {}",
+            path
+        ))),
+    }
+}
+
+/// 呼び出し番号の状態を進める（`ready`，`called`，`picked_up`，`cancelled`）
+async fn advance_call(
+    state: reload::Current<AppState>,
+    path: actix_web::web::Path<(String, String)>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    let (tag, status) = path.into_inner();
+    let status = match call_numbers::CallStatus::parse(&status) {
+        Some(call_numbers::CallStatus::Cooking) | None => {
+            return Err(actix_web::error::ErrorNotFound(format!(
+                "unknown call status: {}",
+                status
+            )));
         }
+        Some(status) => status,
+    };
 
-        Ok(actix_web::HttpResponse::Ok().json(PrintTagResponse {
-            success: true,
-            message: format!("Tag print job queued: {}", req.tag),
-        }))
-    } else {
-        // レシートを印刷（各品目の数量付き）
-        if let Err(e) = state
-            .receipt_printer
-            .print_order_receipt(
-                &req.tag,
-                req.ff_ketchup,
-                req.ff_no_ketchup,
-                req.book,
-                req.pdf_book,
-                req.drink,
-                req.total,
-            )
-            .await
-        {
-            eprintln!("⚠️ Failed to print receipt: {}", e);
-            return Ok(
-                actix_web::HttpResponse::InternalServerError().json(PrintTagResponse {
-                    success: false,
-                    message: format!("Failed to print receipt: {}", e),
-                }),
-            );
+    update_call(&state, &tag, status).await
+}
+
+async fn update_call(
+    state: &AppState,
+    tag: &str,
+    status: call_numbers::CallStatus,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    record_request_field("tag", tag);
+    let advanced = state.call_numbers.advance(tag, status).await.map_err(|e| {
+        tracing::error!("Failed to update call number {}: {}", tag, e);
+        actix_web::error::ErrorInternalServerError(format!("failed to update call number: {}", e))
+    })?;
+
+    match advanced {
+        call_numbers::Advance::Advanced(call) => {
+            tracing::info!("Call number {} is now {}", call.tag, call.status.as_str());
+            Ok(actix_web::HttpResponse::Ok().json(call))
         }
+        call_numbers::Advance::Unchanged(call) => Ok(actix_web::HttpResponse::Ok().json(call)),
+        call_numbers::Advance::Backward(call) => Err(actix_web::error::ErrorConflict(format!(
+            "call number {} is already {}",
+            call.tag,
+            call.status.as_str()
+        ))),
+        call_numbers::Advance::NotFound => Err(actix_web::error::ErrorNotFound(format!(
+            "call number not found: {}",
+            tag
+        ))),
+    }
+}
 
-        Ok(actix_web::HttpResponse::Ok().json(PrintTagResponse {
-            success: true,
-            message: format!("Receipt print job queued: {}", req.tag),
-        }))
+/// レシートのQRコードから署名済みPDFをダウンロードする
+///
+/// 発行したPDF IDで，回数と期限の制限内なら，ストレージの期限付きURLにリダイレクトする（発行できないストレージでは中身を直接返す）
+async fn download_pdf(
+    state: reload::Current<AppState>,
+    pdf_id: actix_web::web::Path<uuid::Uuid>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    record_request_field("pdf_id", *pdf_id);
+    let policy = issued_pdfs::DownloadPolicy {
+        max_count: state.config.download_max_count,
+        valid_secs: i64::from(state.config.download_valid_days) * 24 * 60 * 60,
+    };
+    let claim = state
+        .issued_pdfs
+        .claim_download(&pdf_id, policy)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to check download of PDF {}: {}", pdf_id, e);
+            actix_web::error::ErrorInternalServerError(format!("failed to check download: {}", e))
+        })?;
+
+    match claim {
+        issued_pdfs::DownloadClaim::Allowed => {}
+        issued_pdfs::DownloadClaim::NotIssued => {
+            return Err(actix_web::error::ErrorNotFound(format!(
+                "PDF not found: {}",
+                pdf_id
+            )));
+        }
+        issued_pdfs::DownloadClaim::Expired => {
+            return Err(actix_web::error::ErrorGone(format!(
+                "download period of PDF {} has expired",
+                pdf_id
+            )));
+        }
+        issued_pdfs::DownloadClaim::LimitReached => {
+            tracing::warn!("Download limit reached for PDF {}", pdf_id);
+            return Err(actix_web::error::ErrorGone(format!(
+                "PDF {} has been downloaded {} times already",
+                pdf_id, policy.max_count
+            )));
+        }
+    }
+
+    // 渡せなかったダウンロードは回数に数えない
+    let response = deliver_pdf(&state, &pdf_id).await;
+    if response.is_err()
+        && let Err(e) = state.issued_pdfs.release_download(&pdf_id).await
+    {
+        tracing::error!("Failed to release download of PDF {}: {}", pdf_id, e);
+    }
+    response
+}
+
+/// 署名済みPDFをストレージの期限付きURLか中身そのもので返す
+async fn deliver_pdf(
+    state: &AppState,
+    pdf_id: &uuid::Uuid,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    let object_key = pdf_object_key(&pdf_id.to_string());
+    let presigned_url = state
+        .blob_store
+        .presigned_url(
+            &object_key,
+            std::time::Duration::from_secs(state.config.download_url_ttl_secs),
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to presign download of PDF {}: {}", pdf_id, e);
+            actix_web::error::ErrorInternalServerError(format!(
+                "failed to create download URL: {}",
+                e
+            ))
+        })?;
+    if let Some(url) = presigned_url {
+        return Ok(actix_web::HttpResponse::Found()
+            .insert_header((actix_web::http::header::LOCATION, url))
+            .insert_header((actix_web::http::header::CACHE_CONTROL, "no-store"))
+            .finish());
+    }
+
+    let blob = state.blob_store.get(&object_key).await.map_err(|e| {
+        tracing::error!("Failed to read PDF {}: {}", pdf_id, e);
+        actix_web::error::ErrorInternalServerError(format!("failed to read PDF: {}", e))
+    })?;
+    match blob {
+        Some(blob) => Ok(actix_web::HttpResponse::Ok()
+            .content_type(blob.content_type)
+            .insert_header((actix_web::http::header::CACHE_CONTROL, "no-store"))
+            .body(blob.data)),
+        None => Err(actix_web::error::ErrorNotFound(format!(
+            "PDF not found in storage: {}",
+            pdf_id
+        ))),
+    }
+}
+
+async fn display() -> actix_web::HttpResponse {
+    actix_web::HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(DISPLAY_HTML)
+}
+
+/// 呼び出し番号の表示が変わるたびに`calls`イベントで最新の一覧を送る（Server-Sent Events）
+async fn call_events(state: reload::Current<AppState>) -> actix_web::HttpResponse {
+    let call_numbers = state.call_numbers.clone();
+    event_stream(state.call_numbers.subscribe(), "calls", move || {
+        let call_numbers = call_numbers.clone();
+        async move { call_numbers.board().await }
+    })
+}
+
+/// 呼び出し番号が変わるたびに`load`した内容を`event`として送るServer-Sent Eventsのレスポンス
+///
+/// 接続した直後にも現在の内容を送る
+fn event_stream<T, F, Fut>(
+    changes: tokio::sync::watch::Receiver<u64>,
+    event: &'static str,
+    load: F,
+) -> actix_web::HttpResponse
+where
+    T: serde::Serialize,
+    F: Fn() -> Fut + 'static,
+    Fut: std::future::Future<Output = anyhow::Result<T>>,
+{
+    let load = std::rc::Rc::new(load);
+    let stream = futures_util::stream::unfold((changes, true), move |(mut changes, first)| {
+        let load = load.clone();
+        async move {
+            if !first {
+                tokio::select! {
+                    changed = changes.changed() => changed.ok()?,
+                    _ = tokio::time::sleep(EVENTS_KEEPALIVE) => {
+                        let ping = actix_web::web::Bytes::from_static(b": keep-alive\n\n");
+                        return Some((Ok::<_, std::convert::Infallible>(ping), (changes, false)));
+                    }
+                }
+            }
+            changes.borrow_and_update();
+
+            let message = match load().await {
+                Ok(data) => format!(
+                    "event: {}\ndata: {}\n\n",
+                    event,
+                    serde_json::to_string(&data).unwrap_or_default()
+                ),
+                Err(e) => {
+                    tracing::warn!("Failed to load {} for event stream: {}", event, e);
+                    format!(": failed to load {}\n\n", event)
+                }
+            };
+            Some((Ok(actix_web::web::Bytes::from(message)), (changes, false)))
+        }
+    });
+
+    actix_web::HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream)
+}
+
+async fn load_kitchen_queue(state: &AppState) -> anyhow::Result<kitchen::KitchenQueue> {
+    let orders = state.call_numbers.cooking_orders().await?;
+    Ok(kitchen::KitchenQueue::new(
+        &state.catalog,
+        orders,
+        db::now(),
+    ))
+}
+
+async fn kitchen_queue(
+    state: reload::Current<AppState>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    let queue = load_kitchen_queue(&state).await.map_err(|e| {
+        tracing::error!("Failed to load kitchen queue: {}", e);
+        actix_web::error::ErrorInternalServerError(format!("failed to load kitchen queue: {}", e))
+    })?;
+
+    Ok(actix_web::HttpResponse::Ok().json(queue))
+}
+
+/// 調理を終えた注文を受け取り待ち（`ready`）にする
+async fn kitchen_done(
+    state: reload::Current<AppState>,
+    path: actix_web::web::Path<String>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    update_call(&state, &path, call_numbers::CallStatus::Ready).await
+}
+
+/// 調理待ちの注文が変わるたびに`kitchen`イベントで一覧を送る（Server-Sent Events）
+///
+/// 接続は長く続くので，送るたびにその時点の品目表を使う
+async fn kitchen_events(
+    live: actix_web::web::Data<reload::Live<AppState>>,
+) -> actix_web::HttpResponse {
+    let changes = live.current().call_numbers.subscribe();
+    event_stream(changes, "kitchen", move || {
+        let state = live.current();
+        async move { load_kitchen_queue(&state).await }
+    })
+}
+
+async fn list_jobs(
+    state: reload::Current<AppState>,
+    query: actix_web::web::Query<ListJobsQuery>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    let status = match query.status.as_deref() {
+        Some(s) => Some(print_queue::JobStatus::parse(s).ok_or_else(|| {
+            actix_web::error::ErrorBadRequest(format!("unknown job status: {}", s))
+        })?),
+        None => None,
+    };
+
+    let jobs = state
+        .print_queue
+        .list(status, query.printer.as_deref(), query.limit.unwrap_or(100))
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to list print jobs: {}", e);
+            actix_web::error::ErrorInternalServerError(format!("failed to list print jobs: {}", e))
+        })?;
+
+    Ok(actix_web::HttpResponse::Ok().json(jobs))
+}
+
+async fn get_job(
+    state: reload::Current<AppState>,
+    path: actix_web::web::Path<String>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    record_request_field("job_id", &*path);
+    let job = state.print_queue.get(&path).await.map_err(|e| {
+        tracing::error!("Failed to get print job {}: {}", path, e);
+        actix_web::error::ErrorInternalServerError(format!("failed to get print job: {}", e))
+    })?;
+
+    match job {
+        Some(job) => Ok(actix_web::HttpResponse::Ok().json(job)),
+        None => Err(actix_web::error::ErrorNotFound(format!(
+            "print job not found: {}",
+            path
+        ))),
+    }
+}
+
+async fn reprint_job(
+    state: reload::Current<AppState>,
+    path: actix_web::web::Path<String>,
+    query: actix_web::web::Query<PrinterQuery>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    record_request_field("job_id", &*path);
+    tracing::info!("Reprint request - Job ID: {}", path);
+
+    let job = state.print_queue.get(&path).await.map_err(|e| {
+        tracing::error!("Failed to get print job {}: {}", path, e);
+        actix_web::error::ErrorInternalServerError(format!("failed to get print job: {}", e))
+    })?;
+    let Some(job) = job else {
+        return Err(actix_web::error::ErrorNotFound(format!(
+            "print job not found: {}",
+            path
+        )));
+    };
+
+    // 指定がなければ元のプリンターに送り直す
+    let printer = select_printer(
+        &state,
+        job.kind,
+        Some(query.printer.as_deref().unwrap_or(&job.printer)),
+    )?;
+    ensure_printer_ready(printer)?;
+
+    let job = state
+        .print_queue
+        .reprint(&path, query.printer.as_deref())
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to reprint job {}: {}", path, e);
+            actix_web::error::ErrorInternalServerError(format!("failed to reprint job: {}", e))
+        })?;
+
+    match job {
+        Some(job) => Ok(actix_web::HttpResponse::Ok().json(job)),
+        None => Err(actix_web::error::ErrorNotFound(format!(
+            "print job not found: {}",
+            path
+        ))),
     }
 }
 
 async fn cut_paper(
-    state: actix_web::web::Data<AppState>,
+    state: reload::Current<AppState>,
+    query: actix_web::web::Query<PrinterQuery>,
 ) -> actix_web::Result<actix_web::HttpResponse> {
-    println!("\nCut paper request");
+    tracing::info!("Cut paper request");
+
+    let printer = state
+        .printers
+        .get(query.printer.as_deref())
+        .map_err(|e| actix_web::error::ErrorBadRequest(e.to_string()))?;
 
-    if let Err(e) = state.receipt_printer.cut_paper().await {
-        eprintln!("⚠️ Failed to cut paper: {}", e);
+    if let Err(e) = state.receipt_printer.cut_paper(printer).await {
+        tracing::warn!("Failed to cut paper: {}", e);
+        state.metrics.count_print_job("cut", "failed");
         return Ok(
             actix_web::HttpResponse::InternalServerError().json(serde_json::json!({
                 "success": false,
@@ -252,64 +1959,330 @@ async fn cut_paper(
         );
     }
 
+    state.metrics.count_print_job("cut", "done");
     Ok(actix_web::HttpResponse::Ok().json(serde_json::json!({
         "success": true,
         "message": "Paper cut command sent",
     })))
 }
 
-async fn health_check() -> actix_web::Result<actix_web::HttpResponse> {
+/// Prometheusのメトリクス（テキスト形式）
+async fn get_metrics(
+    state: reload::Current<AppState>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    let text = state.metrics.render().map_err(|e| {
+        tracing::error!("Failed to render metrics: {:#}", e);
+        actix_web::error::ErrorInternalServerError(format!("failed to render metrics: {:#}", e))
+    })?;
+
+    Ok(actix_web::HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(text))
+}
+
+/// 設定ファイルなどを読み直す（SIGHUPと同じ）
+async fn admin_reload(
+    reloader: actix_web::web::Data<Reloader>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    reloader.reload("POST /admin/reload").await.map_err(|e| {
+        actix_web::error::ErrorUnprocessableEntity(format!(
+            "reload failed; keeping the current configuration: {:#}",
+            e
+        ))
+    })?;
+
+    Ok(actix_web::HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Configuration reloaded",
+    })))
+}
+
+async fn health_check(
+    state: reload::Current<AppState>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    let printers = printer_statuses(&state);
+    let degraded = printers.values().any(|status| status.has_problem());
+
     Ok(actix_web::HttpResponse::Ok().json(serde_json::json!({
-        "status": "ok",
-        "service": "kawauso-print-service"
+        "status": if degraded { "degraded" } else { "ok" },
+        "service": "kawauso-print-service",
+        "printers": printers,
     })))
 }
 
+async fn get_printer_status(
+    state: reload::Current<AppState>,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    Ok(actix_web::HttpResponse::Ok().json(printer_statuses(&state)))
+}
+
+fn printer_statuses(
+    state: &AppState,
+) -> std::collections::BTreeMap<String, printer_status::PrinterStatus> {
+    state
+        .printers
+        .iter()
+        .map(|printer| (printer.name.clone(), printer.status.current()))
+        .collect()
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenvy::dotenv().ok();
 
-    let config = std::sync::Arc::new(config::Config::from_env().expect("failed to load env vars"));
+    let args = <config::Args as clap::Parser>::parse();
+    // ログの設定も読めていないので，設定の誤りは標準エラーに出して終了する
+    let config = match config::Config::load(&args) {
+        Ok(config) => std::sync::Arc::new(config),
+        Err(e) => {
+            eprintln!("Failed to load config: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    logging::init(&config.log_format, &config.log_level).expect("failed to configure logging");
+
+    let report = self_check::run(&config);
+    report.log();
+    if args.check {
+        std::process::exit(if report.failures() == 0 { 0 } else { 1 });
+    }
+    if report.failures() > 0 {
+        tracing::error!("Fix the problems above before starting kawauso");
+        std::process::exit(1);
+    }
 
-    let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
-    let s3_client = aws_sdk_s3::Client::new(&aws_config);
+    let catalog = std::sync::Arc::new(
+        catalog::Catalog::load(&config.catalog_path).expect("failed to load catalog"),
+    );
+    let templates = receipt_template::Templates::load(&config.templates_dir)
+        .expect("failed to load receipt templates");
 
-    let pdf_handler = std::sync::Arc::new(pdf_handler::PdfHandler::new());
-    let r2_client = std::sync::Arc::new(r2_client::R2Client::new(
-        s3_client,
-        config.r2_bucket_name.clone(),
-    ));
+    let pdf_handler = std::sync::Arc::new(
+        pdf_handler::PdfHandler::new(&config.cert_path, &config.key_path)
+            .expect("failed to load signing certificate"),
+    );
+    let blob_store = blob_store::from_config(&config.storage)
+        .await
+        .expect("failed to configure storage");
+    tracing::info!("Storage: {}", blob_store.name());
+
+    let text_renderer = config.font_path.as_ref().map(|path| {
+        text_renderer::TextRenderer::from_file(path, config.printer_dot_width)
+            .expect("failed to load font")
+    });
+
+    let db = std::sync::Arc::new(
+        db::Database::open(&config.database_path).expect("failed to open database"),
+    );
+    let metrics = std::sync::Arc::new(metrics::Metrics::new().expect("failed to set up metrics"));
+    let print_queue =
+        std::sync::Arc::new(print_queue::PrintQueue::new(db.clone(), metrics.clone()));
+    let issued_pdfs = std::sync::Arc::new(issued_pdfs::IssuedPdfs::new(db.clone()));
+    let ledger = std::sync::Arc::new(ledger::Ledger::new(db.clone()));
+    let call_numbers = std::sync::Arc::new(call_numbers::CallNumbers::new(db.clone()));
+    match issued_pdfs.recover_payments().await {
+        Ok(0) => {}
+        Ok(n) => tracing::info!(
+            "Marked {} interrupted PDF issue(s) as failed; they resume when the register retries",
+            n
+        ),
+        Err(e) => tracing::warn!("Failed to recover payments: {}", e),
+    }
+
+    let printers = std::sync::Arc::new(
+        printers::Printers::from_config(&config, None).expect("failed to configure printers"),
+    );
 
     let receipt_printer = std::sync::Arc::new(receipt_printer::ReceiptPrinter::new(
-        config.printer_name.clone(),
+        config.receipts_dir.clone().into(),
+        text_renderer,
+        templates,
+        print_queue.clone(),
     ));
 
     let app_state = AppState {
         config,
+        catalog,
         pdf_handler,
-        r2_client,
+        blob_store,
         receipt_printer,
+        print_queue,
+        issued_pdfs,
+        ledger,
+        call_numbers,
+        printers,
+        metrics,
+        authenticator: std::sync::Arc::new(auth::Authenticator::default()),
     };
 
-    let bind_address = "0.0.0.0:8080";
-    println!("Starting server at: http://{}", bind_address);
+    let bind_address = app_state.config.bind_address.clone();
+    let live = actix_web::web::Data::new(reload::Live::new(app_state));
+    let reloader = actix_web::web::Data::new(Reloader::new(args, live.clone()));
+    reloader.start_printers(None).await;
+    actix_rt::spawn(reload_on_signal(reloader.clone()));
+    actix_rt::spawn(reload_on_change(reloader.clone()));
+
+    tracing::info!("Starting server at: http://{}", bind_address);
 
     actix_web::HttpServer::new(move || {
+        // 設定を読み直したら許可するオリジンも変わるよう，リクエストのたびに今の設定を見る
+        let origins = live.clone();
         let cors = actix_cors::Cors::default()
-            .allow_any_origin()
+            .allowed_origin_fn(move |origin, _| {
+                auth::allows_origin(
+                    &origins.current().config.cors_allowed_origins,
+                    origin.as_bytes(),
+                )
+            })
             .allow_any_method()
             .allow_any_header()
             .max_age(3600);
 
         actix_web::App::new()
+            .wrap(actix_web::middleware::from_fn(authorize))
             .wrap(cors)
-            .app_data(actix_web::web::Data::new(app_state.clone()))
+            .wrap_fn(|req, srv| {
+                let span = request_span(&req);
+                let response = srv.call(req);
+                async move {
+                    let response = response.await?;
+                    let status = response.status();
+                    tracing::Span::current().record("status", status.as_u16());
+                    if status.is_server_error() {
+                        tracing::error!("Responded {}", status);
+                    } else if status.is_client_error() {
+                        tracing::warn!("Responded {}", status);
+                    } else {
+                        tracing::debug!("Responded {}", status);
+                    }
+                    Ok(response)
+                }
+                .instrument(span)
+            })
+            .app_data(live.clone())
+            .app_data(reloader.clone())
             .route("/health", actix_web::web::get().to(health_check))
             .route("/print/pdf", actix_web::web::post().to(print_pdf))
             .route("/print/tag", actix_web::web::post().to(print_tag))
+            .service(
+                actix_web::web::resource("/verify")
+                    .app_data(actix_web::web::PayloadConfig::new(MAX_VERIFY_PDF_BYTES))
+                    .route(actix_web::web::post().to(verify_pdf)),
+            )
+            .route(
+                "/printer/status",
+                actix_web::web::get().to(get_printer_status),
+            )
             .route("/cut", actix_web::web::post().to(cut_paper))
+            .route("/sales", actix_web::web::get().to(list_sales))
+            .route("/reports/daily", actix_web::web::post().to(daily_report))
+            .route(
+                "/preview/{kind}",
+                actix_web::web::post().to(preview_receipt),
+            )
+            .route("/download/{pdf_id}", actix_web::web::get().to(download_pdf))
+            .route("/display", actix_web::web::get().to(display))
+            .route("/events", actix_web::web::get().to(call_events))
+            .route("/kitchen", actix_web::web::get().to(kitchen_queue))
+            .route("/kitchen/events", actix_web::web::get().to(kitchen_events))
+            .route(
+                "/kitchen/{tag}/done",
+                actix_web::web::post().to(kitchen_done),
+            )
+            .route("/calls", actix_web::web::get().to(list_calls))
+            .route("/calls/{tag}", actix_web::web::get().to(get_call))
+            .route(
+                "/calls/{tag}/{status}",
+                actix_web::web::post().to(advance_call),
+            )
+            .route("/jobs", actix_web::web::get().to(list_jobs))
+            .route("/jobs/{id}", actix_web::web::get().to(get_job))
+            .route("/jobs/{id}/reprint", actix_web::web::post().to(reprint_job))
+            .route("/admin/reload", actix_web::web::post().to(admin_reload))
+            .route("/metrics", actix_web::web::get().to(get_metrics))
     })
     .bind(bind_address)?
     .run()
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    fn allows(method: actix_web::http::Method, pattern: &str, role: crate::auth::Role) -> bool {
+        super::route_access(&method, pattern).allows(role)
+    }
+
+    #[test]
+    fn route_access_is_public_only_for_customer_routes() {
+        for pattern in ["/health", "/download/{pdf_id}", "/display", "/events"] {
+            assert!(matches!(
+                super::route_access(&actix_web::http::Method::GET, pattern),
+                crate::auth::Access::Public
+            ));
+        }
+        assert!(!matches!(
+            super::route_access(&actix_web::http::Method::POST, "/events"),
+            crate::auth::Access::Public
+        ));
+    }
+
+    #[test]
+    fn route_access_follows_roles() {
+        assert!(allows(
+            actix_web::http::Method::POST,
+            "/print/tag",
+            crate::auth::Role::Register
+        ));
+        assert!(!allows(
+            actix_web::http::Method::POST,
+            "/print/pdf",
+            crate::auth::Role::Kitchen
+        ));
+        assert!(allows(
+            actix_web::http::Method::POST,
+            "/preview/{kind}",
+            crate::auth::Role::Register
+        ));
+        assert!(!allows(
+            actix_web::http::Method::POST,
+            "/preview/{kind}",
+            crate::auth::Role::Kitchen
+        ));
+        assert!(allows(
+            actix_web::http::Method::GET,
+            "/kitchen/events",
+            crate::auth::Role::Kitchen
+        ));
+        assert!(!allows(
+            actix_web::http::Method::GET,
+            "/kitchen",
+            crate::auth::Role::Register
+        ));
+        assert!(allows(
+            actix_web::http::Method::POST,
+            "/calls/{tag}/{status}",
+            crate::auth::Role::Kitchen
+        ));
+        assert!(allows(
+            actix_web::http::Method::POST,
+            "/jobs/{id}/reprint",
+            crate::auth::Role::Register
+        ));
+
+        // 表にないエンドポイントは`admin`だけ
+        for (method, pattern) in [
+            (actix_web::http::Method::GET, "/sales"),
+            (actix_web::http::Method::POST, "/reports/daily"),
+            (actix_web::http::Method::POST, "/admin/reload"),
+            (actix_web::http::Method::GET, "/metrics"),
+        ] {
+            assert!(!allows(
+                method.clone(),
+                pattern,
+                crate::auth::Role::Register
+            ));
+            assert!(!allows(method.clone(), pattern, crate::auth::Role::Kitchen));
+            assert!(allows(method, pattern, crate::auth::Role::Admin));
+        }
+    }
+}
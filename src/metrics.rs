@@ -0,0 +1,116 @@
+use anyhow::Context as _;
+
+/// `/metrics`で公開するPrometheusのメトリクス
+pub struct Metrics {
+    registry: prometheus::Registry,
+    /// 署名したPDFの数
+    pub pdfs_signed: prometheus::IntCounter,
+    /// PDF 1つの署名（スタンプと検証を含む）にかかった秒数
+    pub pdf_sign_seconds: prometheus::Histogram,
+    /// ストレージにアップロードしたPDFの数
+    pub pdfs_uploaded: prometheus::IntCounter,
+    /// PDF 1つのアップロードにかかった秒数（失敗を含む）
+    pub pdf_upload_seconds: prometheus::Histogram,
+    /// アップロードに失敗した回数
+    pub pdf_upload_failures: prometheus::IntCounter,
+    /// 印刷ジョブの数（`kind`，`outcome`は`queued`，`done`，`retry`，`failed`）
+    pub print_jobs: prometheus::IntCounterVec,
+    /// プリンターへの送信の失敗（`printer`，`transport`）
+    pub printer_send_failures: prometheus::IntCounterVec,
+    /// 受けた注文（呼び出し番号タグ）の数
+    pub orders: prometheus::IntCounter,
+    /// 品目ごとの注文数量（`sku`）
+    pub order_items: prometheus::IntCounterVec,
+    /// 注文の売上（円，割引後）
+    pub revenue_yen: prometheus::IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> anyhow::Result<Self> {
+        let registry = prometheus::Registry::new_custom(Some("kawauso".to_string()), None)
+            .context("failed to create metrics registry")?;
+        let latency_buckets = prometheus::exponential_buckets(0.01, 2.0, 12)?;
+
+        let metrics = Self {
+            pdfs_signed: prometheus::IntCounter::new("pdfs_signed_total", "PDFs signed")?,
+            pdf_sign_seconds: prometheus::Histogram::with_opts(
+                prometheus::HistogramOpts::new(
+                    "pdf_sign_seconds",
+                    "Time to stamp, sign and verify one PDF",
+                )
+                .buckets(latency_buckets.clone()),
+            )?,
+            pdfs_uploaded: prometheus::IntCounter::new(
+                "pdfs_uploaded_total",
+                "PDFs uploaded to storage",
+            )?,
+            pdf_upload_seconds: prometheus::Histogram::with_opts(
+                prometheus::HistogramOpts::new(
+                    "pdf_upload_seconds",
+                    "Time to upload one PDF to storage",
+                )
+                .buckets(latency_buckets),
+            )?,
+            pdf_upload_failures: prometheus::IntCounter::new(
+                "pdf_upload_failures_total",
+                "PDF uploads that failed",
+            )?,
+            print_jobs: prometheus::IntCounterVec::new(
+                prometheus::Opts::new("print_jobs_total", "Print jobs by kind and outcome"),
+                &["kind", "outcome"],
+            )?,
+            printer_send_failures: prometheus::IntCounterVec::new(
+                prometheus::Opts::new(
+                    "printer_send_failures_total",
+                    "Failures to send data to a printer",
+                ),
+                &["printer", "transport"],
+            )?,
+            orders: prometheus::IntCounter::new("orders_total", "Orders taken")?,
+            order_items: prometheus::IntCounterVec::new(
+                prometheus::Opts::new("order_items_total", "Quantity ordered by item"),
+                &["sku"],
+            )?,
+            revenue_yen: prometheus::IntCounter::new(
+                "revenue_yen_total",
+                "Order revenue in yen after discounts",
+            )?,
+            registry,
+        };
+
+        let collectors: [Box<dyn prometheus::core::Collector>; 10] = [
+            Box::new(metrics.pdfs_signed.clone()),
+            Box::new(metrics.pdf_sign_seconds.clone()),
+            Box::new(metrics.pdfs_uploaded.clone()),
+            Box::new(metrics.pdf_upload_seconds.clone()),
+            Box::new(metrics.pdf_upload_failures.clone()),
+            Box::new(metrics.print_jobs.clone()),
+            Box::new(metrics.printer_send_failures.clone()),
+            Box::new(metrics.orders.clone()),
+            Box::new(metrics.order_items.clone()),
+            Box::new(metrics.revenue_yen.clone()),
+        ];
+        for collector in collectors {
+            metrics
+                .registry
+                .register(collector)
+                .context("failed to register metric")?;
+        }
+
+        Ok(metrics)
+    }
+
+    /// 印刷ジョブの結果を数える
+    pub fn count_print_job(&self, kind: &str, outcome: &str) {
+        self.print_jobs.with_label_values(&[kind, outcome]).inc();
+    }
+
+    /// Prometheusのテキスト形式
+    pub fn render(&self) -> anyhow::Result<String> {
+        let mut text = String::new();
+        prometheus::TextEncoder::new()
+            .encode_utf8(&self.registry.gather(), &mut text)
+            .context("failed to encode metrics")?;
+        Ok(text)
+    }
+}
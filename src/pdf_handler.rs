@@ -1,10 +1,39 @@
 use anyhow::Context as _;
 
-pub struct PdfHandler;
+/// 署名フィールドの名前
+const SIGNATURE_FIELD: &str = "KyogakuDendoSignature";
+/// 署名のreasonの書式（この後ろにPDF IDが続く）
+const REASON_PREFIX: &str = "Digital signature - ID: ";
+/// `/Contents`に確保するCMS署名の領域（バイト）
+const SIGNATURE_CAPACITY: usize = 8192;
+/// 保存後に書き換える`/ByteRange`の仮の値（実際の値が収まる桁数を確保しておく）
+const BYTE_RANGE_PLACEHOLDER: i64 = 9_999_999_999;
+
+pub struct PdfHandler {
+    certificate: x509_certificate::CapturedX509Certificate,
+    signing_key: x509_certificate::InMemorySigningKeyPair,
+}
 
 impl PdfHandler {
-    pub fn new() -> Self {
-        Self
+    /// 署名に使う証明書（PEM）と秘密鍵（PKCS#8のPEM）を読み込む
+    pub fn new(cert_path: &str, key_path: &str) -> anyhow::Result<Self> {
+        let cert_pem = std::fs::read(cert_path)
+            .context(format!("failed to read certificate: {}", cert_path))?;
+        let certificate = x509_certificate::CapturedX509Certificate::from_pem(cert_pem)
+            .context(format!("failed to parse certificate: {}", cert_path))?;
+
+        let key_pem =
+            std::fs::read(key_path).context(format!("failed to read private key: {}", key_path))?;
+        let signing_key = x509_certificate::InMemorySigningKeyPair::from_pkcs8_pem(&key_pem)
+            .context(format!(
+                "failed to parse private key (PKCS#8 PEM expected): {}",
+                key_path
+            ))?;
+
+        Ok(Self {
+            certificate,
+            signing_key,
+        })
     }
 
     pub async fn fetch_base_pdf(&self, path: &str) -> anyhow::Result<Vec<u8>> {
@@ -15,152 +44,539 @@ impl PdfHandler {
         Ok(bytes)
     }
 
-    fn add_signature_field_to_pdf(&self, pdf_data: &[u8]) -> anyhow::Result<Vec<u8>> {
-        let input_temp_path = format!("/tmp/input_pdf_{}.pdf", uuid::Uuid::new_v4());
-        let output_temp_path = format!("/tmp/output_pdf_{}.pdf", uuid::Uuid::new_v4());
+    /// 各ページに購入者ごとのスタンプを入れ，取引ごとのUUIDをreasonに記録してCMS（adbe.pkcs7.detached）署名を付ける
+    ///
+    /// 元のPDFは書き換えず，スタンプ・署名フィールド・署名を増分更新として末尾に追記する．
+    /// 解析・署名・検証でCPUを使うので，非同期の文脈からはブロッキングスレッドで呼ぶこと
+    pub fn sign_pdf(
+        &self,
+        pdf_data: Vec<u8>,
+        stamp: &crate::pdf_stamp::CopyStamp<'_>,
+        base_pdf_path: &str,
+    ) -> anyhow::Result<Vec<u8>> {
+        let pdf_name = std::path::Path::new(base_pdf_path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("base.pdf");
+        tracing::debug!("Signing PDF: {}", pdf_name);
+
+        let pdf_doc = pdf_signing::PDFSigningDocument::read_from(&*pdf_data, pdf_name.to_string())
+            .map_err(|e| anyhow::anyhow!("Failed to read PDF: {:?}", e))?;
+        let mut doc = pdf_doc.get_incr_document_ref().clone();
+
+        tracing::info_span!("stamp")
+            .in_scope(|| stamp.apply(&mut doc))
+            .context("Failed to stamp PDF")?;
+
+        let signature_id = doc.new_document.add_object(signature_dictionary(&format!(
+            "{}{}",
+            REASON_PREFIX, stamp.pdf_id
+        )));
 
-        std::fs::write(&input_temp_path, pdf_data).context("Failed to write input temp PDF")?;
+        // 署名フィールドがない場合は追加
+        tracing::info_span!("add_field").in_scope(|| -> anyhow::Result<()> {
+            match find_empty_signature_field(doc.get_prev_documents()) {
+                Some(field_id) => {
+                    tracing::debug!("Signature field already exists");
+                    doc.opt_clone_object_to_new_document(field_id)?;
+                    doc.new_document
+                        .get_object_mut(field_id)?
+                        .as_dict_mut()?
+                        .set("V", signature_id);
+                }
+                None => {
+                    tracing::debug!("Adding signature field");
+                    add_signature_field(&mut doc, signature_id)?;
+                }
+            }
+            Ok(())
+        })?;
 
-        let result = std::process::Command::new("python3")
-            .arg("add_sigfield_pikepdf.py")
-            .arg(&input_temp_path)
-            .arg(&output_temp_path)
-            .output()
-            .context("Failed to run pikepdf script")?;
+        let signed_pdf = tracing::info_span!("sign").in_scope(|| {
+            let mut unsigned = Vec::new();
+            doc.save_to(&mut unsigned)
+                .context("Failed to write PDF with signature field")?;
+            self.fill_signature(unsigned)
+        })?;
 
-        if !result.status.success() {
-            let stderr = String::from_utf8_lossy(&result.stderr);
-            let stdout = String::from_utf8_lossy(&result.stdout);
-            eprintln!("pikepdf script failed:");
-            eprintln!("stdout: {}", stdout);
-            eprintln!("stderr: {}", stderr);
+        tracing::info_span!("verify").in_scope(|| self.verify_pdf(&signed_pdf))?;
+        tracing::debug!("Signature is valid");
+
+        Ok(signed_pdf)
+    }
 
-            let _ = std::fs::remove_file(&input_temp_path);
-            let _ = std::fs::remove_file(&output_temp_path);
+    /// 本アプリの証明書で署名され，署名後に改変されていないことを確かめて，reasonに記録したPDF IDを返す
+    ///
+    /// 署名の後に増分更新が追記されたPDFも改変とみなす
+    pub fn verify_pdf(&self, pdf: &[u8]) -> anyhow::Result<uuid::Uuid> {
+        let doc =
+            pdf_signing::lopdf::Document::load_mem(pdf).context("Failed to parse signed PDF")?;
+
+        let signature = find_signature(&doc).context("signature not found")?;
+
+        let byte_range = signature
+            .get(b"ByteRange")?
+            .as_array()?
+            .iter()
+            .map(|n| Ok(usize::try_from(n.as_i64()?)?))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let [start1, len1, start2, len2] = byte_range[..] else {
+            anyhow::bail!("invalid ByteRange: {:?}", byte_range);
+        };
+        if start1 != 0 || start2.checked_add(len2) != Some(pdf.len()) || start2 < len1 {
+            anyhow::bail!("ByteRange does not cover the whole file: {:?}", byte_range);
+        }
 
-            return Err(anyhow::anyhow!("Failed to add signature field: {}", stderr));
+        // 署名していない隙間は，検証する署名の`/Contents`の16進文字列そのものでなければならない
+        let contents = signature.get(b"Contents")?.as_str()?;
+        let gap = pdf[len1..start2]
+            .strip_prefix(b"<")
+            .and_then(|gap| gap.strip_suffix(b">"))
+            .context("ByteRange gap is not a hex string")?;
+        if hex::decode(gap).ok().as_deref() != Some(contents) {
+            anyhow::bail!("ByteRange gap is not the signature contents");
+        }
+        let der = &contents[..der_length(contents)?];
+        let signed_data = cryptographic_message_syntax::SignedData::parse_ber(der)
+            .context("Failed to parse CMS signature")?;
+
+        let mut signed_content = Vec::with_capacity(len1 + len2);
+        signed_content.extend_from_slice(&pdf[start1..start1 + len1]);
+        signed_content.extend_from_slice(&pdf[start2..start2 + len2]);
+
+        let mut signers = 0;
+        for signer in signed_data.signers() {
+            signer
+                .verify_message_digest_with_content(&signed_content)
+                .context("PDF content does not match the signature")?;
+            // 埋め込まれた証明書ではなく，手元の証明書の公開鍵で検証する
+            let verifier = signer
+                .signature_verifier(std::iter::once(&self.certificate))
+                .context("not signed with our certificate")?;
+            verifier
+                .verify(
+                    &signer.signed_content_with_signed_data(&signed_data),
+                    signer.signature(),
+                )
+                .map_err(|_| anyhow::anyhow!("invalid signature"))?;
+            signers += 1;
+        }
+        if signers == 0 {
+            anyhow::bail!("signature has no signer");
         }
 
-        let stdout = String::from_utf8_lossy(&result.stdout);
-        println!("   {}", stdout.trim());
+        let reason = signature
+            .get(b"Reason")
+            .and_then(|r| r.as_str())
+            .context("signature has no reason")?;
+        let pdf_id = std::str::from_utf8(reason)
+            .ok()
+            .and_then(|r| r.strip_prefix(REASON_PREFIX))
+            .and_then(|id| uuid::Uuid::parse_str(id).ok())
+            .context(format!(
+                "PDF ID not found in reason: {}",
+                String::from_utf8_lossy(reason)
+            ))?;
+
+        Ok(pdf_id)
+    }
 
-        let output = std::fs::read(&output_temp_path).context("Failed to read output PDF")?;
-        println!("   Read output PDF: {} bytes", output.len());
+    /// 仮の`/ByteRange`を実際の値に書き換え，`/Contents`以外の全体に対する署名を埋め込む
+    fn fill_signature(&self, mut pdf: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let contents_placeholder = format!("/Contents<{}>", "0".repeat(SIGNATURE_CAPACITY * 2));
+        let contents_start = rfind(&pdf, contents_placeholder.as_bytes())
+            .context("signature placeholder not found")?
+            + "/Contents".len();
+        let contents_end = contents_start + SIGNATURE_CAPACITY * 2 + 2;
+
+        let byte_range_placeholder = format!("/ByteRange[0 {0} {0} {0}]", BYTE_RANGE_PLACEHOLDER);
+        let byte_range_start = rfind(&pdf, byte_range_placeholder.as_bytes())
+            .context("byte range placeholder not found")?;
+        let byte_range = format!(
+            "/ByteRange[0 {} {} {}",
+            contents_start,
+            contents_end,
+            pdf.len() - contents_end
+        );
+        // 長さを変えないように空白で埋める
+        let byte_range = format!(
+            "{:<width$}]",
+            byte_range,
+            width = byte_range_placeholder.len() - 1
+        );
+        pdf[byte_range_start..byte_range_start + byte_range.len()]
+            .copy_from_slice(byte_range.as_bytes());
+
+        let mut signed_content = Vec::with_capacity(pdf.len() - (contents_end - contents_start));
+        signed_content.extend_from_slice(&pdf[..contents_start]);
+        signed_content.extend_from_slice(&pdf[contents_end..]);
+
+        let signature = cryptographic_message_syntax::SignedDataBuilder::default()
+            .content_external(signed_content)
+            .content_type(cryptographic_message_syntax::Oid(
+                cryptographic_message_syntax::Bytes::copy_from_slice(
+                    cryptographic_message_syntax::asn1::rfc5652::OID_ID_DATA.as_ref(),
+                ),
+            ))
+            .signer(cryptographic_message_syntax::SignerBuilder::new(
+                &self.signing_key,
+                self.certificate.clone(),
+            ))
+            .build_der()
+            .context("Failed to build CMS signature")?;
+
+        if signature.len() > SIGNATURE_CAPACITY {
+            anyhow::bail!(
+                "signature is too large: {} bytes (capacity {})",
+                signature.len(),
+                SIGNATURE_CAPACITY
+            );
+        }
 
-        let _ = std::fs::remove_file(&input_temp_path);
-        let _ = std::fs::remove_file(&output_temp_path);
+        let hex: String = signature.iter().map(|b| format!("{:02x}", b)).collect();
+        pdf[contents_start + 1..contents_start + 1 + hex.len()].copy_from_slice(hex.as_bytes());
 
-        Ok(output)
+        Ok(pdf)
     }
+}
 
-    fn has_signature_fields(&self, pdf_data: &[u8], pdf_name: &str) -> anyhow::Result<bool> {
-        let pdf_doc = pdf_signing::PDFSigningDocument::read_from(pdf_data, pdf_name.to_string())
-            .map_err(|_| anyhow::anyhow!("Failed to read PDF"))?;
+/// 署名辞書（`/ByteRange`と`/Contents`は保存後に埋める）
+fn signature_dictionary(reason: &str) -> pdf_signing::lopdf::Dictionary {
+    use pdf_signing::lopdf::Object;
+
+    pdf_signing::lopdf::Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Sig".to_vec())),
+        ("Filter", Object::Name(b"Adobe.PPKLite".to_vec())),
+        ("SubFilter", Object::Name(b"adbe.pkcs7.detached".to_vec())),
+        (
+            "ByteRange",
+            Object::Array(vec![
+                Object::Integer(0),
+                Object::Integer(BYTE_RANGE_PLACEHOLDER),
+                Object::Integer(BYTE_RANGE_PLACEHOLDER),
+                Object::Integer(BYTE_RANGE_PLACEHOLDER),
+            ]),
+        ),
+        (
+            "Contents",
+            Object::String(
+                vec![0u8; SIGNATURE_CAPACITY],
+                pdf_signing::lopdf::StringFormat::Hexadecimal,
+            ),
+        ),
+        (
+            "M",
+            Object::string_literal(
+                chrono::Utc::now()
+                    .format("D:%Y%m%d%H%M%S+00'00'")
+                    .to_string(),
+            ),
+        ),
+        ("Reason", Object::string_literal(reason)),
+    ])
+}
 
-        let doc = pdf_doc.get_prev_document_ref();
+/// まだ署名されていない署名フィールドを探す
+fn find_empty_signature_field(
+    doc: &pdf_signing::lopdf::Document,
+) -> Option<pdf_signing::lopdf::ObjectId> {
+    let acro_form = doc.catalog().ok()?.get(b"AcroForm").ok()?;
+    let (_, acro_form) = doc.dereference(acro_form).ok()?;
+    let fields = acro_form.as_dict().ok()?.get(b"Fields").ok()?;
+    let (_, fields) = doc.dereference(fields).ok()?;
+
+    fields.as_array().ok()?.iter().find_map(|field| {
+        let field_id = field.as_reference().ok()?;
+        let field = doc.get_dictionary(field_id).ok()?;
+        let is_signature = field
+            .get(b"FT")
+            .and_then(|ft| ft.as_name())
+            .is_ok_and(|ft| ft == b"Sig");
+
+        (is_signature && !field.has(b"V")).then_some(field_id)
+    })
+}
 
-        if let Ok(catalog) = doc.catalog()
-            && let Ok(acro_form) = catalog.get(b"AcroForm")
-            && let Ok(acro_form_dict) = acro_form.as_dict()
-            && let Ok(fields) = acro_form_dict.get(b"Fields")
-            && let Ok(fields_array) = fields.as_array()
-        {
-            // 署名フィールドが存在するか確認
-            return Ok(!fields_array.is_empty());
+/// 1ページ目に見えない署名フィールドを追加し，AcroFormに登録する
+fn add_signature_field(
+    doc: &mut pdf_signing::lopdf::IncrementalDocument,
+    signature_id: pdf_signing::lopdf::ObjectId,
+) -> anyhow::Result<()> {
+    use pdf_signing::lopdf::Object;
+
+    let prev = doc.get_prev_documents();
+    let root_id = prev.trailer.get(b"Root")?.as_reference()?;
+    let page_id = *prev
+        .get_pages()
+        .values()
+        .next()
+        .context("PDF has no pages")?;
+
+    let field_id = doc.new_document.new_object_id();
+    let widget_id = doc
+        .new_document
+        .add_object(pdf_signing::lopdf::Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Annot".to_vec())),
+            ("Subtype", Object::Name(b"Widget".to_vec())),
+            (
+                "Rect",
+                Object::Array(vec![0.into(), 0.into(), 0.into(), 0.into()]),
+            ),
+            ("P", Object::Reference(page_id)),
+            ("Parent", Object::Reference(field_id)),
+            // Print + Locked
+            ("F", Object::Integer(132)),
+        ]));
+    doc.new_document.set_object(
+        field_id,
+        pdf_signing::lopdf::Dictionary::from_iter(vec![
+            ("FT", Object::Name(b"Sig".to_vec())),
+            ("T", Object::string_literal(SIGNATURE_FIELD)),
+            ("Kids", Object::Array(vec![Object::Reference(widget_id)])),
+            ("V", Object::Reference(signature_id)),
+        ]),
+    );
+
+    doc.opt_clone_object_to_new_document(page_id)?;
+    push_to_array(doc, page_id, b"Annots", Object::Reference(widget_id))?;
+
+    // AcroFormがカタログに直接書かれている場合は独立したオブジェクトに移す
+    doc.opt_clone_object_to_new_document(root_id)?;
+    let acro_form = doc
+        .new_document
+        .get_dictionary(root_id)?
+        .get(b"AcroForm")
+        .ok()
+        .cloned();
+    let acro_form_id = match acro_form {
+        Some(Object::Reference(id)) => {
+            doc.opt_clone_object_to_new_document(id)?;
+            id
         }
+        Some(Object::Dictionary(dict)) => doc.new_document.add_object(dict),
+        _ => doc
+            .new_document
+            .add_object(pdf_signing::lopdf::Dictionary::new()),
+    };
+    doc.new_document
+        .get_dictionary_mut(root_id)?
+        .set("AcroForm", acro_form_id);
+
+    push_to_array(doc, acro_form_id, b"Fields", Object::Reference(field_id))?;
+    // SignaturesExist + AppendOnly
+    doc.new_document
+        .get_dictionary_mut(acro_form_id)?
+        .set("SigFlags", 3);
+
+    Ok(())
+}
 
-        Ok(false)
+/// 辞書の配列（参照の場合は参照先の配列）に要素を追加する
+fn push_to_array(
+    doc: &mut pdf_signing::lopdf::IncrementalDocument,
+    owner_id: pdf_signing::lopdf::ObjectId,
+    key: &[u8],
+    value: pdf_signing::lopdf::Object,
+) -> anyhow::Result<()> {
+    use pdf_signing::lopdf::Object;
+
+    let existing = doc
+        .new_document
+        .get_dictionary(owner_id)?
+        .get(key)
+        .ok()
+        .cloned();
+
+    match existing {
+        Some(Object::Reference(array_id)) => {
+            doc.opt_clone_object_to_new_document(array_id)?;
+            doc.new_document
+                .get_object_mut(array_id)?
+                .as_array_mut()?
+                .push(value);
+        }
+        Some(Object::Array(mut items)) => {
+            items.push(value);
+            doc.new_document
+                .get_dictionary_mut(owner_id)?
+                .set(key, items);
+        }
+        _ => {
+            doc.new_document
+                .get_dictionary_mut(owner_id)?
+                .set(key, vec![value]);
+        }
     }
 
-    pub async fn sign_pdf(
-        &self,
-        pdf_data: Vec<u8>,
-        pdf_id: &uuid::Uuid,
-        base_pdf_path: &str,
-    ) -> anyhow::Result<Vec<u8>> {
-        let pdf_name = std::path::Path::new(base_pdf_path)
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("base.pdf");
-        println!("🖋  Signing PDF: {}", pdf_name);
+    Ok(())
+}
 
-        // 署名フィールドがない場合は追加
-        let has_sig = self.has_signature_fields(&pdf_data, pdf_name)?;
-        println!("Signature field exists: {}", has_sig);
-
-        let pdf_with_field = if !has_sig {
-            println!("📝 Adding signature field...");
-            self.add_signature_field_to_pdf(&pdf_data)?
-        } else {
-            println!("Signature field already exists");
-            pdf_data
-        };
+/// 最後に付けられた署名（`/ByteRange`が最も後ろまで届くもの）の署名辞書
+fn find_signature(doc: &pdf_signing::lopdf::Document) -> Option<&pdf_signing::lopdf::Dictionary> {
+    let acro_form = doc.catalog().ok()?.get(b"AcroForm").ok()?;
+    let (_, acro_form) = doc.dereference(acro_form).ok()?;
+    let fields = acro_form.as_dict().ok()?.get(b"Fields").ok()?;
+    let (_, fields) = doc.dereference(fields).ok()?;
+
+    fields
+        .as_array()
+        .ok()?
+        .iter()
+        .filter_map(|field| {
+            let (_, field) = doc.dereference(field).ok()?;
+            let (_, signature) = doc
+                .dereference(field.as_dict().ok()?.get(b"V").ok()?)
+                .ok()?;
+            let signature = signature.as_dict().ok()?;
+            let byte_range = signature.get(b"ByteRange").ok()?.as_array().ok()?;
+            let end = byte_range
+                .get(2)?
+                .as_i64()
+                .ok()?
+                .checked_add(byte_range.get(3)?.as_i64().ok()?)?;
+            Some((end, signature))
+        })
+        .max_by_key(|(end, _)| *end)
+        .map(|(_, signature)| signature)
+}
 
-        // 一時ファイルに保存
-        let input_temp_path = format!("/tmp/input_{}.pdf", pdf_id);
-        let output_temp_path = format!("/tmp/signed_{}.pdf", pdf_id);
-
-        std::fs::write(&input_temp_path, &pdf_with_field)
-            .context("Failed to write input temp PDF")?;
-
-        let result = std::process::Command::new("python3")
-            .arg("sign_pdf_pyhanko.py")
-            .arg(&input_temp_path)
-            .arg(&output_temp_path)
-            .arg("cert/cert.crt")
-            .arg("cert/key.pem")
-            .arg("KyogakuDendoSignature")
-            .arg(pdf_id.to_string()) // UUIDをreasonフィールドに記録
-            .output()
-            .context("Failed to run pyhanko signing script")?;
-
-        if !result.status.success() {
-            let stderr = String::from_utf8_lossy(&result.stderr);
-            let stdout = String::from_utf8_lossy(&result.stdout);
-            eprintln!("❌ pyhanko signing failed:");
-            eprintln!("stdout: {}", stdout);
-            eprintln!("stderr: {}", stderr);
-
-            // クリーンアップ
-            let _ = std::fs::remove_file(&input_temp_path);
-            let _ = std::fs::remove_file(&output_temp_path);
-
-            return Err(anyhow::anyhow!(
-                "Failed to sign PDF with pyhanko: {}",
-                stderr
-            ));
+/// `/Contents`は0で埋められているので，DERの先頭のタグと長さから署名本体の長さを求める
+fn der_length(data: &[u8]) -> anyhow::Result<usize> {
+    let (&first, rest) = data
+        .get(1..)
+        .and_then(|rest| rest.split_first())
+        .context("signature is empty")?;
+
+    let length = if first < 0x80 {
+        2 + first as usize
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n > std::mem::size_of::<usize>() {
+            anyhow::bail!("invalid signature length");
         }
+        let bytes = rest.get(..n).context("truncated signature")?;
+        bytes
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize)
+            .checked_add(2 + n)
+            .context("invalid signature length")?
+    };
+
+    if length > data.len() {
+        anyhow::bail!("truncated signature");
+    }
 
-        let stdout = String::from_utf8_lossy(&result.stdout);
-        println!("   {}", stdout.trim());
-        println!("✓ Successfully signed PDF");
+    Ok(length)
+}
 
-        let signed_pdf = std::fs::read(&output_temp_path).context("Failed to read signed PDF")?;
+/// 後ろから探す（増分更新で追記した部分は末尾にある）
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .rposition(|window| window == needle)
+}
 
-        println!("🔍 Verifying signature...");
-        let verify_result = std::process::Command::new("pdfsig")
-            .arg(&output_temp_path)
-            .output();
+#[cfg(test)]
+mod tests {
+    fn handler(cert: &str, key: &str) -> super::PdfHandler {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/");
+        super::PdfHandler::new(&format!("{}{}", dir, cert), &format!("{}{}", dir, key)).unwrap()
+    }
 
-        match verify_result {
-            Ok(verify_output) => {
-                let stdout = String::from_utf8_lossy(&verify_output.stdout);
-                println!("pdfsig output:\n{}", stdout);
-            }
-            Err(e) => {
-                println!("⚠️  Failed to run pdfsig: {}", e);
-            }
-        }
+    /// 白紙1ページのPDF
+    fn blank_pdf() -> Vec<u8> {
+        use pdf_signing::lopdf::dictionary;
+
+        let mut doc = pdf_signing::lopdf::Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let content_id =
+            doc.add_object(pdf_signing::lopdf::Stream::new(dictionary! {}, Vec::new()));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        });
+        doc.objects.insert(
+            pages_id,
+            pdf_signing::lopdf::Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).unwrap();
+        pdf
+    }
+
+    fn signed_pdf(handler: &super::PdfHandler, pdf_id: &uuid::Uuid) -> Vec<u8> {
+        let stamp = crate::pdf_stamp::CopyStamp {
+            pdf_id,
+            paid_at: 1_760_000_000,
+            buyer_name: None,
+        };
+        handler.sign_pdf(blank_pdf(), &stamp, "blank.pdf").unwrap()
+    }
 
-        let _ = std::fs::remove_file(&input_temp_path);
-        let _ = std::fs::remove_file(&output_temp_path);
+    #[test]
+    fn verifies_own_signature_and_returns_pdf_id() {
+        let handler = handler("cert.crt", "key.pem");
+        let pdf_id = uuid::Uuid::new_v4();
+        let pdf = signed_pdf(&handler, &pdf_id);
+        assert_eq!(handler.verify_pdf(&pdf).unwrap(), pdf_id);
+    }
 
-        Ok(signed_pdf)
+    #[test]
+    fn rejects_signature_by_another_certificate() {
+        let other = handler("other_cert.crt", "other_key.pem");
+        let pdf = signed_pdf(&other, &uuid::Uuid::new_v4());
+        assert!(handler("cert.crt", "key.pem").verify_pdf(&pdf).is_err());
+    }
+
+    #[test]
+    fn rejects_modified_or_appended_pdf() {
+        let handler = handler("cert.crt", "key.pem");
+        let pdf = signed_pdf(&handler, &uuid::Uuid::new_v4());
+
+        let mut modified = pdf.clone();
+        // ヘッダーの`%PDF-1.5`の版数を書き換える
+        modified[7] = b'4';
+        assert!(handler.verify_pdf(&modified).is_err());
+
+        let mut appended = pdf.clone();
+        appended.extend_from_slice(b"\n% appended\n");
+        assert!(handler.verify_pdf(&appended).is_err());
+
+        assert!(handler.verify_pdf(b"not a pdf").is_err());
+    }
+
+    #[test]
+    fn rejects_bytes_outside_contents_in_unsigned_gap() {
+        let handler = handler("cert.crt", "key.pem");
+        let mut pdf = signed_pdf(&handler, &uuid::Uuid::new_v4());
+
+        // `/Contents`の16進文字列を縮め，署名していない隙間に文字列の外のバイトを入れる
+        let contents = super::rfind(&pdf, b"/Contents<").unwrap() + "/Contents".len();
+        let end = contents + super::SIGNATURE_CAPACITY * 2 + 1;
+        assert_eq!(&pdf[end - 2..=end], b"00>");
+        pdf[end - 2..=end].copy_from_slice(b">  ");
+        assert!(handler.verify_pdf(&pdf).is_err());
     }
-}
 
-impl Default for PdfHandler {
-    fn default() -> Self {
-        Self::new()
+    #[test]
+    fn der_length_rejects_oversized_lengths() {
+        assert_eq!(super::der_length(&[0x30, 0x02, 0x05, 0x00]).unwrap(), 4);
+        assert!(super::der_length(&[0x30, 0x82, 0x01]).is_err());
+        let mut huge = vec![0x30, 0x88];
+        huge.extend_from_slice(&[0xff; 8]);
+        assert!(super::der_length(&huge).is_err());
+        assert!(super::der_length(&[0x30, 0x89]).is_err());
     }
 }
@@ -0,0 +1,344 @@
+use anyhow::Context as _;
+
+/// スタンプに使うフォントのリソース名
+const FONT_RESOURCE: &str = "KawausoStamp";
+/// スタンプの文字の大きさ（pt）
+const FONT_SIZE: i64 = 7;
+/// ページの左下からの余白（pt）
+const MARGIN: f32 = 12.0;
+/// XMPに追加するプロパティの名前空間
+const XMP_NAMESPACE: &str = "urn:kyogaku-dendo:kawauso:1.0";
+
+/// 販売したPDFの各ページに入れる，購入者ごとのスタンプ
+pub struct CopyStamp<'a> {
+    pub pdf_id: &'a uuid::Uuid,
+    /// 決済日時（UNIX秒）
+    pub paid_at: u64,
+    /// 購入者が入力した名前（任意）
+    pub buyer_name: Option<&'a str>,
+}
+
+impl CopyStamp<'_> {
+    fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "Licensed copy {} / {}",
+            self.pdf_id,
+            self.paid_at_display()
+        )];
+        if let Some(buyer_name) = self.buyer_name {
+            lines.push(format!("Licensed to {}", buyer_name));
+        }
+        lines
+    }
+
+    fn paid_at_display(&self) -> String {
+        let dt_utc = chrono::DateTime::from_timestamp(self.paid_at as i64, 0).unwrap_or_default();
+        let dt_jst = dt_utc + chrono::Duration::hours(9);
+        dt_jst.format("%Y/%m/%d %H:%M JST").to_string()
+    }
+
+    /// 全ページの左下にスタンプを入れ，XMPメタデータにPDF IDを記録する
+    ///
+    /// 変更は増分更新の`new_document`に入るので，署名より前に呼ぶこと
+    pub fn apply(&self, doc: &mut pdf_signing::lopdf::IncrementalDocument) -> anyhow::Result<()> {
+        let font_id = doc.new_document.add_object(stamp_font());
+
+        // 元のページの内容がグラフィックス状態を戻さずに終わっていてもスタンプの位置がずれないようにする
+        let save_state_id = doc.new_document.add_object(pdf_signing::lopdf::Stream::new(
+            pdf_signing::lopdf::Dictionary::new(),
+            b"q\n".to_vec(),
+        ));
+
+        let page_ids: Vec<_> = doc.get_prev_documents().get_pages().into_values().collect();
+        for page_id in page_ids {
+            let (x, y) = page_origin(doc.get_prev_documents(), page_id);
+            let stamp_id = doc.new_document.add_object(pdf_signing::lopdf::Stream::new(
+                pdf_signing::lopdf::Dictionary::new(),
+                self.content(x + MARGIN, y + MARGIN)?,
+            ));
+
+            let mut contents: Vec<pdf_signing::lopdf::Object> = doc
+                .get_prev_documents()
+                .get_page_contents(page_id)
+                .into_iter()
+                .map(pdf_signing::lopdf::Object::Reference)
+                .collect();
+            contents.insert(0, save_state_id.into());
+            contents.push(stamp_id.into());
+
+            add_font_resource(doc, page_id, font_id)?;
+            doc.new_document
+                .get_dictionary_mut(page_id)?
+                .set("Contents", contents);
+        }
+
+        self.add_xmp(doc)?;
+
+        Ok(())
+    }
+
+    fn content(&self, x: f32, y: f32) -> anyhow::Result<Vec<u8>> {
+        use pdf_signing::lopdf::Object;
+        use pdf_signing::lopdf::content::Operation;
+
+        let lines = self.lines();
+        let leading = FONT_SIZE as f32 * 1.3;
+
+        let mut operations = vec![
+            Operation::new("Q", vec![]),
+            Operation::new("q", vec![]),
+            Operation::new("BT", vec![]),
+            Operation::new(
+                "Tf",
+                vec![Object::Name(FONT_RESOURCE.into()), FONT_SIZE.into()],
+            ),
+            Operation::new("TL", vec![leading.into()]),
+            Operation::new("g", vec![0.4.into()]),
+            Operation::new(
+                "Td",
+                vec![x.into(), (y + leading * (lines.len() - 1) as f32).into()],
+            ),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 {
+                operations.push(Operation::new("T*", vec![]));
+            }
+            operations.push(Operation::new(
+                "Tj",
+                vec![Object::String(
+                    ucs2_bytes(line),
+                    pdf_signing::lopdf::StringFormat::Hexadecimal,
+                )],
+            ));
+        }
+        operations.push(Operation::new("ET", vec![]));
+        operations.push(Operation::new("Q", vec![]));
+
+        pdf_signing::lopdf::content::Content { operations }
+            .encode()
+            .context("Failed to encode stamp content")
+    }
+
+    /// 既存のXMPがあれば`rdf:Description`を書き足し，なければ新しく作る
+    fn add_xmp(&self, doc: &mut pdf_signing::lopdf::IncrementalDocument) -> anyhow::Result<()> {
+        let prev = doc.get_prev_documents();
+        let root_id = prev.trailer.get(b"Root")?.as_reference()?;
+
+        let existing = prev
+            .get_dictionary(root_id)?
+            .get(b"Metadata")
+            .and_then(|metadata| metadata.as_reference())
+            .and_then(|id| prev.get_object(id))
+            .and_then(|metadata| metadata.as_stream())
+            .and_then(|stream| stream.decompressed_content())
+            .ok()
+            .and_then(|content| String::from_utf8(content).ok());
+
+        let description = format!(
+            r#"<rdf:Description rdf:about="" xmlns:kawauso="{}"><kawauso:PdfId>{}</kawauso:PdfId><kawauso:PaidAt>{}</kawauso:PaidAt></rdf:Description>"#,
+            XMP_NAMESPACE,
+            self.pdf_id,
+            chrono::DateTime::from_timestamp(self.paid_at as i64, 0)
+                .unwrap_or_default()
+                .to_rfc3339()
+        );
+
+        let xmp = match existing
+            .as_deref()
+            .and_then(|xmp| xmp.rfind("</rdf:RDF>").map(|at| (xmp, at)))
+        {
+            Some((xmp, at)) => format!("{}{}{}", &xmp[..at], description, &xmp[at..]),
+            None => format!(
+                concat!(
+                    "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+                    "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">",
+                    "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">{}</rdf:RDF>",
+                    "</x:xmpmeta>\n",
+                    "<?xpacket end=\"w\"?>"
+                ),
+                description
+            ),
+        };
+
+        let metadata_id = doc.new_document.add_object(pdf_signing::lopdf::Stream::new(
+            pdf_signing::lopdf::Dictionary::from_iter(vec![
+                (
+                    "Type",
+                    pdf_signing::lopdf::Object::Name(b"Metadata".to_vec()),
+                ),
+                ("Subtype", pdf_signing::lopdf::Object::Name(b"XML".to_vec())),
+            ]),
+            xmp.into_bytes(),
+        ));
+
+        doc.opt_clone_object_to_new_document(root_id)?;
+        doc.new_document
+            .get_dictionary_mut(root_id)?
+            .set("Metadata", metadata_id);
+
+        Ok(())
+    }
+}
+
+/// 埋め込まない日本語フォント（平成角ゴシック）
+///
+/// 主要なPDFビューアーは代替フォントで表示するので，購入者名に日本語を使える
+fn stamp_font() -> pdf_signing::lopdf::Dictionary {
+    use pdf_signing::lopdf::{Dictionary, Object};
+
+    let descriptor = Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"FontDescriptor".to_vec())),
+        ("FontName", Object::Name(b"HeiseiKakuGo-W5".to_vec())),
+        ("Flags", Object::Integer(4)),
+        (
+            "FontBBox",
+            Object::Array(vec![(-92).into(), (-250).into(), 1010.into(), 922.into()]),
+        ),
+        ("ItalicAngle", Object::Integer(0)),
+        ("Ascent", Object::Integer(752)),
+        ("Descent", Object::Integer(-221)),
+        ("CapHeight", Object::Integer(737)),
+        ("StemV", Object::Integer(114)),
+    ]);
+
+    let descendant = Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Font".to_vec())),
+        ("Subtype", Object::Name(b"CIDFontType0".to_vec())),
+        ("BaseFont", Object::Name(b"HeiseiKakuGo-W5".to_vec())),
+        (
+            "CIDSystemInfo",
+            Object::Dictionary(Dictionary::from_iter(vec![
+                ("Registry", Object::string_literal("Adobe")),
+                ("Ordering", Object::string_literal("Japan1")),
+                ("Supplement", Object::Integer(2)),
+            ])),
+        ),
+        ("FontDescriptor", Object::Dictionary(descriptor)),
+        ("DW", Object::Integer(1000)),
+        // ASCII（CID 1〜95）は半角幅
+        ("W", Object::Array(vec![1.into(), 95.into(), 500.into()])),
+    ]);
+
+    Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Font".to_vec())),
+        ("Subtype", Object::Name(b"Type0".to_vec())),
+        (
+            "BaseFont",
+            Object::Name(b"HeiseiKakuGo-W5-UniJIS-UCS2-H".to_vec()),
+        ),
+        ("Encoding", Object::Name(b"UniJIS-UCS2-H".to_vec())),
+        (
+            "DescendantFonts",
+            Object::Array(vec![Object::Dictionary(descendant)]),
+        ),
+    ])
+}
+
+/// UniJIS-UCS2-H用にUCS-2（ビッグエンディアン）で符号化する（BMP外の文字は`?`にする）
+fn ucs2_bytes(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| u16::try_from(c as u32).unwrap_or(b'?' as u16))
+        .flat_map(u16::to_be_bytes)
+        .collect()
+}
+
+/// 親から継承した値も含めてページの属性を探す
+fn inherited<'a>(
+    doc: &'a pdf_signing::lopdf::Document,
+    page_id: pdf_signing::lopdf::ObjectId,
+    key: &[u8],
+) -> Option<&'a pdf_signing::lopdf::Object> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    // 壊れたPDFで親をたどり続けないように深さを制限する
+    for _ in 0..32 {
+        if let Ok(value) = node.get(key) {
+            return doc.dereference(value).ok().map(|(_, value)| value);
+        }
+        node = doc
+            .get_dictionary(node.get(b"Parent").ok()?.as_reference().ok()?)
+            .ok()?;
+    }
+    None
+}
+
+/// 表示領域（CropBox，なければMediaBox）の左下の座標
+fn page_origin(
+    doc: &pdf_signing::lopdf::Document,
+    page_id: pdf_signing::lopdf::ObjectId,
+) -> (f32, f32) {
+    let number = |object: &pdf_signing::lopdf::Object| match object {
+        pdf_signing::lopdf::Object::Integer(n) => Some(*n as f32),
+        pdf_signing::lopdf::Object::Real(n) => Some(*n),
+        _ => None,
+    };
+
+    inherited(doc, page_id, b"CropBox")
+        .or_else(|| inherited(doc, page_id, b"MediaBox"))
+        .and_then(|rect| rect.as_array().ok())
+        .and_then(|rect| Some((number(rect.first()?)?, number(rect.get(1)?)?)))
+        .unwrap_or((0.0, 0.0))
+}
+
+/// ページのリソースにスタンプ用のフォントを追加する
+///
+/// 親から継承したリソースはページに複製してから書き足す
+fn add_font_resource(
+    doc: &mut pdf_signing::lopdf::IncrementalDocument,
+    page_id: pdf_signing::lopdf::ObjectId,
+    font_id: pdf_signing::lopdf::ObjectId,
+) -> anyhow::Result<()> {
+    use pdf_signing::lopdf::Object;
+
+    doc.opt_clone_object_to_new_document(page_id)?;
+
+    let resources = match doc.new_document.get_dictionary(page_id)?.get(b"Resources") {
+        Ok(Object::Reference(id)) => Some(*id),
+        Ok(_) => None,
+        Err(_) => {
+            let inherited = inherited(doc.get_prev_documents(), page_id, b"Resources")
+                .and_then(|resources| resources.as_dict().ok())
+                .cloned()
+                .unwrap_or_default();
+            doc.new_document
+                .get_dictionary_mut(page_id)?
+                .set("Resources", inherited);
+            None
+        }
+    };
+    if let Some(id) = resources {
+        doc.opt_clone_object_to_new_document(id)?;
+    }
+
+    let resources = match resources {
+        Some(id) => doc.new_document.get_dictionary_mut(id)?,
+        None => doc
+            .new_document
+            .get_dictionary_mut(page_id)?
+            .get_mut(b"Resources")?
+            .as_dict_mut()?,
+    };
+
+    match resources.get(b"Font") {
+        Ok(Object::Reference(fonts_id)) => {
+            let fonts_id = *fonts_id;
+            doc.opt_clone_object_to_new_document(fonts_id)?;
+            doc.new_document
+                .get_dictionary_mut(fonts_id)?
+                .set(FONT_RESOURCE, font_id);
+        }
+        Ok(Object::Dictionary(_)) => {
+            resources
+                .get_mut(b"Font")?
+                .as_dict_mut()?
+                .set(FONT_RESOURCE, font_id);
+        }
+        _ => {
+            resources.set(
+                "Font",
+                pdf_signing::lopdf::Dictionary::from_iter(vec![(FONT_RESOURCE, font_id.into())]),
+            );
+        }
+    }
+
+    Ok(())
+}
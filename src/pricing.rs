@@ -0,0 +1,213 @@
+/// 注文の金額内訳
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PriceBreakdown {
+    pub lines: Vec<PricedLine>,
+    pub discounts: Vec<AppliedDiscount>,
+    pub total: u32,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PricedLine {
+    pub sku: String,
+    pub name: String,
+    pub qty: u32,
+    #[serde(rename = "unitPrice")]
+    pub unit_price: u32,
+    pub subtotal: u32,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AppliedDiscount {
+    pub name: String,
+    pub sets: u32,
+    pub amount: u32,
+}
+
+/// カタログの単価とセット割引から合計金額を計算する
+///
+/// セット割引はカタログに書かれた順に適用し，一度セットに数えた品目は次の割引には使わない．合計が`u32`に収まらなければエラー
+pub fn compute(
+    catalog: &crate::catalog::Catalog,
+    lines: &[crate::catalog::OrderLine<'_>],
+) -> anyhow::Result<PriceBreakdown> {
+    let too_large = || anyhow::anyhow!("order total is too large");
+    let priced_lines: Vec<PricedLine> = lines
+        .iter()
+        .map(|line| PricedLine {
+            sku: line.item.sku.clone(),
+            name: line.item.name.clone(),
+            qty: line.qty,
+            unit_price: line.item.price,
+            subtotal: line.subtotal(),
+        })
+        .collect();
+
+    let mut remaining: std::collections::HashMap<&str, u32> = lines
+        .iter()
+        .map(|line| (line.item.sku.as_str(), line.qty))
+        .collect();
+
+    let mut discounts = Vec::new();
+    for discount in catalog.discounts() {
+        let sets = discount
+            .components
+            .iter()
+            .map(|component| {
+                component
+                    .skus
+                    .iter()
+                    .map(|sku| remaining.get(sku.as_str()).copied().unwrap_or(0))
+                    .try_fold(0u32, u32::checked_add)
+                    .map(|available| available / component.qty)
+                    .ok_or_else(too_large)
+            })
+            .collect::<anyhow::Result<Vec<u32>>>()?
+            .into_iter()
+            .min()
+            .unwrap_or(0);
+
+        if sets == 0 {
+            continue;
+        }
+
+        // セットに使った分を残りの数量から差し引く
+        for component in &discount.components {
+            let mut needed = component.qty.checked_mul(sets).ok_or_else(too_large)?;
+            for sku in &component.skus {
+                if let Some(qty) = remaining.get_mut(sku.as_str()) {
+                    let used = std::cmp::min(*qty, needed);
+                    *qty -= used;
+                    needed -= used;
+                }
+            }
+        }
+
+        discounts.push(AppliedDiscount {
+            name: discount.name.clone(),
+            sets,
+            amount: discount.amount.checked_mul(sets).ok_or_else(too_large)?,
+        });
+    }
+
+    let gross = priced_lines
+        .iter()
+        .map(|line| line.subtotal)
+        .try_fold(0u32, u32::checked_add)
+        .ok_or_else(too_large)?;
+    let discount_total = discounts
+        .iter()
+        .map(|discount| discount.amount)
+        .try_fold(0u32, u32::checked_add)
+        .ok_or_else(too_large)?;
+
+    Ok(PriceBreakdown {
+        lines: priced_lines,
+        discounts,
+        total: gross.saturating_sub(discount_total),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    const CATALOG: &str = r#"
+        [[items]]
+        sku = "ff"
+        name = "フランクフルト"
+        price = 300
+
+        [[items]]
+        sku = "ff_plain"
+        name = "フランクフルト（ケチャップなし）"
+        price = 300
+
+        [[items]]
+        sku = "drink"
+        name = "ドリンク"
+        price = 100
+
+        [[discounts]]
+        name = "セット"
+        amount = 50
+        components = [
+            { skus = ["ff", "ff_plain"], qty = 1 },
+            { skus = ["drink"], qty = 1 },
+        ]
+    "#;
+
+    fn order(items: &[(&str, u32)]) -> Vec<crate::catalog::OrderItem> {
+        items
+            .iter()
+            .map(|(sku, qty)| crate::catalog::OrderItem {
+                sku: sku.to_string(),
+                qty: *qty,
+            })
+            .collect()
+    }
+
+    fn total(catalog: &crate::catalog::Catalog, items: &[(&str, u32)]) -> super::PriceBreakdown {
+        let lines = catalog.resolve(&order(items)).unwrap();
+        super::compute(catalog, &lines).unwrap()
+    }
+
+    #[test]
+    fn sums_line_subtotals_without_discount() {
+        let catalog = crate::catalog::Catalog::parse(CATALOG).unwrap();
+        let breakdown = total(&catalog, &[("ff", 2)]);
+        assert_eq!(breakdown.lines.len(), 1);
+        assert_eq!(breakdown.lines[0].subtotal, 600);
+        assert!(breakdown.discounts.is_empty());
+        assert_eq!(breakdown.total, 600);
+    }
+
+    #[test]
+    fn applies_set_discount_per_complete_set() {
+        let catalog = crate::catalog::Catalog::parse(CATALOG).unwrap();
+        // フランクフルトは2種類を合わせて3本，ドリンクは2本なので2セット
+        let breakdown = total(&catalog, &[("ff", 2), ("ff_plain", 1), ("drink", 2)]);
+        assert_eq!(breakdown.discounts.len(), 1);
+        assert_eq!(breakdown.discounts[0].sets, 2);
+        assert_eq!(breakdown.discounts[0].amount, 100);
+        assert_eq!(breakdown.total, 900 + 200 - 100);
+    }
+
+    #[test]
+    fn skips_discount_when_a_component_is_missing() {
+        let catalog = crate::catalog::Catalog::parse(CATALOG).unwrap();
+        let breakdown = total(&catalog, &[("ff", 3)]);
+        assert!(breakdown.discounts.is_empty());
+        assert_eq!(breakdown.total, 900);
+    }
+
+    #[test]
+    fn rejects_quantity_above_max_qty() {
+        let catalog = crate::catalog::Catalog::parse(CATALOG).unwrap();
+        assert!(catalog.resolve(&order(&[("ff", 100)])).is_err());
+        assert!(catalog.resolve(&order(&[("ff", 60), ("ff", 40)])).is_err());
+        assert!(
+            catalog
+                .resolve(&order(&[("ff", u32::MAX), ("ff", 2)]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_total_that_does_not_fit_in_u32() {
+        let catalog = crate::catalog::Catalog::parse(
+            r#"
+            max_qty = 2
+            [[items]]
+            sku = "a"
+            name = "A"
+            price = 2000000000
+
+            [[items]]
+            sku = "b"
+            name = "B"
+            price = 2000000000
+            "#,
+        )
+        .unwrap();
+        let lines = catalog.resolve(&order(&[("a", 2), ("b", 2)])).unwrap();
+        assert!(super::compute(&catalog, &lines).is_err());
+    }
+}
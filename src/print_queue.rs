@@ -0,0 +1,600 @@
+use rusqlite::OptionalExtension as _;
+use tracing::Instrument as _;
+
+/// この回数失敗したジョブは自動では再試行しない（`/jobs/{id}/reprint`で再印刷できる）
+const MAX_ATTEMPTS: u32 = 8;
+/// 再試行の待ち時間の上限（秒）
+const MAX_BACKOFF_SECS: i64 = 300;
+/// キューが空のときに再確認する間隔
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    PdfReceipt,
+    Tag,
+    OrderReceipt,
+    /// 売上レポート（X/Zレポート）
+    Report,
+}
+
+impl JobKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::PdfReceipt => "pdf_receipt",
+            JobKind::Tag => "tag",
+            JobKind::OrderReceipt => "order_receipt",
+            JobKind::Report => "report",
+        }
+    }
+
+    pub fn parse(s: &str) -> rusqlite::Result<Self> {
+        match s {
+            "pdf_receipt" => Ok(JobKind::PdfReceipt),
+            "tag" => Ok(JobKind::Tag),
+            "order_receipt" => Ok(JobKind::OrderReceipt),
+            "report" => Ok(JobKind::Report),
+            _ => Err(rusqlite::Error::InvalidColumnType(
+                0,
+                format!("unknown job kind: {}", s),
+                rusqlite::types::Type::Text,
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Printing,
+    Done,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Pending => "pending",
+            JobStatus::Printing => "printing",
+            JobStatus::Done => "done",
+            JobStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "pending" => Some(JobStatus::Pending),
+            "printing" => Some(JobStatus::Printing),
+            "done" => Some(JobStatus::Done),
+            "failed" => Some(JobStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// 印刷ジョブ（ESC/POSのバイト列そのものは含まない）
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PrintJob {
+    pub id: String,
+    pub kind: JobKind,
+    /// PDF IDや呼び出し番号など，スタッフがジョブを探すための値
+    pub reference: String,
+    /// 送り先のプリンター名
+    pub printer: String,
+    pub status: JobStatus,
+    pub attempts: u32,
+    #[serde(rename = "lastError")]
+    pub last_error: Option<String>,
+    #[serde(rename = "nextAttemptAt")]
+    pub next_attempt_at: i64,
+    #[serde(rename = "createdAt")]
+    pub created_at: i64,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: i64,
+}
+
+const JOB_COLUMNS: &str = "id, kind, reference, status, attempts, last_error, next_attempt_at, created_at, updated_at, printer";
+
+impl PrintJob {
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        let kind: String = row.get(1)?;
+        let status: String = row.get(3)?;
+        Ok(Self {
+            id: row.get(0)?,
+            kind: JobKind::parse(&kind)?,
+            reference: row.get(2)?,
+            printer: row.get(9)?,
+            status: JobStatus::parse(&status).unwrap_or(JobStatus::Failed),
+            attempts: row.get(4)?,
+            last_error: row.get(5)?,
+            next_attempt_at: row.get(6)?,
+            created_at: row.get(7)?,
+            updated_at: row.get(8)?,
+        })
+    }
+}
+
+/// 生成したESC/POSジョブを永続化し，印刷できるまで再試行するキュー
+pub struct PrintQueue {
+    db: std::sync::Arc<crate::db::Database>,
+    notify: tokio::sync::Notify,
+    metrics: std::sync::Arc<crate::metrics::Metrics>,
+}
+
+impl PrintQueue {
+    pub fn new(
+        db: std::sync::Arc<crate::db::Database>,
+        metrics: std::sync::Arc<crate::metrics::Metrics>,
+    ) -> Self {
+        Self {
+            db,
+            notify: tokio::sync::Notify::new(),
+            metrics,
+        }
+    }
+
+    /// ジョブを追加してワーカーを起こす
+    pub async fn enqueue(
+        &self,
+        kind: JobKind,
+        reference: &str,
+        printer: &str,
+        data: Vec<u8>,
+    ) -> anyhow::Result<String> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let reference = reference.to_string();
+        let printer = printer.to_string();
+        let job_id = id.clone();
+
+        self.db
+            .call(move |conn| {
+                let now = crate::db::now();
+                conn.execute(
+                    "INSERT INTO print_jobs
+                        (id, kind, reference, printer, data, status, attempts, next_attempt_at, created_at, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, 0, ?7, ?7, ?7)",
+                    rusqlite::params![
+                        job_id,
+                        kind.as_str(),
+                        reference,
+                        printer,
+                        data,
+                        JobStatus::Pending.as_str(),
+                        now
+                    ],
+                )
+            })
+            .await?;
+
+        self.metrics.count_print_job(kind.as_str(), "queued");
+        self.notify.notify_waiters();
+
+        Ok(id)
+    }
+
+    pub async fn list(
+        &self,
+        status: Option<JobStatus>,
+        printer: Option<&str>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<PrintJob>> {
+        let printer = printer.map(|p| p.to_string());
+        self.db
+            .call(move |conn| {
+                let sql = format!(
+                    "SELECT {} FROM print_jobs
+                     WHERE (?1 IS NULL OR status = ?1) AND (?3 IS NULL OR printer = ?3)
+                     ORDER BY rowid DESC LIMIT ?2",
+                    JOB_COLUMNS
+                );
+                let mut stmt = conn.prepare(&sql)?;
+                let jobs = stmt
+                    .query_map(
+                        rusqlite::params![status.map(|s| s.as_str()), limit, printer],
+                        PrintJob::from_row,
+                    )?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok(jobs)
+            })
+            .await
+    }
+
+    pub async fn get(&self, id: &str) -> anyhow::Result<Option<PrintJob>> {
+        let id = id.to_string();
+        self.db
+            .call(move |conn| {
+                let sql = format!("SELECT {} FROM print_jobs WHERE id = ?1", JOB_COLUMNS);
+                conn.query_row(&sql, [id], PrintJob::from_row).optional()
+            })
+            .await
+    }
+
+    /// 同じESC/POSデータの新しいジョブを作ってもう一度印刷する（元のジョブの記録はそのまま残す）
+    ///
+    /// `printer`を指定すると別のプリンターに送り直す．元のジョブがなければ`None`
+    pub async fn reprint(
+        &self,
+        id: &str,
+        printer: Option<&str>,
+    ) -> anyhow::Result<Option<PrintJob>> {
+        let source_id = id.to_string();
+        let new_id = uuid::Uuid::new_v4().to_string();
+        let job_id = new_id.clone();
+        let printer = printer.map(|p| p.to_string());
+        let inserted = self
+            .db
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO print_jobs
+                        (id, kind, reference, printer, data, status, attempts, next_attempt_at, created_at, updated_at)
+                     SELECT ?2, kind, reference, COALESCE(?3, printer), data, ?4, 0, ?5, ?5, ?5
+                     FROM print_jobs WHERE id = ?1",
+                    rusqlite::params![
+                        source_id,
+                        job_id,
+                        printer,
+                        JobStatus::Pending.as_str(),
+                        crate::db::now()
+                    ],
+                )
+            })
+            .await?;
+        if inserted == 0 {
+            return Ok(None);
+        }
+
+        let job = self.get(&new_id).await?;
+        if let Some(job) = &job {
+            self.metrics.count_print_job(job.kind.as_str(), "queued");
+        }
+        self.notify.notify_waiters();
+
+        Ok(job)
+    }
+
+    /// 設定から消えたプリンター宛ての未印刷ジョブを既定のプリンターに付け替える
+    pub async fn reassign_orphans(
+        &self,
+        printers: Vec<String>,
+        fallback: &str,
+    ) -> anyhow::Result<usize> {
+        let fallback = fallback.to_string();
+        self.db
+            .call(move |conn| {
+                let placeholders = vec!["?"; printers.len()].join(", ");
+                let sql = format!(
+                    "UPDATE print_jobs SET printer = ?
+                     WHERE status IN ('pending', 'printing') AND printer NOT IN ({})",
+                    placeholders
+                );
+                conn.execute(
+                    &sql,
+                    rusqlite::params_from_iter(std::iter::once(fallback).chain(printers)),
+                )
+            })
+            .await
+    }
+
+    /// 前回の起動中に印刷中だったジョブを未印刷に戻す
+    async fn recover(&self, printer: &str) -> anyhow::Result<usize> {
+        let printer = printer.to_string();
+        self.db
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE print_jobs SET status = ?1 WHERE status = ?2 AND printer = ?3",
+                    rusqlite::params![
+                        JobStatus::Pending.as_str(),
+                        JobStatus::Printing.as_str(),
+                        printer
+                    ],
+                )
+            })
+            .await
+    }
+
+    /// 指定したプリンター宛ての印刷待ちジョブを1件取り出して印刷中にする
+    async fn claim_next(&self, printer: &str) -> anyhow::Result<Option<(PrintJob, Vec<u8>)>> {
+        let printer = printer.to_string();
+        self.db
+            .call(move |conn| {
+                let now = crate::db::now();
+                let tx = conn.transaction()?;
+                let sql = format!(
+                    "SELECT {}, data FROM print_jobs
+                     WHERE status = ?1 AND next_attempt_at <= ?2 AND printer = ?3
+                     ORDER BY rowid LIMIT 1",
+                    JOB_COLUMNS
+                );
+                let next = tx
+                    .query_row(
+                        &sql,
+                        rusqlite::params![JobStatus::Pending.as_str(), now, printer],
+                        |row| Ok((PrintJob::from_row(row)?, row.get::<_, Vec<u8>>(10)?)),
+                    )
+                    .optional()?;
+
+                if let Some((job, _)) = &next {
+                    tx.execute(
+                        "UPDATE print_jobs SET status = ?2, updated_at = ?3 WHERE id = ?1",
+                        rusqlite::params![job.id, JobStatus::Printing.as_str(), now],
+                    )?;
+                }
+                tx.commit()?;
+
+                Ok(next)
+            })
+            .await
+    }
+
+    async fn mark_done(&self, id: &str) -> anyhow::Result<()> {
+        let id = id.to_string();
+        self.db
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE print_jobs
+                     SET status = ?2, attempts = attempts + 1, last_error = NULL, updated_at = ?3
+                     WHERE id = ?1",
+                    rusqlite::params![id, JobStatus::Done.as_str(), crate::db::now()],
+                )
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// 失敗を記録し，上限に達していなければ指数バックオフで再試行を予約する
+    async fn mark_failed(&self, job: &PrintJob, error: &str) -> anyhow::Result<JobStatus> {
+        let attempts = job.attempts + 1;
+        let status = if attempts >= MAX_ATTEMPTS {
+            JobStatus::Failed
+        } else {
+            JobStatus::Pending
+        };
+        let backoff = std::cmp::min(2_i64.pow(attempts), MAX_BACKOFF_SECS);
+        let id = job.id.clone();
+        let error = error.to_string();
+
+        self.db
+            .call(move |conn| {
+                let now = crate::db::now();
+                conn.execute(
+                    "UPDATE print_jobs
+                     SET status = ?2, attempts = ?3, last_error = ?4, next_attempt_at = ?5, updated_at = ?6
+                     WHERE id = ?1",
+                    rusqlite::params![id, status.as_str(), attempts, error, now + backoff, now],
+                )
+            })
+            .await?;
+
+        Ok(status)
+    }
+}
+
+/// キューから指定したプリンター宛てのジョブを取り出して送り続ける
+///
+/// プリンター名ごとに1つ起動するので，1台が止まっても他のプリンターの印刷は進む．
+/// 送り先は`current`でジョブごとに引き直す（設定を読み直すとプリンターが入れ替わる．設定から消えた間は何もしない）
+pub async fn run_worker<F>(queue: std::sync::Arc<PrintQueue>, name: String, current: F)
+where
+    F: Fn() -> Option<std::sync::Arc<crate::printers::Printer>>,
+{
+    match queue.recover(&name).await {
+        Ok(0) => {}
+        Ok(n) => tracing::info!("Requeued {} interrupted print job(s) for {}", n, name),
+        Err(e) => tracing::warn!("Failed to recover print jobs: {}", e),
+    }
+
+    loop {
+        if let Some(printer) = current() {
+            match queue.claim_next(&name).await {
+                Ok(Some((job, data))) => {
+                    let span = tracing::info_span!(
+                        "print",
+                        job_id = %job.id,
+                        kind = job.kind.as_str(),
+                        reference = %job.reference,
+                        printer = %name,
+                        attempt = job.attempts + 1,
+                    );
+                    send_job(&queue, &printer, &job, &data)
+                        .instrument(span)
+                        .await;
+                    continue;
+                }
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Failed to fetch print job: {}", e),
+            }
+        }
+
+        tokio::select! {
+            _ = queue.notify.notified() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+/// 取り出したジョブをプリンターに送り，結果をキューに記録する
+async fn send_job(
+    queue: &PrintQueue,
+    printer: &crate::printers::Printer,
+    job: &PrintJob,
+    data: &[u8],
+) {
+    let kind = job.kind.as_str();
+    match printer.send(data).await {
+        Ok(()) => {
+            tracing::info!("Print job {} ({}) done", job.id, job.reference);
+            queue.metrics.count_print_job(kind, "done");
+            if let Err(e) = queue.mark_done(&job.id).await {
+                tracing::warn!("Failed to update print job {}: {}", job.id, e);
+            }
+        }
+        Err(e) => {
+            tracing::warn!("Print job {} ({}) failed: {:#}", job.id, job.reference, e);
+            queue
+                .metrics
+                .printer_send_failures
+                .with_label_values(&[printer.name.as_str(), printer.transport_kind()])
+                .inc();
+            match queue.mark_failed(job, &format!("{:#}", e)).await {
+                Ok(JobStatus::Failed) => {
+                    queue.metrics.count_print_job(kind, "failed");
+                    tracing::error!(
+                        "Print job {} gave up after {} attempts",
+                        job.id,
+                        MAX_ATTEMPTS
+                    )
+                }
+                Ok(_) => queue.metrics.count_print_job(kind, "retry"),
+                Err(e) => tracing::warn!("Failed to update print job {}: {}", job.id, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn queue() -> super::PrintQueue {
+        let db = crate::db::Database::open(":memory:").unwrap();
+        let metrics = crate::metrics::Metrics::new().unwrap();
+        super::PrintQueue::new(std::sync::Arc::new(db), std::sync::Arc::new(metrics))
+    }
+
+    async fn enqueue(queue: &super::PrintQueue, reference: &str, printer: &str) -> String {
+        queue
+            .enqueue(
+                super::JobKind::Tag,
+                reference,
+                printer,
+                reference.as_bytes().to_vec(),
+            )
+            .await
+            .unwrap()
+    }
+
+    async fn status(queue: &super::PrintQueue, id: &str) -> super::JobStatus {
+        queue.get(id).await.unwrap().unwrap().status
+    }
+
+    #[tokio::test]
+    async fn claims_jobs_in_order_per_printer() {
+        let queue = queue();
+        let first = enqueue(&queue, "1", "kitchen").await;
+        let other = enqueue(&queue, "2", "register").await;
+        let second = enqueue(&queue, "3", "kitchen").await;
+
+        let (job, data) = queue.claim_next("kitchen").await.unwrap().unwrap();
+        assert_eq!(job.id, first);
+        assert_eq!(data, b"1");
+        assert_eq!(status(&queue, &first).await, super::JobStatus::Printing);
+
+        let (job, _) = queue.claim_next("kitchen").await.unwrap().unwrap();
+        assert_eq!(job.id, second);
+        assert!(queue.claim_next("kitchen").await.unwrap().is_none());
+
+        let (job, _) = queue.claim_next("register").await.unwrap().unwrap();
+        assert_eq!(job.id, other);
+    }
+
+    #[tokio::test]
+    async fn failed_jobs_wait_for_backoff_and_give_up_at_max_attempts() {
+        let queue = queue();
+        let id = enqueue(&queue, "1", "kitchen").await;
+        let (mut job, _) = queue.claim_next("kitchen").await.unwrap().unwrap();
+
+        let before = crate::db::now();
+        assert_eq!(
+            queue.mark_failed(&job, "offline").await.unwrap(),
+            super::JobStatus::Pending
+        );
+        let failed = queue.get(&id).await.unwrap().unwrap();
+        assert_eq!(failed.attempts, 1);
+        assert_eq!(failed.last_error.as_deref(), Some("offline"));
+        assert!((before + 2..=crate::db::now() + 2).contains(&failed.next_attempt_at));
+        // 待ち時間が過ぎるまでは取り出さない
+        assert!(queue.claim_next("kitchen").await.unwrap().is_none());
+
+        job.attempts = 4;
+        queue.mark_failed(&job, "offline").await.unwrap();
+        let failed = queue.get(&id).await.unwrap().unwrap();
+        assert!(failed.next_attempt_at >= before + 32);
+
+        job.attempts = super::MAX_ATTEMPTS - 1;
+        assert_eq!(
+            queue.mark_failed(&job, "offline").await.unwrap(),
+            super::JobStatus::Failed
+        );
+        assert_eq!(status(&queue, &id).await, super::JobStatus::Failed);
+    }
+
+    #[tokio::test]
+    async fn backoff_is_capped() {
+        let queue = queue();
+        let id = enqueue(&queue, "1", "kitchen").await;
+        let (mut job, _) = queue.claim_next("kitchen").await.unwrap().unwrap();
+
+        job.attempts = 20;
+        let before = crate::db::now();
+        queue.mark_failed(&job, "offline").await.unwrap();
+        let failed = queue.get(&id).await.unwrap().unwrap();
+        assert!(failed.next_attempt_at <= crate::db::now() + super::MAX_BACKOFF_SECS);
+        assert!(failed.next_attempt_at >= before + super::MAX_BACKOFF_SECS);
+    }
+
+    #[tokio::test]
+    async fn recover_requeues_jobs_interrupted_by_a_restart() {
+        let queue = queue();
+        let id = enqueue(&queue, "1", "kitchen").await;
+        queue.claim_next("kitchen").await.unwrap().unwrap();
+
+        assert_eq!(queue.recover("register").await.unwrap(), 0);
+        assert_eq!(queue.recover("kitchen").await.unwrap(), 1);
+        let (job, _) = queue.claim_next("kitchen").await.unwrap().unwrap();
+        assert_eq!(job.id, id);
+    }
+
+    #[tokio::test]
+    async fn reprint_creates_a_new_job() {
+        let queue = queue();
+        let id = enqueue(&queue, "1", "kitchen").await;
+        queue.claim_next("kitchen").await.unwrap().unwrap();
+        queue.mark_done(&id).await.unwrap();
+
+        let reprinted = queue.reprint(&id, Some("register")).await.unwrap().unwrap();
+        assert_ne!(reprinted.id, id);
+        assert_eq!(reprinted.status, super::JobStatus::Pending);
+        assert_eq!(reprinted.attempts, 0);
+        assert_eq!(reprinted.reference, "1");
+        assert_eq!(reprinted.printer, "register");
+        assert_eq!(status(&queue, &id).await, super::JobStatus::Done);
+
+        let (job, data) = queue.claim_next("register").await.unwrap().unwrap();
+        assert_eq!(job.id, reprinted.id);
+        assert_eq!(data, b"1");
+        assert!(queue.reprint("unknown", None).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn reassigns_unprinted_jobs_of_removed_printers() {
+        let queue = queue();
+        let done = enqueue(&queue, "1", "old").await;
+        queue.claim_next("old").await.unwrap().unwrap();
+        queue.mark_done(&done).await.unwrap();
+        let pending = enqueue(&queue, "2", "old").await;
+        enqueue(&queue, "3", "kitchen").await;
+
+        let reassigned = queue
+            .reassign_orphans(
+                vec!["kitchen".to_string(), "register".to_string()],
+                "register",
+            )
+            .await
+            .unwrap();
+        assert_eq!(reassigned, 1);
+        assert_eq!(
+            queue.get(&pending).await.unwrap().unwrap().printer,
+            "register"
+        );
+        assert_eq!(queue.get(&done).await.unwrap().unwrap().printer, "old");
+    }
+}
@@ -0,0 +1,164 @@
+/// DLE EOTで問い合わせたプリンターの状態
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct PrinterStatus {
+    /// 転送方法がプリンターからの読み返しに対応しているか
+    pub supported: bool,
+    pub offline: bool,
+    #[serde(rename = "paperNearEnd")]
+    pub paper_near_end: bool,
+    #[serde(rename = "paperOut")]
+    pub paper_out: bool,
+    #[serde(rename = "coverOpen")]
+    pub cover_open: bool,
+    #[serde(rename = "cutterError")]
+    pub cutter_error: bool,
+    /// 問い合わせ自体に失敗した場合のエラー
+    pub error: Option<String>,
+    #[serde(rename = "checkedAt")]
+    pub checked_at: Option<i64>,
+}
+
+impl PrinterStatus {
+    /// 印刷に支障がある状態か
+    pub fn has_problem(&self) -> bool {
+        self.supported
+            && (self.offline
+                || self.paper_out
+                || self.cover_open
+                || self.cutter_error
+                || self.error.is_some())
+    }
+
+    fn from_responses(responses: &[u8]) -> anyhow::Result<Self> {
+        use escpos::utils::{RealTimeStatusRequest as Request, RealTimeStatusResponse as Response};
+
+        let [printer, offline_cause, error_cause, roll_paper] = responses else {
+            anyhow::bail!("unexpected number of status responses: {}", responses.len());
+        };
+
+        let parse = |request, response| {
+            Response::parse(request, response)
+                .map_err(|e| anyhow::anyhow!("invalid status response: {}", e))
+        };
+        let printer = parse(Request::Printer, *printer)?;
+        let offline_cause = parse(Request::OfflineCause, *offline_cause)?;
+        let error_cause = parse(Request::ErrorCause, *error_cause)?;
+        let roll_paper = parse(Request::RollPaperSensor, *roll_paper)?;
+
+        let flag = |map: &std::collections::HashMap<Response, bool>, key| {
+            map.get(&key).copied().unwrap_or(false)
+        };
+
+        Ok(Self {
+            supported: true,
+            offline: !flag(&printer, Response::Online),
+            paper_near_end: !flag(&roll_paper, Response::RollPaperNearEndSensorPaperAdequate),
+            paper_out: !flag(&roll_paper, Response::RollPaperEndSensorPaperPresent)
+                || flag(&offline_cause, Response::PrintingStopsDueToPaperEnd),
+            cover_open: !flag(&offline_cause, Response::CoverClosed),
+            cutter_error: flag(&error_cause, Response::AutocutterErrorOccurred),
+            error: None,
+            checked_at: Some(crate::db::now()),
+        })
+    }
+}
+
+/// プリンターの状態を定期的に問い合わせて保持する
+pub struct StatusMonitor {
+    /// ログ表示用のプリンター名
+    name: String,
+    transport: std::sync::Arc<dyn crate::printer_transport::PrinterTransport>,
+    status: std::sync::RwLock<PrinterStatus>,
+}
+
+impl StatusMonitor {
+    pub fn new(
+        name: String,
+        transport: std::sync::Arc<dyn crate::printer_transport::PrinterTransport>,
+    ) -> Self {
+        Self {
+            name,
+            transport,
+            status: std::sync::RwLock::new(PrinterStatus::default()),
+        }
+    }
+
+    pub fn current(&self) -> PrinterStatus {
+        self.status
+            .read()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    /// 用紙切れを検知しているか（状態を取れない転送方法では常にfalse）
+    pub fn is_paper_out(&self) -> bool {
+        let status = self.current();
+        status.supported && status.paper_out
+    }
+
+    /// DLE EOT 1〜4を送って状態を更新する
+    pub async fn poll(&self) {
+        let transport = self.transport.clone();
+        let commands: Vec<Vec<u8>> = [1u8, 2, 3, 4]
+            .iter()
+            .map(|n| [escpos::utils::DLE_REAL_TIME_STATUS, &[*n]].concat())
+            .collect();
+
+        let result = tokio::task::spawn_blocking(move || transport.query(&commands))
+            .await
+            .map_err(|e| anyhow::anyhow!("status task panicked: {}", e))
+            .and_then(|result| result);
+
+        let status = match result {
+            Ok(Some(responses)) => {
+                PrinterStatus::from_responses(&responses).unwrap_or_else(|e| PrinterStatus {
+                    supported: true,
+                    error: Some(e.to_string()),
+                    checked_at: Some(crate::db::now()),
+                    ..Default::default()
+                })
+            }
+            Ok(None) => PrinterStatus::default(),
+            // 問い合わせに答えないプリンターはオフラインとみなす
+            Err(e) => PrinterStatus {
+                supported: true,
+                offline: true,
+                error: Some(format!("{:#}", e)),
+                checked_at: Some(crate::db::now()),
+                ..Default::default()
+            },
+        };
+
+        let previous = self.current();
+        if status.has_problem() != previous.has_problem() || status.paper_out != previous.paper_out
+        {
+            if status.has_problem() {
+                tracing::warn!("Printer {} status: {:?}", self.name, status);
+            } else {
+                tracing::info!("Printer {} status recovered", self.name);
+            }
+        }
+
+        if let Ok(mut current) = self.status.write() {
+            *current = status;
+        }
+    }
+}
+
+/// 一定間隔でプリンターの状態を問い合わせ続ける
+///
+/// 設定を読み直してプリンターが作り直され，古い`StatusMonitor`が使われなくなったら終わる
+pub async fn run_monitor(monitor: std::sync::Weak<StatusMonitor>, interval: std::time::Duration) {
+    while let Some(monitor) = monitor.upgrade() {
+        monitor.poll().await;
+        if !monitor.current().supported {
+            tracing::info!(
+                "Printer {} transport does not report status; monitoring disabled",
+                monitor.name
+            );
+            return;
+        }
+        drop(monitor);
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,310 @@
+use anyhow::Context as _;
+use escpos::driver::Driver as _;
+use std::io::{Read as _, Write as _};
+use std::os::unix::fs::OpenOptionsExt as _;
+
+/// 生成済みのESC/POSデータをプリンターに届ける方法
+///
+/// どの実装もブロッキングI/Oなので，`Printer`からは`spawn_blocking`で呼ぶ
+pub trait PrinterTransport: Send + Sync {
+    /// ログ表示用の名前
+    fn name(&self) -> String;
+
+    fn send(&self, data: &[u8]) -> anyhow::Result<()>;
+
+    /// 何も印刷せずに，送れる状態か確かめる（起動時の自己診断で使う）
+    fn check(&self) -> anyhow::Result<()>;
+
+    /// DLE EOTなどのリアルタイムコマンドを順に送り，それぞれの応答1バイトを読む
+    ///
+    /// プリンターから読み返せない転送方法では`None`を返す
+    fn query(&self, _commands: &[Vec<u8>]) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+}
+
+/// ステータス応答を待つ時間
+const QUERY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// プリンターの設定から転送方法を選ぶ
+///
+/// - `lpr`: CUPSのキュー`cups_queue`（省略時はプリンター名）にRAWで送る（既定）
+/// - `tcp`: `address`（`IP:ポート`，ポート省略時は9100）に直接送る
+/// - `device`: `address`のデバイスファイル（`/dev/usb/lp0`，`/dev/ttyUSB0`など）に書き込む
+/// - `file`: `address`のディレクトリ（既定は`printed/{プリンター名}`）に保存するだけ
+pub fn from_config(
+    name: &str,
+    config: &crate::config::PrinterConfig,
+) -> anyhow::Result<std::sync::Arc<dyn PrinterTransport>> {
+    let address = config.address.as_deref();
+
+    let transport: std::sync::Arc<dyn PrinterTransport> = match config.transport.as_str() {
+        "lpr" => std::sync::Arc::new(LprTransport::new(
+            config
+                .cups_queue
+                .clone()
+                .unwrap_or_else(|| name.to_string()),
+        )),
+        "tcp" => {
+            let address = address.context("address is required for tcp transport")?;
+            std::sync::Arc::new(TcpTransport::parse(address)?)
+        }
+        "device" => {
+            let address = address.context("address is required for device transport")?;
+            std::sync::Arc::new(DeviceTransport::new(address.into()))
+        }
+        "file" => std::sync::Arc::new(FileTransport::new(match address {
+            Some(address) => address.into(),
+            None => std::path::Path::new("printed").join(name),
+        })),
+        other => anyhow::bail!("unknown printer transport: {}", other),
+    };
+
+    Ok(transport)
+}
+
+/// CUPSの`lpr`コマンドで印刷
+pub struct LprTransport {
+    printer_name: String,
+}
+
+impl LprTransport {
+    pub fn new(printer_name: String) -> Self {
+        Self { printer_name }
+    }
+}
+
+impl PrinterTransport for LprTransport {
+    fn name(&self) -> String {
+        format!("lpr ({})", self.printer_name)
+    }
+
+    fn send(&self, data: &[u8]) -> anyhow::Result<()> {
+        let mut child = std::process::Command::new("lpr")
+            .arg("-P")
+            .arg(&self.printer_name)
+            .arg("-l") // RAWモード
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .context("Failed to execute lpr command")?;
+
+        let mut stdin = child.stdin.take().context("Failed to open lpr stdin")?;
+        stdin.write_all(data).context("Failed to write to lpr")?;
+        drop(stdin);
+
+        let output = child
+            .wait_with_output()
+            .context("Failed to wait for lpr command")?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow::anyhow!("lpr command failed: {}", stderr));
+        }
+
+        Ok(())
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        let output = std::process::Command::new("lpstat")
+            .arg("-p")
+            .arg(&self.printer_name)
+            .output()
+            .context("Failed to execute lpstat command")?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("CUPS queue not found: {}", stderr.trim());
+        }
+
+        Ok(())
+    }
+}
+
+/// ネットワークプリンターのRAWポート（JetDirect 9100）に直接送る
+pub struct TcpTransport {
+    host: String,
+    port: u16,
+    timeout: std::time::Duration,
+}
+
+impl TcpTransport {
+    pub fn parse(address: &str) -> anyhow::Result<Self> {
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .context(format!("invalid printer port: {}", address))?,
+            ),
+            None => (address, 9100),
+        };
+
+        // escposのNetworkDriverは接続タイムアウトを使う場合IPアドレスしか受け付けない
+        host.parse::<std::net::IpAddr>()
+            .context(format!("printer address must be an IP address: {}", host))?;
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            timeout: std::time::Duration::from_secs(5),
+        })
+    }
+
+    fn connect(&self) -> anyhow::Result<escpos::driver::NetworkDriver> {
+        escpos::driver::NetworkDriver::open(&self.host, self.port, Some(self.timeout))
+            .context(format!("Failed to connect to {}:{}", self.host, self.port))
+    }
+}
+
+impl PrinterTransport for TcpTransport {
+    fn name(&self) -> String {
+        format!("tcp ({}:{})", self.host, self.port)
+    }
+
+    fn send(&self, data: &[u8]) -> anyhow::Result<()> {
+        let driver = self.connect()?;
+        driver.write(data).context("Failed to send to printer")?;
+        driver
+            .flush()
+            .context("Failed to flush printer connection")?;
+
+        Ok(())
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        self.connect()?;
+        Ok(())
+    }
+
+    fn query(&self, commands: &[Vec<u8>]) -> anyhow::Result<Option<Vec<u8>>> {
+        let driver = self.connect()?;
+        let mut responses = Vec::with_capacity(commands.len());
+
+        for command in commands {
+            driver
+                .write(command)
+                .context("Failed to send status request")?;
+            driver.flush().context("Failed to flush status request")?;
+
+            let mut buf = [0u8; 1];
+            let n = driver
+                .read(&mut buf)
+                .context("Failed to read status response")?;
+            if n == 0 {
+                anyhow::bail!("printer closed the connection");
+            }
+            responses.push(buf[0]);
+        }
+
+        Ok(Some(responses))
+    }
+}
+
+/// USB（`/dev/usb/lp0`）やシリアル（`/dev/ttyUSB0`）のデバイスファイルに書き込む
+pub struct DeviceTransport {
+    path: std::path::PathBuf,
+}
+
+impl DeviceTransport {
+    pub fn new(path: std::path::PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl PrinterTransport for DeviceTransport {
+    fn name(&self) -> String {
+        format!("device ({})", self.path.display())
+    }
+
+    fn send(&self, data: &[u8]) -> anyhow::Result<()> {
+        let mut device = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&self.path)
+            .context(format!("Failed to open {}", self.path.display()))?;
+        device
+            .write_all(data)
+            .context("Failed to write to printer device")?;
+        device.flush().context("Failed to flush printer device")?;
+
+        Ok(())
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&self.path)
+            .context(format!("Failed to open {}", self.path.display()))?;
+        Ok(())
+    }
+
+    fn query(&self, commands: &[Vec<u8>]) -> anyhow::Result<Option<Vec<u8>>> {
+        // 応答しないデバイスで読み込みが止まらないように非ブロッキングで開く
+        let mut device = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)
+            .context(format!("Failed to open {}", self.path.display()))?;
+        let mut responses = Vec::with_capacity(commands.len());
+
+        for command in commands {
+            device
+                .write_all(command)
+                .context("Failed to send status request")?;
+
+            let deadline = std::time::Instant::now() + QUERY_TIMEOUT;
+            let mut buf = [0u8; 1];
+            loop {
+                match device.read(&mut buf) {
+                    Ok(1) => break,
+                    Ok(_) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e).context("Failed to read status response"),
+                }
+                if std::time::Instant::now() >= deadline {
+                    anyhow::bail!("printer did not answer status request");
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            responses.push(buf[0]);
+        }
+
+        Ok(Some(responses))
+    }
+}
+
+/// 印刷せずにファイルとして保存するだけ（プリンターのない開発環境用）
+pub struct FileTransport {
+    dir: std::path::PathBuf,
+}
+
+impl FileTransport {
+    pub fn new(dir: std::path::PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+impl PrinterTransport for FileTransport {
+    fn name(&self) -> String {
+        format!("file ({})", self.dir.display())
+    }
+
+    fn send(&self, data: &[u8]) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir).context("Failed to create output directory")?;
+
+        let filename = format!(
+            "{}_{}.bin",
+            chrono::Utc::now().format("%Y%m%d%H%M%S"),
+            uuid::Uuid::new_v4()
+        );
+        std::fs::write(self.dir.join(filename), data).context("Failed to write print job")?;
+
+        Ok(())
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir).context("Failed to create output directory")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::Context as _;
+
+/// 名前付きのプリンター1台
+pub struct Printer {
+    pub name: String,
+    /// 読み直したときに作り直す必要があるか比べる
+    config: crate::config::PrinterConfig,
+    transport: std::sync::Arc<dyn crate::printer_transport::PrinterTransport>,
+    pub status: std::sync::Arc<crate::printer_status::StatusMonitor>,
+}
+
+impl Printer {
+    fn new(
+        name: String,
+        config: crate::config::PrinterConfig,
+        transport: std::sync::Arc<dyn crate::printer_transport::PrinterTransport>,
+    ) -> Self {
+        let status = std::sync::Arc::new(crate::printer_status::StatusMonitor::new(
+            name.clone(),
+            transport.clone(),
+        ));
+
+        Self {
+            name,
+            config,
+            transport,
+            status,
+        }
+    }
+
+    /// 転送方法の表示用の名前
+    pub fn transport_name(&self) -> String {
+        self.transport.name()
+    }
+
+    /// 設定の転送方法（`lpr`，`tcp`，`device`，`file`）
+    pub fn transport_kind(&self) -> &str {
+        &self.config.transport
+    }
+
+    /// 何も印刷せずに，送れる状態か確かめる（ブロッキング）
+    pub fn check(&self) -> anyhow::Result<()> {
+        self.transport.check()
+    }
+
+    /// ESC/POSデータを設定された転送方法でプリンターに送る
+    pub async fn send(&self, data: &[u8]) -> anyhow::Result<()> {
+        let transport = self.transport.clone();
+        let data = data.to_vec();
+
+        tokio::task::spawn_blocking(move || transport.send(&data))
+            .await
+            .context("Printer transport task panicked")?
+            .context(format!(
+                "Failed to send to {} ({})",
+                self.name,
+                self.transport.name()
+            ))
+    }
+}
+
+/// 設定されたすべてのプリンターと，ジョブの種類ごとの送り先
+pub struct Printers {
+    printers: std::collections::BTreeMap<String, std::sync::Arc<Printer>>,
+    routing: crate::config::RoutingConfig,
+    default: String,
+}
+
+impl Printers {
+    /// プリンターを作り，ルーティング規則が存在するプリンターを指しているか確認する
+    ///
+    /// 設定を読み直したときは，設定が変わっていないプリンターを`previous`からそのまま引き継ぐ（状態の監視も続く）
+    pub fn from_config(
+        config: &crate::config::Config,
+        previous: Option<&Printers>,
+    ) -> anyhow::Result<Self> {
+        let mut printers = std::collections::BTreeMap::new();
+        for (name, printer_config) in &config.printers {
+            let unchanged = previous
+                .and_then(|previous| previous.printers.get(name))
+                .filter(|printer| printer.config == *printer_config);
+            let printer = match unchanged {
+                Some(printer) => printer.clone(),
+                None => {
+                    let transport = crate::printer_transport::from_config(name, printer_config)
+                        .context(format!("failed to configure printer: {}", name))?;
+                    std::sync::Arc::new(Printer::new(
+                        name.clone(),
+                        printer_config.clone(),
+                        transport,
+                    ))
+                }
+            };
+            printers.insert(name.clone(), printer);
+        }
+
+        let routing = config.routing.clone();
+        let default = match &routing.default {
+            Some(name) => name.clone(),
+            None if printers.len() == 1 => printers.keys().next().unwrap().clone(),
+            None if printers.contains_key("default") => "default".to_string(),
+            None => {
+                anyhow::bail!("routing.default is required when several printers are configured")
+            }
+        };
+
+        for (rule, name) in [
+            ("default", Some(&default)),
+            ("tag", routing.tag.as_ref()),
+            ("order_receipt", routing.order_receipt.as_ref()),
+            ("pdf_receipt", routing.pdf_receipt.as_ref()),
+            ("report", routing.report.as_ref()),
+        ] {
+            if let Some(name) = name
+                && !printers.contains_key(name)
+            {
+                anyhow::bail!("routing.{} refers to unknown printer: {}", rule, name);
+            }
+        }
+
+        Ok(Self {
+            printers,
+            routing,
+            default,
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &std::sync::Arc<Printer>> {
+        self.printers.values()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.printers.keys().cloned().collect()
+    }
+
+    pub fn default_name(&self) -> &str {
+        &self.default
+    }
+
+    /// 名前でプリンターを探す（省略時は既定のプリンター）
+    pub fn get(&self, name: Option<&str>) -> anyhow::Result<&std::sync::Arc<Printer>> {
+        let name = name.unwrap_or(&self.default);
+        self.printers
+            .get(name)
+            .context(format!("unknown printer: {}", name))
+    }
+
+    /// リクエストで指定されたプリンター，なければジョブの種類のルーティング規則で送り先を選ぶ
+    pub fn route(
+        &self,
+        kind: crate::print_queue::JobKind,
+        requested: Option<&str>,
+    ) -> anyhow::Result<&std::sync::Arc<Printer>> {
+        let routed = match kind {
+            crate::print_queue::JobKind::Tag => self.routing.tag.as_deref(),
+            crate::print_queue::JobKind::OrderReceipt => self.routing.order_receipt.as_deref(),
+            crate::print_queue::JobKind::PdfReceipt => self.routing.pdf_receipt.as_deref(),
+            crate::print_queue::JobKind::Report => self.routing.report.as_deref(),
+        };
+
+        self.get(requested.or(routed))
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::Context as _;
 
+/// S3互換ストレージ（R2）
 pub struct R2Client {
     client: aws_sdk_s3::Client,
     bucket_name: String,
@@ -12,26 +13,98 @@ impl R2Client {
             bucket_name,
         }
     }
+}
+
+impl crate::blob_store::BlobStore for R2Client {
+    fn name(&self) -> String {
+        format!("s3 ({})", self.bucket_name)
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        data: Vec<u8>,
+        content_type: &'a str,
+    ) -> crate::blob_store::BlobFuture<'a, ()> {
+        Box::pin(async move {
+            tracing::debug!(
+                bucket = %self.bucket_name,
+                key,
+                bytes = data.len(),
+                "Uploading to R2"
+            );
+
+            let body = aws_sdk_s3::primitives::ByteStream::from(data);
+
+            self.client
+                .put_object()
+                .bucket(&self.bucket_name)
+                .key(key)
+                .body(body)
+                .content_type(content_type)
+                .send()
+                .await
+                .context("failed to upload to R2")?;
+
+            tracing::debug!("Successfully uploaded to R2");
+            Ok(())
+        })
+    }
+
+    fn get<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> crate::blob_store::BlobFuture<'a, Option<crate::blob_store::Blob>> {
+        Box::pin(async move {
+            let output = match self
+                .client
+                .get_object()
+                .bucket(&self.bucket_name)
+                .key(key)
+                .send()
+                .await
+            {
+                Ok(output) => output,
+                Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e).context("failed to download from R2"),
+            };
+
+            let content_type = output
+                .content_type()
+                .unwrap_or("application/octet-stream")
+                .to_string();
+            let data = output
+                .body
+                .collect()
+                .await
+                .context("failed to read object body from R2")?
+                .into_bytes()
+                .to_vec();
+
+            Ok(Some(crate::blob_store::Blob { data, content_type }))
+        })
+    }
+
+    fn presigned_url<'a>(
+        &'a self,
+        key: &'a str,
+        expires_in: std::time::Duration,
+    ) -> crate::blob_store::BlobFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let presigning = aws_sdk_s3::presigning::PresigningConfig::expires_in(expires_in)
+                .context("invalid presigned URL lifetime")?;
+            let request = self
+                .client
+                .get_object()
+                .bucket(&self.bucket_name)
+                .key(key)
+                .presigned(presigning)
+                .await
+                .context("failed to presign R2 download URL")?;
 
-    pub async fn upload_pdf(&self, object_key: &str, pdf_data: Vec<u8>) -> anyhow::Result<()> {
-        println!("☁️  Uploading PDF to R2...");
-        println!("   Bucket: {}", self.bucket_name);
-        println!("   Key: {}", object_key);
-        println!("   Size: {} bytes", pdf_data.len());
-
-        let body = aws_sdk_s3::primitives::ByteStream::from(pdf_data);
-
-        self.client
-            .put_object()
-            .bucket(&self.bucket_name)
-            .key(object_key)
-            .body(body)
-            .content_type("application/pdf")
-            .send()
-            .await
-            .context("failed to upload PDF to R2")?;
-
-        println!("Successfully uploaded to R2");
-        Ok(())
+            Ok(Some(request.uri().to_string()))
+        })
     }
 }
@@ -0,0 +1,470 @@
+use anyhow::Context as _;
+
+/// 内蔵フォントA（12×24ドット）
+const FONT_A: (u32, u32) = (12, 24);
+/// 内蔵フォントB（9×17ドット）
+const FONT_B: (u32, u32) = (9, 17);
+/// `ESC 2`の行間（ドット）
+const DEFAULT_LINE_SPACING: u32 = 30;
+/// 暴走したデータで巨大な画像を作らないための上限（ドット）
+const MAX_HEIGHT: u32 = 30_000;
+
+const ESC: u8 = 0x1B;
+const GS: u8 = 0x1D;
+const DLE: u8 = 0x10;
+const LF: u8 = 0x0A;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Justify {
+    Left,
+    Center,
+    Right,
+}
+
+/// 印字中の文字の装飾
+#[derive(Debug, Clone, Copy)]
+struct TextState {
+    font: (u32, u32),
+    width_mul: u32,
+    height_mul: u32,
+    bold: bool,
+    underline: bool,
+    reverse: bool,
+}
+
+impl Default for TextState {
+    fn default() -> Self {
+        Self {
+            font: FONT_A,
+            width_mul: 1,
+            height_mul: 1,
+            bold: false,
+            underline: false,
+            reverse: false,
+        }
+    }
+}
+
+impl TextState {
+    fn cell(&self) -> (u32, u32) {
+        (self.font.0 * self.width_mul, self.font.1 * self.height_mul)
+    }
+}
+
+/// ESC/POSのバイト列を解釈して，感熱紙に印字される見た目を再現する
+///
+/// このアプリが生成するコマンド（文字サイズ，寄せ，ビットイメージ，QRコード，カット）を扱う
+struct Interpreter<'a> {
+    renderer: Option<&'a crate::text_renderer::TextRenderer>,
+    width: u32,
+    /// 白=255，黒=0．行ごとに伸ばす
+    pixels: Vec<u8>,
+    y: u32,
+    justify: Justify,
+    line_spacing: u32,
+    text: TextState,
+    line: Vec<(char, TextState)>,
+    qr_size: u32,
+    qr_level: qrcode::EcLevel,
+    qr_data: Vec<u8>,
+}
+
+/// ESC/POSのバイト列を印字幅`dot_width`ドットのPNGに描画する
+///
+/// フォントがなければ文字は灰色の箱で表す
+pub fn render_png(
+    data: &[u8],
+    dot_width: u32,
+    renderer: Option<&crate::text_renderer::TextRenderer>,
+) -> anyhow::Result<Vec<u8>> {
+    let img = render(data, dot_width, renderer)?;
+    let mut png = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut png, image::ImageFormat::Png)
+        .context("failed to encode preview as PNG")?;
+
+    Ok(png.into_inner())
+}
+
+pub fn render(
+    data: &[u8],
+    dot_width: u32,
+    renderer: Option<&crate::text_renderer::TextRenderer>,
+) -> anyhow::Result<image::GrayImage> {
+    let mut interpreter = Interpreter {
+        renderer,
+        width: dot_width,
+        pixels: Vec::new(),
+        y: 0,
+        justify: Justify::Left,
+        line_spacing: DEFAULT_LINE_SPACING,
+        text: TextState::default(),
+        line: Vec::new(),
+        qr_size: 3,
+        qr_level: qrcode::EcLevel::L,
+        qr_data: Vec::new(),
+    };
+    interpreter.run(data)?;
+    interpreter.flush_line(false)?;
+
+    let height = interpreter.y.max(1);
+    interpreter.grow(height)?;
+    image::GrayImage::from_raw(dot_width, height, interpreter.pixels)
+        .context("preview buffer size mismatch")
+}
+
+impl Interpreter<'_> {
+    fn run(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let mut i = 0;
+        while i < data.len() {
+            let rest = &data[i..];
+            i += match rest[0] {
+                LF => {
+                    self.flush_line(true)?;
+                    1
+                }
+                ESC => self.esc(rest)?,
+                GS => self.gs(rest)?,
+                // DLE EOT n（状態の問い合わせ）は印字に影響しない
+                DLE => 3,
+                0x00..=0x1F => 1,
+                _ => {
+                    // このアプリが送る文字はASCIIだけなので，1バイトを1文字として扱う
+                    self.line.push((rest[0] as char, self.text));
+                    1
+                }
+            };
+        }
+        Ok(())
+    }
+
+    fn esc(&mut self, cmd: &[u8]) -> anyhow::Result<usize> {
+        let arg = |n: usize| -> anyhow::Result<u8> {
+            cmd.get(n).copied().context("truncated ESC command")
+        };
+        Ok(match arg(1)? {
+            b'@' => {
+                self.justify = Justify::Left;
+                self.line_spacing = DEFAULT_LINE_SPACING;
+                self.text = TextState::default();
+                2
+            }
+            b'a' => {
+                self.justify = match arg(2)? {
+                    1 | b'1' => Justify::Center,
+                    2 | b'2' => Justify::Right,
+                    _ => Justify::Left,
+                };
+                3
+            }
+            b'E' => {
+                self.text.bold = arg(2)? & 1 == 1;
+                3
+            }
+            b'-' => {
+                self.text.underline = arg(2)? & 3 != 0;
+                3
+            }
+            b'M' => {
+                self.text.font = if arg(2)? & 1 == 1 { FONT_B } else { FONT_A };
+                3
+            }
+            b'2' => {
+                self.line_spacing = DEFAULT_LINE_SPACING;
+                2
+            }
+            b'3' => {
+                self.line_spacing = arg(2)? as u32;
+                3
+            }
+            b'd' => {
+                let lines = arg(2)? as u32;
+                self.flush_line(false)?;
+                self.y += self.line_spacing * lines;
+                3
+            }
+            b'J' => {
+                let dots = arg(2)? as u32;
+                self.flush_line(false)?;
+                self.y += dots;
+                3
+            }
+            // 文字コード表や倒立印字などは見た目に大きく影響しないので読み飛ばす
+            b'G' | b't' | b'R' | b'V' | b'{' | b'p' => 3,
+            other => anyhow::bail!("unsupported command: ESC {:#04x}", other),
+        })
+    }
+
+    fn gs(&mut self, cmd: &[u8]) -> anyhow::Result<usize> {
+        let arg = |n: usize| -> anyhow::Result<u8> {
+            cmd.get(n).copied().context("truncated GS command")
+        };
+        Ok(match arg(1)? {
+            b'!' => {
+                let n = arg(2)?;
+                self.text.width_mul = (n >> 4) as u32 + 1;
+                self.text.height_mul = (n & 0x0F) as u32 + 1;
+                3
+            }
+            b'B' => {
+                self.text.reverse = arg(2)? & 1 == 1;
+                3
+            }
+            b'b' => 3,
+            b'V' => {
+                self.flush_line(false)?;
+                self.cut()?;
+                match arg(2)? {
+                    65 | 66 => 4,
+                    _ => 3,
+                }
+            }
+            b'v' => self.raster_image(cmd)?,
+            b'(' if arg(2)? == b'k' => self.code_2d(cmd)?,
+            other => anyhow::bail!("unsupported command: GS {:#04x}", other),
+        })
+    }
+
+    /// `GS v 0 m xL xH yL yH d1...dk`
+    fn raster_image(&mut self, cmd: &[u8]) -> anyhow::Result<usize> {
+        let header = cmd.get(..8).context("truncated raster image")?;
+        let mode = header[3];
+        let width_bytes = u16::from_le_bytes([header[4], header[5]]) as usize;
+        let height = u16::from_le_bytes([header[6], header[7]]) as usize;
+        let len = 8 + width_bytes * height;
+        let data = cmd.get(8..len).context("truncated raster image data")?;
+
+        let (x_mul, y_mul) = match mode & 3 {
+            1 | 49 => (2, 1),
+            2 | 50 => (1, 2),
+            3 | 51 => (2, 2),
+            _ => (1, 1),
+        };
+        let width = (width_bytes * 8) as u32;
+
+        self.flush_line(false)?;
+        let x0 = self.aligned_x(width * x_mul);
+        self.grow(self.y + height as u32 * y_mul)?;
+        for row in 0..height {
+            for col in 0..width as usize {
+                let byte = data[row * width_bytes + col / 8];
+                if byte & (0x80 >> (col % 8)) == 0 {
+                    continue;
+                }
+                for dy in 0..y_mul {
+                    for dx in 0..x_mul {
+                        self.set(
+                            x0 + col as u32 * x_mul + dx,
+                            self.y + row as u32 * y_mul + dy,
+                        );
+                    }
+                }
+            }
+        }
+        self.y += height as u32 * y_mul;
+
+        Ok(len)
+    }
+
+    /// `GS ( k pL pH cn fn ...`（QRコードのみ）
+    fn code_2d(&mut self, cmd: &[u8]) -> anyhow::Result<usize> {
+        let header = cmd.get(..7).context("truncated 2D code command")?;
+        let len = 5 + u16::from_le_bytes([header[3], header[4]]) as usize;
+        let params = cmd.get(5..len).context("truncated 2D code data")?;
+        let code = params.get(..2).context("truncated 2D code parameters")?;
+        let (symbol, function) = (code[0], code[1]);
+        if symbol != 49 {
+            anyhow::bail!("unsupported 2D code: {}", symbol);
+        }
+
+        match function {
+            // モデル
+            65 => {}
+            67 => self.qr_size = params.get(2).copied().unwrap_or(3) as u32,
+            69 => {
+                self.qr_level = match params.get(2).copied().unwrap_or(48) {
+                    49 => qrcode::EcLevel::M,
+                    50 => qrcode::EcLevel::Q,
+                    51 => qrcode::EcLevel::H,
+                    _ => qrcode::EcLevel::L,
+                }
+            }
+            80 => self.qr_data = params.get(3..).unwrap_or_default().to_vec(),
+            81 => self.print_qr()?,
+            other => anyhow::bail!("unsupported QR code function: {}", other),
+        }
+
+        Ok(len)
+    }
+
+    fn print_qr(&mut self) -> anyhow::Result<()> {
+        let code = qrcode::QrCode::with_error_correction_level(&self.qr_data, self.qr_level)
+            .context("failed to encode QR code")?;
+        let modules = code.width() as u32;
+        let size = modules * self.qr_size;
+
+        self.flush_line(false)?;
+        let x0 = self.aligned_x(size);
+        self.grow(self.y + size)?;
+        for (i, color) in code.to_colors().into_iter().enumerate() {
+            if color != qrcode::Color::Dark {
+                continue;
+            }
+            let (mx, my) = (i as u32 % modules, i as u32 / modules);
+            for dy in 0..self.qr_size {
+                for dx in 0..self.qr_size {
+                    self.set(x0 + mx * self.qr_size + dx, self.y + my * self.qr_size + dy);
+                }
+            }
+        }
+        self.y += size;
+
+        Ok(())
+    }
+
+    /// 溜まった文字を1行として印字する（印字幅を超えた分は折り返す）
+    ///
+    /// `feed`なら文字がなくても1行送る
+    fn flush_line(&mut self, feed: bool) -> anyhow::Result<()> {
+        if self.line.is_empty() {
+            if feed {
+                self.y += self.line_spacing;
+            }
+            return Ok(());
+        }
+
+        let chars = std::mem::take(&mut self.line);
+        let mut start = 0;
+        while start < chars.len() {
+            let mut end = start;
+            let mut width = 0;
+            while end < chars.len() {
+                let (w, _) = chars[end].1.cell();
+                if width + w > self.width && end > start {
+                    break;
+                }
+                width += w;
+                end += 1;
+            }
+
+            let row = &chars[start..end];
+            let height = row.iter().map(|(_, s)| s.cell().1).max().unwrap_or(0);
+            self.grow(self.y + height.max(self.line_spacing))?;
+
+            let mut x = self.aligned_x(width);
+            for &(ch, state) in row {
+                let (w, h) = state.cell();
+                // 高さの違う文字はベースラインをそろえる
+                let y = self.y + height - h;
+                self.draw_char(ch, state, x, y, w, h);
+                x += w;
+            }
+
+            self.y += height.max(self.line_spacing);
+            start = end;
+        }
+
+        Ok(())
+    }
+
+    fn draw_char(&mut self, ch: char, state: TextState, x: u32, y: u32, w: u32, h: u32) {
+        let mut cell = image::GrayImage::from_pixel(w, h, image::Luma([255]));
+        match self.renderer {
+            Some(renderer) => {
+                renderer.draw_cell(&mut cell, ch, 0, 0, w, h);
+                if state.bold {
+                    let copy = cell.clone();
+                    for (px, py, p) in copy.enumerate_pixels() {
+                        if p.0[0] == 0 && px + 1 < w {
+                            cell.put_pixel(px + 1, py, image::Luma([0]));
+                        }
+                    }
+                }
+            }
+            None if ch != ' ' => {
+                // フォントがないときは文字の位置だけを灰色で示す
+                for py in h / 5..h * 4 / 5 {
+                    for px in 1..w.saturating_sub(1) {
+                        cell.put_pixel(px, py, image::Luma([160]));
+                    }
+                }
+            }
+            None => {}
+        }
+        if state.underline {
+            for px in 0..w {
+                cell.put_pixel(px, h - 1, image::Luma([0]));
+            }
+        }
+        if state.reverse {
+            image::imageops::invert(&mut cell);
+        }
+
+        for (px, py, p) in cell.enumerate_pixels() {
+            if p.0[0] < 255 {
+                let offset = ((y + py) * self.width + x + px) as usize;
+                if x + px < self.width && offset < self.pixels.len() {
+                    self.pixels[offset] = p.0[0];
+                }
+            }
+        }
+    }
+
+    /// 切り取り線を引いて少し余白を空ける
+    fn cut(&mut self) -> anyhow::Result<()> {
+        self.y += 8;
+        self.grow(self.y + 1)?;
+        for x in (0..self.width).filter(|x| x % 12 < 6) {
+            let offset = (self.y * self.width + x) as usize;
+            self.pixels[offset] = 96;
+        }
+        self.y += 24;
+        Ok(())
+    }
+
+    fn aligned_x(&self, width: u32) -> u32 {
+        let free = self.width.saturating_sub(width);
+        match self.justify {
+            Justify::Left => 0,
+            Justify::Center => free / 2,
+            Justify::Right => free,
+        }
+    }
+
+    fn grow(&mut self, height: u32) -> anyhow::Result<()> {
+        if height > MAX_HEIGHT {
+            anyhow::bail!("preview is too tall: {} dots", height);
+        }
+        let len = (height * self.width) as usize;
+        if self.pixels.len() < len {
+            self.pixels.resize(len, 255);
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, x: u32, y: u32) {
+        if x < self.width {
+            self.pixels[(y * self.width + x) as usize] = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn renders_qr_codes() {
+        let data = [
+            b"\x1d(k\x06\x001P0abc".as_slice(),
+            b"\x1d(k\x03\x001Q0".as_slice(),
+        ]
+        .concat();
+        let img = super::render(&data, 384, None).unwrap();
+        assert!(img.height() > 1);
+        assert!(img.pixels().any(|pixel| pixel.0[0] == 0));
+    }
+
+    #[test]
+    fn rejects_truncated_2d_code_commands() {
+        assert!(super::render(b"\x1d(k\x01\x001\n", 384, None).is_err());
+        assert!(super::render(b"\x1d(k\x00\x00\n\n", 384, None).is_err());
+    }
+}
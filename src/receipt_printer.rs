@@ -1,28 +1,61 @@
 use anyhow::Context as _;
 use chrono::TimeZone as _;
 
+/// プレビューするレシートの種類と内容
+pub enum Preview<'a> {
+    PdfReceipt {
+        pdf_url: &'a str,
+        pdf_id: &'a str,
+        paid_at: u64,
+        count: u32,
+    },
+    Tag {
+        tag: &'a str,
+        lines: &'a [crate::catalog::OrderLine<'a>],
+    },
+    OrderReceipt {
+        tag: &'a str,
+        lines: &'a [crate::catalog::OrderLine<'a>],
+        breakdown: &'a crate::pricing::PriceBreakdown,
+    },
+    Report(&'a crate::reports::DailyReport),
+}
+
 pub struct ReceiptPrinter {
-    printer_name: String,
     receipts_dir: std::path::PathBuf,
+    text_renderer: Option<crate::text_renderer::TextRenderer>,
+    templates: crate::receipt_template::Templates,
+    queue: std::sync::Arc<crate::print_queue::PrintQueue>,
 }
 
 impl ReceiptPrinter {
-    pub fn new(printer_name: String) -> Self {
+    pub fn new(
+        receipts_dir: std::path::PathBuf,
+        text_renderer: Option<crate::text_renderer::TextRenderer>,
+        templates: crate::receipt_template::Templates,
+        queue: std::sync::Arc<crate::print_queue::PrintQueue>,
+    ) -> Self {
         Self {
-            printer_name,
-            receipts_dir: std::path::PathBuf::from("receipts"),
+            receipts_dir,
+            text_renderer,
+            templates,
+            queue,
         }
     }
 
+    pub fn templates(&self) -> &crate::receipt_template::Templates {
+        &self.templates
+    }
+
     /// QRコード付きレシートを印刷
     pub async fn print_pdf_receipt(
         &self,
         pdf_url: &str,
         pdf_id: &str,
-        payment_id: &str,
         paid_at: u64,
-        _count: u32,
-    ) -> anyhow::Result<()> {
+        count: u32,
+        printer: &str,
+    ) -> anyhow::Result<String> {
         // receiptsディレクトリを作成
         tokio::fs::create_dir_all(&self.receipts_dir)
             .await
@@ -33,14 +66,22 @@ impl ReceiptPrinter {
         let receipt_path = self.receipts_dir.join(&receipt_filename);
 
         // ESC/POSコマンドを生成
-        self.generate_receipt(&receipt_path, pdf_url, pdf_id, paid_at, _count)?;
-
-        // lprコマンドで印刷ジョブをキューイング
-        self.send_to_printer(&receipt_path).await?;
-
-        println!("✓ Receipt printed: {}", receipt_filename);
-
-        Ok(())
+        tracing::info_span!("render")
+            .in_scope(|| self.generate_receipt(&receipt_path, pdf_url, pdf_id, paid_at, count))?;
+
+        // 印刷ジョブをキューに登録（印刷できるまでワーカーが再試行する）
+        let job_id = self
+            .enqueue(
+                crate::print_queue::JobKind::PdfReceipt,
+                pdf_id,
+                printer,
+                &receipt_path,
+            )
+            .await?;
+
+        tracing::info!("Receipt queued: {} (job {})", receipt_filename, job_id);
+
+        Ok(job_id)
     }
 
     /// ESC/POSコマンドを生成
@@ -50,115 +91,49 @@ impl ReceiptPrinter {
         pdf_url: &str,
         pdf_id: &str,
         paid_at: u64,
-        _count: u32,
+        count: u32,
     ) -> anyhow::Result<()> {
-        std::fs::File::create(path).context("Failed to create receipt file")?;
-
-        let driver =
-            escpos::driver::FileDriver::open(path).context("Failed to open file driver")?;
-
         let dt_utc = chrono::DateTime::from_timestamp(paid_at as i64, 0)
             .unwrap_or_else(|| chrono::Utc.timestamp_opt(0, 0).unwrap());
         let dt_jst = dt_utc + chrono::Duration::hours(9);
-        let paid_at_display = dt_jst.format("%Y/%m/%d %H:%M:%S").to_string();
 
-        // プリンターを初期化
-        escpos::printer::Printer::new(
-            driver,
-            Default::default(),
-            Some(escpos::printer_options::PrinterOptions::default()),
-        )
-        .init()
-        .context("Failed to init printer")?
-        .justify(escpos::utils::JustifyMode::CENTER)
-        .context("Failed to set justify")?
-        .size(1, 1)?
-        .bit_image_option(
-            "./img/npo_top.png",
-            escpos::utils::BitImageOption::new(
-                Some(400),
-                None,
-                escpos::utils::BitImageSize::Normal,
-            )?,
-        )?
-        .bit_image_option(
-            "./img/book_receipt.png",
-            escpos::utils::BitImageOption::new(
-                Some(600),
-                None,
-                escpos::utils::BitImageSize::Normal,
-            )?,
-        )?
-        .writeln("")
-        .context("Failed to write newline")?
-        .writeln("")
-        .context("Failed to write newline")?
-        .qrcode(pdf_url)
-        .context("Failed to write QR code")?
-        .bit_image_option(
-            "./img/qr-instruction.png",
-            escpos::utils::BitImageOption::new(
-                Some(600),
-                None,
-                escpos::utils::BitImageSize::Normal,
-            )?,
-        )?
-        .bit_image_option(
-            "./img/white.png",
-            escpos::utils::BitImageOption::new(
-                Some(400),
-                None,
-                escpos::utils::BitImageSize::Normal,
-            )?,
-        )?
-        .writeln(&format!("PDF ID: {}", &pdf_id[..8]))
-        .context("Failed to write PDF ID")?
-        .bit_image_option(
-            "./img/white.png",
-            escpos::utils::BitImageOption::new(
-                Some(400),
-                None,
-                escpos::utils::BitImageSize::Normal,
-            )?,
-        )?
-        .writeln("Thank you!")
-        .context("Failed to write footer")?
-        .feed()
-        .context("Failed to feed")?
-        .print_cut()
-        .context("Failed to cut")?;
+        let vars = [
+            ("pdf_url", pdf_url.to_string()),
+            ("pdf_id", pdf_id.to_string()),
+            ("pdf_id_short", pdf_id.chars().take(8).collect()),
+            ("paid_at", dt_jst.format("%Y/%m/%d %H:%M:%S").to_string()),
+            ("count", count.to_string()),
+        ];
 
-        Ok(())
+        self.render_template(path, &self.templates.pdf_receipt, &vars, &[], None)
     }
 
-    async fn send_to_printer(&self, receipt_path: &std::path::PathBuf) -> anyhow::Result<()> {
-        let output = tokio::process::Command::new("lpr")
-            .arg("-P")
-            .arg(&self.printer_name)
-            .arg("-l") // RAWモード
-            .arg(receipt_path)
-            .output()
+    /// 生成済みのESC/POSファイルを印刷キューに登録
+    #[tracing::instrument(name = "queue", skip_all, fields(kind = kind.as_str(), %reference, %printer))]
+    async fn enqueue(
+        &self,
+        kind: crate::print_queue::JobKind,
+        reference: &str,
+        printer: &str,
+        receipt_path: &std::path::PathBuf,
+    ) -> anyhow::Result<String> {
+        let data = tokio::fs::read(receipt_path)
             .await
-            .context("Failed to execute lpr command")?;
+            .context("Failed to read generated receipt")?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!("lpr command failed: {}", stderr));
-        }
-
-        Ok(())
+        self.queue
+            .enqueue(kind, reference, printer, data)
+            .await
+            .context("Failed to enqueue print job")
     }
 
     /// 呼び出し番号タグを印刷（品目情報付き）
     pub async fn print_tag_receipt(
         &self,
         tag: &str,
-        ff_ketchup: u32,
-        ff_no_ketchup: u32,
-        book: u32,
-        pdf_book: u32,
-        drink: u32,
-    ) -> anyhow::Result<()> {
+        lines: &[crate::catalog::OrderLine<'_>],
+        printer: &str,
+    ) -> anyhow::Result<String> {
         // receiptsディレクトリを作成
         tokio::fs::create_dir_all(&self.receipts_dir)
             .await
@@ -169,22 +144,21 @@ impl ReceiptPrinter {
         let receipt_path = self.receipts_dir.join(&receipt_filename);
 
         // ESC/POSコマンドを生成
-        self.generate_tag(
-            &receipt_path,
-            tag,
-            ff_ketchup,
-            ff_no_ketchup,
-            book,
-            pdf_book,
-            drink,
-        )?;
+        tracing::info_span!("render").in_scope(|| self.generate_tag(&receipt_path, tag, lines))?;
 
-        // lprコマンドで印刷ジョブをキューイング
-        self.send_to_printer(&receipt_path).await?;
+        // 印刷ジョブをキューに登録
+        let job_id = self
+            .enqueue(
+                crate::print_queue::JobKind::Tag,
+                tag,
+                printer,
+                &receipt_path,
+            )
+            .await?;
 
-        println!("✓ Tag printed: {}", receipt_filename);
+        tracing::info!("Tag queued: {} (job {})", receipt_filename, job_id);
 
-        Ok(())
+        Ok(job_id)
     }
 
     /// 呼び出し番号タグのESC/POSコマンドを生成（品目情報付き）
@@ -192,418 +166,433 @@ impl ReceiptPrinter {
         &self,
         path: &std::path::PathBuf,
         tag: &str,
-        ff_ketchup: u32,
-        ff_no_ketchup: u32,
-        book: u32,
-        pdf_book: u32,
-        drink: u32,
+        lines: &[crate::catalog::OrderLine<'_>],
     ) -> anyhow::Result<()> {
-        // ファイルを作成
-        std::fs::File::create(path).context("Failed to create tag file")?;
+        let vars = [("tag", tag.to_string())];
+
+        self.render_template(path, &self.templates.tag, &vars, lines, None)
+    }
+
+    /// 注文レシートを印刷（品目情報 + 合計金額付き）
+    pub async fn print_order_receipt(
+        &self,
+        tag: &str,
+        lines: &[crate::catalog::OrderLine<'_>],
+        breakdown: &crate::pricing::PriceBreakdown,
+        printer: &str,
+    ) -> anyhow::Result<String> {
+        // receiptsディレクトリを作成
+        tokio::fs::create_dir_all(&self.receipts_dir)
+            .await
+            .context("Failed to create receipts directory")?;
+
+        // ESC/POSバイナリファイルのパス（タグ名を使用）
+        let receipt_filename = format!("order_{}.bin", tag);
+        let receipt_path = self.receipts_dir.join(&receipt_filename);
+
+        // ESC/POSコマンドを生成
+        tracing::info_span!("render")
+            .in_scope(|| self.generate_order_receipt(&receipt_path, tag, lines, breakdown))?;
+
+        // 印刷ジョブをキューに登録
+        let job_id = self
+            .enqueue(
+                crate::print_queue::JobKind::OrderReceipt,
+                tag,
+                printer,
+                &receipt_path,
+            )
+            .await?;
+
+        tracing::info!(
+            "Order receipt queued: {} (job {})",
+            receipt_filename,
+            job_id
+        );
+
+        Ok(job_id)
+    }
+
+    /// 注文レシートのESC/POSコマンドを生成（品目情報 + 合計金額付き）
+    fn generate_order_receipt(
+        &self,
+        path: &std::path::PathBuf,
+        tag: &str,
+        lines: &[crate::catalog::OrderLine<'_>],
+        breakdown: &crate::pricing::PriceBreakdown,
+    ) -> anyhow::Result<()> {
+        let vars = [
+            ("tag", tag.to_string()),
+            ("total", breakdown.total.to_string()),
+        ];
+
+        self.render_template(
+            path,
+            &self.templates.order_receipt,
+            &vars,
+            lines,
+            Some(breakdown),
+        )
+    }
+
+    /// テンプレートのブロックを上から順にESC/POSコマンドにする
+    fn render_template(
+        &self,
+        path: &std::path::PathBuf,
+        template: &crate::receipt_template::Template,
+        vars: &[(&str, String)],
+        lines: &[crate::catalog::OrderLine<'_>],
+        breakdown: Option<&crate::pricing::PriceBreakdown>,
+    ) -> anyhow::Result<()> {
+        std::fs::File::create(path).context("Failed to create receipt file")?;
 
-        // ESC/POSドライバを初期化
         let driver =
             escpos::driver::FileDriver::open(path).context("Failed to open file driver")?;
 
-        // プリンターを初期化して、ヘッダー部分を作成
         let mut printer = escpos::printer::Printer::new(
             driver,
             Default::default(),
             Some(escpos::printer_options::PrinterOptions::default()),
         );
-
-        printer
-            .init()
-            .context("Failed to init printer")?
-            .justify(escpos::utils::JustifyMode::CENTER)
-            .context("Failed to set justify")?
-            .bit_image_option(
-                "./img/npo_top.png",
-                escpos::utils::BitImageOption::new(
-                    Some(400),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
-            .bit_image_option(
-                "./img/white.png",
-                escpos::utils::BitImageOption::new(
-                    Some(640),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
-            .bit_image_option(
-                "./img/callnumber.png",
-                escpos::utils::BitImageOption::new(
-                    Some(320),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
-            .bit_image_option(
-                "./img/white.png",
-                escpos::utils::BitImageOption::new(
-                    Some(480),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
-            .size(3, 4)?
-            .writeln(&format!("[ {} ]", tag))
-            .context("Failed to write tag")?
-            .size(1, 1)?
-            .bit_image_option(
-                "./img/white.png",
-                escpos::utils::BitImageOption::new(
-                    Some(320),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
-            .bit_image_option(
-                "./img/white.png",
-                escpos::utils::BitImageOption::new(
-                    Some(320),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
-            .bit_image_option(
-                "./img/orders.png",
-                escpos::utils::BitImageOption::new(
-                    Some(320),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?;
-
-        // 各品目の画像を数量に応じて追加（0個の品目はスキップ）
-
-        // フライドポテト（ケチャップあり）
-        if ff_ketchup > 0 {
-            let img_path = format!("./img/ffketchup/line_{}.png", ff_ketchup);
-            printer.bit_image_option(
-                &img_path,
-                escpos::utils::BitImageOption::new(
-                    Some(600),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?;
+        printer.init().context("Failed to init printer")?;
+
+        for block in &template.blocks {
+            match block {
+                crate::receipt_template::Block::Image {
+                    path,
+                    width,
+                    justify,
+                } => {
+                    printer
+                        .justify(justify.escpos())?
+                        .bit_image_option(
+                            path,
+                            escpos::utils::BitImageOption::new(
+                                Some(*width),
+                                None,
+                                escpos::utils::BitImageSize::Normal,
+                            )?,
+                        )
+                        .context(format!("Failed to print image: {}", path))?;
+                }
+                crate::receipt_template::Block::Text {
+                    text,
+                    size,
+                    justify,
+                    bold,
+                    font,
+                } => {
+                    let text = crate::receipt_template::fill(text, vars);
+                    if *font {
+                        let renderer = self
+                            .text_renderer
+                            .as_ref()
+                            .context("FONT_PATH is required to print text with font = true")?;
+                        let style = crate::text_renderer::TextStyle {
+                            px_height: 32.0 * size[1] as f32,
+                            align: justify.text_align(),
+                        };
+                        self.print_text(&mut printer, renderer, &text, style)?;
+                    } else {
+                        printer
+                            .justify(justify.escpos())?
+                            .size(size[0], size[1])?
+                            .bold(*bold)?
+                            .writeln(&text)
+                            .context("Failed to write text")?
+                            .bold(false)?
+                            .size(1, 1)?;
+                    }
+                }
+                crate::receipt_template::Block::Spacer { dots } => {
+                    // ESC Jで1回に送れるのは255ドットまで
+                    let mut remaining = *dots;
+                    while remaining > 0 {
+                        let n = remaining.min(255);
+                        printer.custom(&[0x1B, b'J', n as u8])?;
+                        remaining -= n;
+                    }
+                }
+                crate::receipt_template::Block::Qr {
+                    data,
+                    size,
+                    justify,
+                } => {
+                    printer
+                        .justify(justify.escpos())?
+                        .qrcode_option(
+                            &crate::receipt_template::fill(data, vars),
+                            escpos::utils::QRCodeOption::new(
+                                escpos::utils::QRCodeModel::Model1,
+                                *size,
+                                escpos::utils::QRCodeCorrectionLevel::H,
+                            ),
+                        )
+                        .context("Failed to write QR code")?;
+                }
+                crate::receipt_template::Block::Items { subtotals } => {
+                    for line in lines {
+                        printer.justify(escpos::utils::JustifyMode::CENTER)?;
+                        self.print_item(&mut printer, line)?;
+                        if *subtotals {
+                            printer
+                                .justify(escpos::utils::JustifyMode::RIGHT)?
+                                .writeln(&format!(
+                                    "{} yen x {} = {} yen",
+                                    line.item.price,
+                                    line.qty,
+                                    line.subtotal()
+                                ))
+                                .context("Failed to write subtotal")?;
+                        }
+                    }
+                }
+                crate::receipt_template::Block::Discounts => {
+                    let breakdown = breakdown.context("discounts block needs an order")?;
+                    printer.justify(escpos::utils::JustifyMode::RIGHT)?;
+                    for discount in &breakdown.discounts {
+                        printer
+                            .writeln(&format!(
+                                "Set discount x {}: -{} yen",
+                                discount.sets, discount.amount
+                            ))
+                            .context("Failed to write discount")?;
+                    }
+                }
+                crate::receipt_template::Block::Total { size, justify } => {
+                    let breakdown = breakdown.context("total block needs an order")?;
+                    printer
+                        .justify(justify.escpos())?
+                        .size(size[0], size[1])?
+                        .writeln(&format!("Total: {} yen", breakdown.total))
+                        .context("Failed to write total")?
+                        .size(1, 1)?;
+                }
+                crate::receipt_template::Block::Cut => {
+                    printer
+                        .feed()
+                        .context("Failed to feed")?
+                        .print_cut()
+                        .context("Failed to cut")?;
+                }
+            }
         }
 
-        // フライドポテト（ケチャップなし）
-        if ff_no_ketchup > 0 {
-            let img_path = format!("./img/ffnoketchup/line_{}.png", ff_no_ketchup);
-            printer.bit_image_option(
-                &img_path,
-                escpos::utils::BitImageOption::new(
-                    Some(600),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?;
-        }
+        // 最後が`cut`でなくても溜まったコマンドを書き出す
+        printer.print().context("Failed to write receipt")?;
 
-        // 物理本（10ずつ分割: book_phys_1, book_phys_2, book_phys_3）
-        let mut remaining = book;
-        let mut batch = 1;
-        while remaining > 0 && batch <= 3 {
-            let count_in_batch = std::cmp::min(remaining, 10);
-            let img_path = format!("./img/book_phys_{}/line_{}.png", batch, count_in_batch);
-            printer.bit_image_option(
-                &img_path,
-                escpos::utils::BitImageOption::new(
-                    Some(600),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?;
+        Ok(())
+    }
 
-            remaining -= count_in_batch;
-            batch += 1;
+    /// 品目を数量付きで印刷
+    ///
+    /// カタログの`label`に従って，品名をフォントで描画するか切り出し済みの画像を使う
+    fn print_item(
+        &self,
+        printer: &mut escpos::printer::Printer<escpos::driver::FileDriver>,
+        line: &crate::catalog::OrderLine<'_>,
+    ) -> anyhow::Result<()> {
+        match &line.item.label {
+            crate::catalog::ItemLabel::Text => {
+                let renderer = self.text_renderer.as_ref().context(format!(
+                    "FONT_PATH is required to print text label: {}",
+                    line.item.sku
+                ))?;
+                self.print_text(
+                    printer,
+                    renderer,
+                    &format!("{} × {}", line.item.name, line.qty),
+                    crate::text_renderer::TextStyle::default(),
+                )?;
+            }
+            crate::catalog::ItemLabel::Images { dirs, per_image } => {
+                // 1枚で表せる数量ごとに分割（例: 物理本は10冊ずつbook_phys_1, book_phys_2, ...）
+                let mut remaining = line.qty;
+                for dir in dirs {
+                    if remaining == 0 {
+                        break;
+                    }
+                    let count_in_batch = std::cmp::min(remaining, *per_image);
+                    let img_path = format!("{}/line_{}.png", dir, count_in_batch);
+                    printer.bit_image_option(
+                        &img_path,
+                        escpos::utils::BitImageOption::new(
+                            Some(600),
+                            None,
+                            escpos::utils::BitImageSize::Normal,
+                        )?,
+                    )?;
+
+                    remaining -= count_in_batch;
+                }
+
+                if remaining > 0 {
+                    return Err(anyhow::anyhow!(
+                        "not enough label images for {} x {}",
+                        line.item.sku,
+                        line.qty
+                    ));
+                }
+            }
         }
 
-        // PDFの本
-        if pdf_book > 0 {
-            let img_path = format!("./img/book_pdf/line_{}.png", pdf_book);
-            printer.bit_image_option(
-                &img_path,
-                escpos::utils::BitImageOption::new(
-                    Some(600),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?;
-        }
+        Ok(())
+    }
 
-        // 飲み物
-        if drink > 0 {
-            let img_path = format!("./img/drink/line_{}.png", drink);
-            printer.bit_image_option(
-                &img_path,
-                escpos::utils::BitImageOption::new(
-                    Some(600),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?;
-        }
+    /// 任意の文字列をフォントで描画してビットイメージとして印刷
+    fn print_text(
+        &self,
+        printer: &mut escpos::printer::Printer<escpos::driver::FileDriver>,
+        renderer: &crate::text_renderer::TextRenderer,
+        text: &str,
+        style: crate::text_renderer::TextStyle,
+    ) -> anyhow::Result<()> {
+        let png = renderer
+            .render_png(text, style)
+            .context("Failed to render text")?;
 
-        // フッター画像を追加して印刷完了
-        printer
-            .bit_image_option(
-                "./img/white.png",
-                escpos::utils::BitImageOption::new(
-                    Some(400),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
-            .bit_image_option(
-                "./img/signage.png",
-                escpos::utils::BitImageOption::new(
-                    Some(600),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
-            .bit_image_option(
-                "./img/drink.png",
-                escpos::utils::BitImageOption::new(
-                    Some(600),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
-            .feed()
-            .context("Failed to feed")?
-            .print_cut()
-            .context("Failed to cut")?;
+        printer.bit_image_from_bytes_option(
+            &png,
+            escpos::utils::BitImageOption::new(
+                Some(renderer.dot_width()),
+                None,
+                escpos::utils::BitImageSize::Normal,
+            )?,
+        )?;
 
         Ok(())
     }
 
-    /// 注文レシートを印刷（品目情報 + 合計金額付き）
-    pub async fn print_order_receipt(
+    /// 売上レポート（X/Zレポート）を印刷
+    pub async fn print_daily_report(
         &self,
-        tag: &str,
-        ff_ketchup: u32,
-        ff_no_ketchup: u32,
-        book: u32,
-        pdf_book: u32,
-        drink: u32,
-        total: u32,
-    ) -> anyhow::Result<()> {
-        // receiptsディレクトリを作成
+        report: &crate::reports::DailyReport,
+        printer: &str,
+    ) -> anyhow::Result<String> {
         tokio::fs::create_dir_all(&self.receipts_dir)
             .await
             .context("Failed to create receipts directory")?;
 
-        // ESC/POSバイナリファイルのパス（タグ名を使用）
-        let receipt_filename = format!("order_{}.bin", tag);
+        let reference = format!("{}-{}", report.kind.title(), report.date);
+        let receipt_filename = format!("report_{}.bin", reference);
         let receipt_path = self.receipts_dir.join(&receipt_filename);
 
-        // ESC/POSコマンドを生成
-        self.generate_order_receipt(
-            &receipt_path,
-            tag,
-            ff_ketchup,
-            ff_no_ketchup,
-            book,
-            pdf_book,
-            drink,
-            total,
-        )?;
+        tracing::info_span!("render")
+            .in_scope(|| self.generate_daily_report(&receipt_path, report))?;
 
-        // lprコマンドで印刷ジョブをキューイング
-        self.send_to_printer(&receipt_path).await?;
+        let job_id = self
+            .enqueue(
+                crate::print_queue::JobKind::Report,
+                &reference,
+                printer,
+                &receipt_path,
+            )
+            .await?;
 
-        println!("✓ Order receipt printed: {}", receipt_filename);
+        tracing::info!("Report queued: {} (job {})", receipt_filename, job_id);
 
-        Ok(())
+        Ok(job_id)
     }
 
-    /// 注文レシートのESC/POSコマンドを生成（品目情報 + 合計金額付き）
-    fn generate_order_receipt(
+    /// 売上レポートのESC/POSコマンドを生成
+    ///
+    /// 品名はフォントがあれば描画し，なければSKUを印字する
+    fn generate_daily_report(
         &self,
         path: &std::path::PathBuf,
-        tag: &str,
-        ff_ketchup: u32,
-        ff_no_ketchup: u32,
-        book: u32,
-        pdf_book: u32,
-        drink: u32,
-        total: u32,
+        report: &crate::reports::DailyReport,
     ) -> anyhow::Result<()> {
-        // ファイルを作成
-        std::fs::File::create(path).context("Failed to create order receipt file")?;
+        std::fs::File::create(path).context("Failed to create report file")?;
 
-        // ESC/POSドライバを初期化
         let driver =
             escpos::driver::FileDriver::open(path).context("Failed to open file driver")?;
 
-        // プリンターを初期化して、ヘッダー部分を作成
         let mut printer = escpos::printer::Printer::new(
             driver,
             Default::default(),
             Some(escpos::printer_options::PrinterOptions::default()),
         );
 
+        let generated_at = chrono::DateTime::from_timestamp(report.generated_at, 0)
+            .unwrap_or_else(|| chrono::Utc.timestamp_opt(0, 0).unwrap())
+            + chrono::Duration::hours(9);
+
         printer
             .init()
             .context("Failed to init printer")?
-            .justify(escpos::utils::JustifyMode::CENTER)
-            .context("Failed to set justify")?
-            .bit_image_option(
-                "./img/npo_top.png",
-                escpos::utils::BitImageOption::new(
-                    Some(400),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
+            .justify(escpos::utils::JustifyMode::CENTER)?
             .size(2, 2)?
-            .bit_image_option(
-                "./img/white.png",
-                escpos::utils::BitImageOption::new(
-                    Some(320),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
-            .bit_image_option(
-                "./img/three.png",
-                escpos::utils::BitImageOption::new(
-                    Some(600),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
+            .writeln(report.kind.title())
+            .context("Failed to write title")?
             .size(1, 1)?
-            .bit_image_option(
-                "./img/white.png",
-                escpos::utils::BitImageOption::new(
-                    Some(240),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
-            .bit_image_option(
-                "./img/white.png",
-                escpos::utils::BitImageOption::new(
-                    Some(320),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
-            .bit_image_option(
-                "./img/orders.png",
-                escpos::utils::BitImageOption::new(
-                    Some(320),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?;
-
-        // 各品目の画像を数量に応じて追加（0個の品目はスキップ）
-
-        // フランクフルト（ケチャップあり）
-        if ff_ketchup > 0 {
-            let img_path = format!("./img/ffketchup/line_{}.png", ff_ketchup);
-            printer.bit_image_option(
-                &img_path,
-                escpos::utils::BitImageOption::new(
-                    Some(600),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
+            .writeln(&report.date)?
+            .writeln(&format!(
+                "Printed {}",
+                generated_at.format("%Y/%m/%d %H:%M:%S")
+            ))?
+            .justify(escpos::utils::JustifyMode::LEFT)?
+            .writeln("------------------------------------------")?;
+
+        for item in &report.items {
+            match &self.text_renderer {
+                Some(renderer) => self.print_text(
+                    &mut printer,
+                    renderer,
+                    &item.name,
+                    crate::text_renderer::TextStyle::default(),
                 )?,
-            )?;
+                None => {
+                    printer.writeln(&item.sku)?;
+                }
+            }
+            printer
+                .justify(escpos::utils::JustifyMode::RIGHT)?
+                .writeln(&format!("{} pcs  {} yen", item.qty, item.revenue))
+                .context("Failed to write item total")?
+                .justify(escpos::utils::JustifyMode::LEFT)?;
         }
 
-        // フランクフルト（ケチャップなし）
-        if ff_no_ketchup > 0 {
-            let img_path = format!("./img/ffnoketchup/line_{}.png", ff_no_ketchup);
-            printer.bit_image_option(
-                &img_path,
-                escpos::utils::BitImageOption::new(
-                    Some(600),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?;
-        }
-
-        // 物理本（10ずつ分割: book_phys_1, book_phys_2, book_phys_3）
-        let mut remaining = book;
-        let mut batch = 1;
-        while remaining > 0 && batch <= 3 {
-            let count_in_batch = std::cmp::min(remaining, 10);
-            let img_path = format!("./img/book_phys_{}/line_{}.png", batch, count_in_batch);
-            printer.bit_image_option(
-                &img_path,
-                escpos::utils::BitImageOption::new(
-                    Some(600),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?;
-
-            remaining -= count_in_batch;
-            batch += 1;
+        if !report.discounts.is_empty() {
+            printer.writeln("------------------------------------------")?;
+            for discount in &report.discounts {
+                match &self.text_renderer {
+                    Some(renderer) => self.print_text(
+                        &mut printer,
+                        renderer,
+                        &discount.name,
+                        crate::text_renderer::TextStyle::default(),
+                    )?,
+                    None => {
+                        printer.writeln("Set discount")?;
+                    }
+                }
+                printer
+                    .justify(escpos::utils::JustifyMode::RIGHT)?
+                    .writeln(&format!("x {}  -{} yen", discount.sets, discount.amount))
+                    .context("Failed to write discount")?
+                    .justify(escpos::utils::JustifyMode::LEFT)?;
+            }
         }
 
-        // PDFの本
-        if pdf_book > 0 {
-            let img_path = format!("./img/book_pdf/line_{}.png", pdf_book);
-            printer.bit_image_option(
-                &img_path,
-                escpos::utils::BitImageOption::new(
-                    Some(600),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?;
-        }
+        printer
+            .writeln("------------------------------------------")?
+            .writeln(&format!("Orders: {}", report.orders))?
+            .writeln(&format!(
+                "PDF: {} payments, {} copies  {} yen",
+                report.pdf_payments, report.pdfs_issued, report.pdf_revenue
+            ))?
+            .size(2, 2)?
+            .writeln(&format!("Total: {} yen", report.total))
+            .context("Failed to write total")?
+            .size(1, 1)?;
 
-        // 飲み物
-        if drink > 0 {
-            let img_path = format!("./img/drink/line_{}.png", drink);
-            printer.bit_image_option(
-                &img_path,
-                escpos::utils::BitImageOption::new(
-                    Some(600),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?;
+        if report.kind == crate::reports::ReportKind::Z {
+            printer
+                .justify(escpos::utils::JustifyMode::CENTER)?
+                .writeln("*** DAY CLOSED ***")?;
         }
 
-        // 合計金額とフッター画像を追加して印刷完了
         printer
-            .bit_image_option(
-                "./img/white.png",
-                escpos::utils::BitImageOption::new(
-                    Some(320),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
-            .size(2, 2)?
-            .writeln(&format!("Total: {} yen", total))
-            .context("Failed to write total")?
-            .size(1, 1)?
-            .bit_image_option(
-                "./img/white.png",
-                escpos::utils::BitImageOption::new(
-                    Some(400),
-                    None,
-                    escpos::utils::BitImageSize::Normal,
-                )?,
-            )?
-            .writeln("Thank you!")
-            .context("Failed to write footer")?
             .feed()
             .context("Failed to feed")?
             .print_cut()
@@ -612,8 +601,37 @@ impl ReceiptPrinter {
         Ok(())
     }
 
+    /// 印刷せずにレシートのESC/POSを生成し，印字幅`dot_width`ドットのPNGに描画する
+    pub fn preview_png(&self, preview: Preview<'_>, dot_width: u32) -> anyhow::Result<Vec<u8>> {
+        std::fs::create_dir_all(&self.receipts_dir)
+            .context("Failed to create receipts directory")?;
+        let path = self
+            .receipts_dir
+            .join(format!("preview_{}.bin", uuid::Uuid::new_v4()));
+
+        let generated = match preview {
+            Preview::PdfReceipt {
+                pdf_url,
+                pdf_id,
+                paid_at,
+                count,
+            } => self.generate_receipt(&path, pdf_url, pdf_id, paid_at, count),
+            Preview::Tag { tag, lines } => self.generate_tag(&path, tag, lines),
+            Preview::OrderReceipt {
+                tag,
+                lines,
+                breakdown,
+            } => self.generate_order_receipt(&path, tag, lines, breakdown),
+            Preview::Report(report) => self.generate_daily_report(&path, report),
+        };
+        let data = generated.and_then(|_| std::fs::read(&path).context("Failed to read preview"));
+        let _ = std::fs::remove_file(&path);
+
+        crate::receipt_preview::render_png(&data?, dot_width, self.text_renderer.as_ref())
+    }
+
     // 紙詰まり時などに紙を切る
-    pub async fn cut_paper(&self) -> anyhow::Result<()> {
+    pub async fn cut_paper(&self, printer: &crate::printers::Printer) -> anyhow::Result<()> {
         tokio::fs::create_dir_all(&self.receipts_dir)
             .await
             .context("Failed to create receipts directory")?;
@@ -623,9 +641,17 @@ impl ReceiptPrinter {
 
         self.generate_cut(&receipt_path)?;
 
-        self.send_to_printer(&receipt_path).await?;
+        // 紙詰まりからの復旧用なのでキューを通さず直接送る
+        let data = tokio::fs::read(&receipt_path)
+            .await
+            .context("Failed to read cut command")?;
+        printer.send(&data).await?;
 
-        println!("✓ Paper cut command sent: {}", receipt_filename);
+        tracing::info!(
+            "Paper cut command sent to {}: {}",
+            printer.name,
+            receipt_filename
+        );
 
         Ok(())
     }
@@ -0,0 +1,347 @@
+use anyhow::Context as _;
+
+/// レシートのレイアウト（上から順に印刷するブロックの並び）
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Template {
+    pub blocks: Vec<Block>,
+}
+
+/// レイアウトを構成するブロック
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Block {
+    /// 画像を`width`ドットに縮小して印刷する
+    Image {
+        path: String,
+        width: u32,
+        #[serde(default)]
+        justify: Justify,
+    },
+    /// 文字列（`{tag}`などのプレースホルダーを置き換える）
+    ///
+    /// プリンター内蔵のフォントはASCIIしか印字できないので，日本語は`font = true`で`FONT_PATH`のフォントで描画する
+    Text {
+        text: String,
+        /// 横と縦の倍率（1〜8）
+        #[serde(default = "default_size")]
+        size: [u8; 2],
+        #[serde(default)]
+        justify: Justify,
+        #[serde(default)]
+        bold: bool,
+        #[serde(default)]
+        font: bool,
+    },
+    /// 空白（ドット）
+    Spacer { dots: u32 },
+    /// QRコード（`size`はモジュール1個のドット数）
+    Qr {
+        data: String,
+        #[serde(default = "default_qr_size")]
+        size: u8,
+        #[serde(default)]
+        justify: Justify,
+    },
+    /// 注文の品目（`subtotals`なら単価×数量の小計も印字する）
+    Items {
+        #[serde(default)]
+        subtotals: bool,
+    },
+    /// 適用したセット割引
+    Discounts,
+    /// 合計金額
+    Total {
+        #[serde(default = "default_total_size")]
+        size: [u8; 2],
+        #[serde(default)]
+        justify: Justify,
+    },
+    /// 紙を送って切る
+    Cut,
+}
+
+/// 寄せ（省略時は中央）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Justify {
+    Left,
+    #[default]
+    Center,
+    Right,
+}
+
+impl Justify {
+    pub fn escpos(self) -> escpos::utils::JustifyMode {
+        match self {
+            Justify::Left => escpos::utils::JustifyMode::LEFT,
+            Justify::Center => escpos::utils::JustifyMode::CENTER,
+            Justify::Right => escpos::utils::JustifyMode::RIGHT,
+        }
+    }
+
+    pub fn text_align(self) -> crate::text_renderer::TextAlign {
+        match self {
+            Justify::Left => crate::text_renderer::TextAlign::Left,
+            Justify::Center => crate::text_renderer::TextAlign::Center,
+            Justify::Right => crate::text_renderer::TextAlign::Right,
+        }
+    }
+}
+
+fn default_size() -> [u8; 2] {
+    [1, 1]
+}
+
+fn default_total_size() -> [u8; 2] {
+    [2, 2]
+}
+
+fn default_qr_size() -> u8 {
+    4
+}
+
+/// レシートの種類ごとのテンプレートファイル名と使えるプレースホルダー
+struct Kind {
+    file: &'static str,
+    vars: &'static [&'static str],
+    /// 品目のブロックを使えるか
+    items: bool,
+    /// 割引と合計のブロックを使えるか
+    totals: bool,
+}
+
+const PDF_RECEIPT: Kind = Kind {
+    file: "pdf_receipt.toml",
+    vars: &["pdf_url", "pdf_id", "pdf_id_short", "paid_at", "count"],
+    items: false,
+    totals: false,
+};
+
+const TAG: Kind = Kind {
+    file: "tag.toml",
+    vars: &["tag"],
+    items: true,
+    totals: false,
+};
+
+const ORDER_RECEIPT: Kind = Kind {
+    file: "order_receipt.toml",
+    vars: &["tag", "total"],
+    items: true,
+    totals: true,
+};
+
+/// `templates/`以下のレシートのテンプレート
+#[derive(Debug, Clone)]
+pub struct Templates {
+    /// `/print/pdf`のQRコード付きレシート
+    pub pdf_receipt: Template,
+    /// 呼び出し番号タグ
+    pub tag: Template,
+    /// 注文レシート
+    pub order_receipt: Template,
+}
+
+impl Templates {
+    pub fn load(dir: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            pdf_receipt: Template::load(dir, &PDF_RECEIPT)?,
+            tag: Template::load(dir, &TAG)?,
+            order_receipt: Template::load(dir, &ORDER_RECEIPT)?,
+        })
+    }
+
+    /// フォントで描画する文字列があるか
+    pub fn uses_font(&self) -> bool {
+        [&self.pdf_receipt, &self.tag, &self.order_receipt]
+            .iter()
+            .flat_map(|template| &template.blocks)
+            .any(|block| matches!(block, Block::Text { font: true, .. }))
+    }
+
+    /// テンプレートが使う画像のパス
+    pub fn image_paths(&self) -> Vec<&str> {
+        [&self.pdf_receipt, &self.tag, &self.order_receipt]
+            .iter()
+            .flat_map(|template| &template.blocks)
+            .filter_map(|block| match block {
+                Block::Image { path, .. } => Some(path.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Template {
+    fn load(dir: &str, kind: &Kind) -> anyhow::Result<Self> {
+        let path = std::path::Path::new(dir).join(kind.file);
+        let text = std::fs::read_to_string(&path)
+            .context(format!("failed to read template: {}", path.display()))?;
+        let template: Template = toml::from_str(&text)
+            .context(format!("failed to parse template: {}", path.display()))?;
+
+        template
+            .validate(kind)
+            .context(format!("invalid template: {}", path.display()))?;
+
+        Ok(template)
+    }
+
+    /// 印刷するときに失敗しないよう，読み込んだ時点でプレースホルダーなどを確かめる（画像は自己診断で確かめる）
+    fn validate(&self, kind: &Kind) -> anyhow::Result<()> {
+        for block in &self.blocks {
+            match block {
+                Block::Image { path, width, .. } => {
+                    if *width == 0 {
+                        anyhow::bail!("image width must be positive: {}", path);
+                    }
+                }
+                Block::Text {
+                    text, size, font, ..
+                } => {
+                    check_size(*size)?;
+                    for name in placeholders(text)? {
+                        if !kind.vars.contains(&name) {
+                            anyhow::bail!("unknown placeholder {{{}}} in {:?}", name, text);
+                        }
+                    }
+                    if !font && !text.is_ascii() {
+                        anyhow::bail!("non-ASCII text needs font = true: {:?}", text);
+                    }
+                }
+                Block::Qr { data, size, .. } => {
+                    if !(1..=16).contains(size) {
+                        anyhow::bail!("QR code size must be 1-16: {}", size);
+                    }
+                    for name in placeholders(data)? {
+                        if !kind.vars.contains(&name) {
+                            anyhow::bail!("unknown placeholder {{{}}} in {:?}", name, data);
+                        }
+                    }
+                }
+                Block::Items { .. } if !kind.items => {
+                    anyhow::bail!("items block is not available in {}", kind.file);
+                }
+                Block::Discounts | Block::Total { .. } if !kind.totals => {
+                    anyhow::bail!(
+                        "discounts and total blocks are only available in order receipts"
+                    );
+                }
+                Block::Total { size, .. } => check_size(*size)?,
+                Block::Spacer { .. } | Block::Items { .. } | Block::Discounts | Block::Cut => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn check_size(size: [u8; 2]) -> anyhow::Result<()> {
+    if size.iter().any(|n| !(1..=8).contains(n)) {
+        anyhow::bail!("text size must be 1-8: {:?}", size);
+    }
+    Ok(())
+}
+
+/// 文字列中の`{name}`を列挙する
+fn placeholders(text: &str) -> anyhow::Result<Vec<&str>> {
+    let mut names = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .context(format!("unclosed placeholder in {:?}", text))?;
+        names.push(&rest[start + 1..start + end]);
+        rest = &rest[start + end + 1..];
+    }
+    Ok(names)
+}
+
+/// `{name}`を`vars`の値に置き換える（値の中の`{...}`はそのまま残す）
+pub fn fill(text: &str, vars: &[(&str, String)]) -> String {
+    let mut filled = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 1..start + end];
+        filled.push_str(&rest[..start]);
+        match vars.iter().find(|(var, _)| *var == name) {
+            Some((_, value)) => filled.push_str(value),
+            None => filled.push_str(&rest[start..=start + end]),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    filled.push_str(rest);
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    fn template(text: &str) -> super::Template {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn shipped_templates_are_valid() {
+        let templates =
+            super::Templates::load(concat!(env!("CARGO_MANIFEST_DIR"), "/templates")).unwrap();
+        assert!(!templates.pdf_receipt.blocks.is_empty());
+        assert!(!templates.tag.blocks.is_empty());
+        assert!(!templates.order_receipt.blocks.is_empty());
+    }
+
+    #[test]
+    fn validate_checks_placeholders_and_blocks() {
+        let valid = template(
+            r#"
+            [[blocks]]
+            type = "text"
+            text = "No. {tag}"
+
+            [[blocks]]
+            type = "items"
+            "#,
+        );
+        assert!(valid.validate(&super::TAG).is_ok());
+        // 注文の品目はPDFのレシートでは使えない
+        assert!(valid.validate(&super::PDF_RECEIPT).is_err());
+
+        for invalid in [
+            "[[blocks]]\ntype = \"text\"\ntext = \"{total}\"",
+            "[[blocks]]\ntype = \"text\"\ntext = \"{tag\"",
+            "[[blocks]]\ntype = \"text\"\ntext = \"呼び出し番号\"",
+            "[[blocks]]\ntype = \"text\"\ntext = \"x\"\nsize = [9, 1]",
+            "[[blocks]]\ntype = \"qr\"\ndata = \"{tag}\"\nsize = 0",
+            "[[blocks]]\ntype = \"image\"\npath = \"logo.png\"\nwidth = 0",
+            "[[blocks]]\ntype = \"total\"",
+        ] {
+            assert!(
+                template(invalid).validate(&super::TAG).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn placeholders_lists_names_and_rejects_unclosed_braces() {
+        assert_eq!(
+            super::placeholders("{pdf_url} ({count})").unwrap(),
+            ["pdf_url", "count"]
+        );
+        assert!(super::placeholders("{pdf_url").is_err());
+    }
+
+    #[test]
+    fn fill_replaces_known_names() {
+        let vars = [("tag", "12".to_string()), ("total", "{tag}".to_string())];
+        assert_eq!(super::fill("No. {tag}!", &vars), "No. 12!");
+        // 値の中の`{...}`は置き換えない
+        assert_eq!(super::fill("{total}", &vars), "{tag}");
+        // 知らない名前と閉じていない`{`はそのまま残す
+        assert_eq!(super::fill("{name} {tag}", &vars), "{name} 12");
+        assert_eq!(super::fill("{tag} {tag", &vars), "12 {tag");
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::Context as _;
+
+/// ファイルの変更が続いている間は読み直しを待つ時間（エディタは1回の保存で何度も書き込む）
+const DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// 設定を読み直すたびに丸ごと入れ替わる状態
+pub struct Live<T> {
+    current: arc_swap::ArcSwap<T>,
+}
+
+impl<T> Live<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            current: arc_swap::ArcSwap::from_pointee(value),
+        }
+    }
+
+    pub fn current(&self) -> std::sync::Arc<T> {
+        self.current.load_full()
+    }
+
+    /// 新しい状態に入れ替える（`current`で取り出し済みの古い状態は使い終わるまで残る）
+    pub fn replace(&self, value: T) {
+        self.current.store(std::sync::Arc::new(value));
+    }
+}
+
+/// リクエストを受け付けた時点の状態
+///
+/// 処理中に設定を読み直しても，そのリクエストは最後まで同じ状態で処理する
+pub struct Current<T>(std::sync::Arc<T>);
+
+impl<T> Clone for Current<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> std::ops::Deref for Current<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: 'static> actix_web::FromRequest for Current<T> {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &actix_web::HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        std::future::ready(
+            req.app_data::<actix_web::web::Data<Live<T>>>()
+                .map(|live| Current(live.current()))
+                .ok_or_else(|| {
+                    actix_web::error::ErrorInternalServerError(
+                        "application state is not configured",
+                    )
+                }),
+        )
+    }
+}
+
+/// ファイルとディレクトリの変更を監視する
+///
+/// ファイルはエディタが置き換えても追えるよう親ディレクトリごと監視し，`files`そのものと`dirs`直下の作成・変更・削除だけを通知する
+pub struct FileWatcher {
+    _watcher: notify::RecommendedWatcher,
+    changes: tokio::sync::mpsc::UnboundedReceiver<std::path::PathBuf>,
+}
+
+impl FileWatcher {
+    pub fn new(files: &[String], dirs: &[String]) -> anyhow::Result<Self> {
+        let absolute =
+            |path: &String| std::path::absolute(path).context(format!("invalid path: {}", path));
+        let files = files
+            .iter()
+            .map(absolute)
+            .collect::<anyhow::Result<std::collections::BTreeSet<_>>>()?;
+        let dirs = dirs
+            .iter()
+            .map(absolute)
+            .collect::<anyhow::Result<std::collections::BTreeSet<_>>>()?;
+
+        let (tx, changes) = tokio::sync::mpsc::unbounded_channel();
+        let watched_files = files.clone();
+        let watched_dirs = dirs.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                // 印刷のために画像を読んだだけのイベントは無視する
+                if !(event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove()) {
+                    return;
+                }
+                for path in event.paths {
+                    if watched_files.contains(&path)
+                        || path.parent().is_some_and(|dir| watched_dirs.contains(dir))
+                    {
+                        tx.send(path).ok();
+                    }
+                }
+            })
+            .context("failed to start file watcher")?;
+
+        let targets: std::collections::BTreeSet<&std::path::Path> = files
+            .iter()
+            .filter_map(|file| file.parent())
+            .chain(dirs.iter().map(std::path::PathBuf::as_path))
+            .collect();
+        for target in targets {
+            if let Err(e) =
+                notify::Watcher::watch(&mut watcher, target, notify::RecursiveMode::NonRecursive)
+            {
+                tracing::warn!("Cannot watch {}: {}", target.display(), e);
+            }
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            changes,
+        })
+    }
+
+    /// 変更があり，その後`DEBOUNCE`の間変更が続かなくなるまで待つ（変更されたパスのうち1つを返す）
+    pub async fn changed(&mut self) -> Option<std::path::PathBuf> {
+        let path = self.changes.recv().await?;
+        loop {
+            tokio::select! {
+                more = self.changes.recv() => more?,
+                _ = tokio::time::sleep(DEBOUNCE) => return Some(path),
+            };
+        }
+    }
+}
@@ -0,0 +1,154 @@
+/// XレポートとZレポート
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportKind {
+    /// 途中経過（営業日を締めない）
+    X,
+    /// 締め（1営業日に1回だけ）
+    Z,
+}
+
+impl ReportKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "x" | "X" => Some(ReportKind::X),
+            "z" | "Z" => Some(ReportKind::Z),
+            _ => None,
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            ReportKind::X => "X-REPORT",
+            ReportKind::Z => "Z-REPORT",
+        }
+    }
+}
+
+/// 品目ごとの販売数と売上
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ReportItem {
+    pub sku: String,
+    pub name: String,
+    pub qty: u32,
+    pub revenue: u32,
+}
+
+/// 1営業日（日本時間）の売上レポート
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DailyReport {
+    pub kind: ReportKind,
+    /// 営業日（YYYY-MM-DD）
+    pub date: String,
+    #[serde(rename = "generatedAt")]
+    pub generated_at: i64,
+    /// 締めた日時（Xレポートでは締め済みの場合のみ）
+    #[serde(rename = "closedAt")]
+    pub closed_at: Option<i64>,
+    pub orders: u32,
+    pub items: Vec<ReportItem>,
+    pub discounts: Vec<crate::pricing::AppliedDiscount>,
+    /// 注文とPDFの支払いの合計
+    pub total: u32,
+    /// `/print/pdf`の支払い件数
+    #[serde(rename = "pdfPayments")]
+    pub pdf_payments: u32,
+    /// `/print/pdf`の支払いの合計
+    #[serde(rename = "pdfRevenue")]
+    pub pdf_revenue: u32,
+    /// 発行した署名付きPDFの枚数
+    #[serde(rename = "pdfsIssued")]
+    pub pdfs_issued: u32,
+}
+
+impl DailyReport {
+    /// カタログの全品目を並び順に並べ（売れていない品目は0），カタログから消えた品目を後ろに付ける
+    ///
+    /// PDF版（`pdf_sku`）は品目ではなくPDFの支払いとして載せる
+    pub fn new(
+        kind: ReportKind,
+        date: &str,
+        generated_at: i64,
+        closed_at: Option<i64>,
+        catalog: &crate::catalog::Catalog,
+        summary: crate::ledger::SalesSummary,
+    ) -> Self {
+        let mut sold = summary.items;
+        let mut items: Vec<ReportItem> = catalog
+            .items()
+            .iter()
+            .filter(|item| Some(item.sku.as_str()) != catalog.pdf_sku())
+            .map(|item| {
+                let (qty, revenue) = match sold.iter().position(|line| line.sku == item.sku) {
+                    Some(i) => {
+                        let line = sold.remove(i);
+                        (line.qty, line.subtotal)
+                    }
+                    None => (0, 0),
+                };
+                ReportItem {
+                    sku: item.sku.clone(),
+                    name: item.name.clone(),
+                    qty,
+                    revenue,
+                }
+            })
+            .collect();
+        items.extend(sold.into_iter().map(|line| ReportItem {
+            sku: line.sku,
+            name: line.name,
+            qty: line.qty,
+            revenue: line.subtotal,
+        }));
+
+        Self {
+            kind,
+            date: date.to_string(),
+            generated_at,
+            closed_at,
+            orders: summary.orders,
+            items,
+            discounts: summary.discounts,
+            total: summary.total,
+            pdf_payments: summary.pdf_payments,
+            pdf_revenue: summary.pdf_revenue,
+            pdfs_issued: summary.pdfs_issued,
+        }
+    }
+
+    /// 表計算ソフトに貼り付けられるCSV（品目，割引，PDF，合計の順）
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("type,sku,name,qty,amount\n");
+        for item in &self.items {
+            csv.push_str(&format!(
+                "item,{},{},{},{}\n",
+                csv_field(&item.sku),
+                csv_field(&item.name),
+                item.qty,
+                item.revenue
+            ));
+        }
+        for discount in &self.discounts {
+            csv.push_str(&format!(
+                "discount,,{},{},-{}\n",
+                csv_field(&discount.name),
+                discount.sets,
+                discount.amount
+            ));
+        }
+        csv.push_str(&format!(
+            "pdf,,pdf payments,{},{}\npdf,,pdfs issued,{},\n",
+            self.pdf_payments, self.pdf_revenue, self.pdfs_issued
+        ));
+        csv.push_str(&format!("total,,orders,{},{}\n", self.orders, self.total));
+        csv
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
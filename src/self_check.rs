@@ -0,0 +1,356 @@
+use anyhow::Context as _;
+
+/// 端末の鍵の最短の長さ
+const MIN_CLIENT_KEY_LEN: usize = 16;
+
+/// 自己診断の1項目の結果
+enum Outcome {
+    Ok(String),
+    /// 起動はできるが，印刷などが失敗するかもしれない
+    Warning(String),
+    /// このままでは起動しない
+    Failed(String),
+}
+
+/// 起動時の自己診断の結果
+pub struct Report {
+    entries: Vec<(String, Outcome)>,
+}
+
+impl Report {
+    fn push(&mut self, name: impl Into<String>, result: anyhow::Result<String>) {
+        let outcome = match result {
+            Ok(detail) => Outcome::Ok(detail),
+            Err(e) => Outcome::Failed(format!("{:#}", e)),
+        };
+        self.entries.push((name.into(), outcome));
+    }
+
+    fn warn(&mut self, name: impl Into<String>, detail: String) {
+        self.entries.push((name.into(), Outcome::Warning(detail)));
+    }
+
+    /// 起動を止める問題の数
+    pub fn failures(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, Outcome::Failed(_)))
+            .count()
+    }
+
+    fn warnings(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, Outcome::Warning(_)))
+            .count()
+    }
+
+    /// 項目ごとに1件ずつログに出す（`check`フィールドに項目名が入る）
+    pub fn log(&self) {
+        for (name, outcome) in &self.entries {
+            match outcome {
+                Outcome::Ok(detail) => tracing::info!(check = %name, "{}: {}", name, detail),
+                Outcome::Warning(detail) => tracing::warn!(check = %name, "{}: {}", name, detail),
+                Outcome::Failed(detail) => tracing::error!(check = %name, "{}: {}", name, detail),
+            }
+        }
+        tracing::info!(
+            problems = self.failures(),
+            warnings = self.warnings(),
+            "Self-check finished: {} problem(s), {} warning(s)",
+            self.failures(),
+            self.warnings()
+        );
+    }
+}
+
+/// 設定が参照するファイルやプリンターを起動時にまとめて確かめる
+///
+/// 最初の売上で初めて失敗しないよう，見つかった問題をすべて報告する．プリンターの問題は警告にとどめる（印刷ジョブは復旧を待って送られる）
+pub fn run(config: &crate::config::Config) -> Report {
+    let mut report = Report {
+        entries: Vec::new(),
+    };
+
+    report.push("bind address", check_bind_address(&config.bind_address));
+    report.push(
+        "public base URL",
+        check_public_base_url(&config.public_base_url),
+    );
+    report.push(
+        "printer status interval",
+        check_status_interval(config.printer_status_interval_secs),
+    );
+    report.push(
+        "printer dot width",
+        check_dot_width(config.printer_dot_width),
+    );
+    report.push("base PDF", check_base_pdf(&config.base_pdf_path));
+    report.push(
+        "signing key",
+        crate::pdf_handler::PdfHandler::new(&config.cert_path, &config.key_path)
+            .map(|_| format!("{} / {}", config.cert_path, config.key_path)),
+    );
+
+    let mut needs_font = Vec::new();
+    match crate::catalog::Catalog::load(&config.catalog_path) {
+        Ok(catalog) => {
+            report.push(
+                "catalog",
+                Ok(format!(
+                    "{} ({} items)",
+                    config.catalog_path,
+                    catalog.items().len()
+                )),
+            );
+            for item in catalog.items() {
+                if let crate::catalog::ItemLabel::Images { dirs, per_image } = &item.label {
+                    report.push(
+                        format!("label images {}", item.sku),
+                        check_label_images(dirs, *per_image),
+                    );
+                }
+            }
+            if catalog.has_text_labels() {
+                needs_font.push("catalog has text labels");
+            }
+        }
+        Err(e) => report.push("catalog", Err(e)),
+    }
+
+    match crate::receipt_template::Templates::load(&config.templates_dir) {
+        Ok(templates) => {
+            report.push("templates", Ok(config.templates_dir.clone()));
+            let paths: std::collections::BTreeSet<&str> =
+                templates.image_paths().into_iter().collect();
+            for path in paths {
+                report.push(format!("template image {}", path), check_image(path));
+            }
+            if templates.uses_font() {
+                needs_font.push("templates use font = true");
+            }
+        }
+        Err(e) => report.push("templates", Err(e)),
+    }
+
+    match &config.font_path {
+        Some(path) => report.push(
+            "font",
+            crate::text_renderer::TextRenderer::from_file(path, config.printer_dot_width)
+                .map(|_| path.clone()),
+        ),
+        None if !needs_font.is_empty() => report.push(
+            "font",
+            Err(anyhow::anyhow!(
+                "FONT_PATH is not set but {}",
+                needs_font.join(" and ")
+            )),
+        ),
+        None => {}
+    }
+
+    match config.storage.backend.as_str() {
+        "s3" => report.push(
+            "storage",
+            config
+                .storage
+                .bucket_name
+                .as_ref()
+                .map(|bucket| format!("s3 ({})", bucket))
+                .context("R2_BUCKET_NAME is required for s3 storage"),
+        ),
+        "local" => report.push(
+            "storage",
+            check_writable_dir(&config.storage.dir).map(|dir| format!("local ({})", dir)),
+        ),
+        "memory" => report.warn(
+            "storage",
+            "memory (signed PDFs are lost on restart)".to_string(),
+        ),
+        other => report.push(
+            "storage",
+            Err(anyhow::anyhow!("unknown storage backend: {}", other)),
+        ),
+    }
+    report.push(
+        "signed PDF directory",
+        check_writable_dir(&config.signed_pdf_dir),
+    );
+    report.push(
+        "receipts directory",
+        check_writable_dir(&config.receipts_dir),
+    );
+
+    check_auth(&mut report, config);
+    for origin in &config.cors_allowed_origins {
+        let name = format!("CORS origin {}", origin);
+        if origin == "*" {
+            report.warn(
+                name,
+                "any website can call the API from a browser".to_string(),
+            );
+        } else {
+            report.push(name, check_origin(origin));
+        }
+    }
+
+    match crate::printers::Printers::from_config(config, None) {
+        Ok(printers) => {
+            for printer in printers.iter() {
+                let name = format!("printer {}", printer.name);
+                match printer.check() {
+                    Ok(()) => report.push(name, Ok(printer.transport_name())),
+                    Err(e) => report.warn(name, format!("{}: {:#}", printer.transport_name(), e)),
+                }
+            }
+        }
+        Err(e) => report.push("printers", Err(e)),
+    }
+
+    report
+}
+
+/// 端末の設定（認証を求めないときは警告する）
+fn check_auth(report: &mut Report, config: &crate::config::Config) {
+    if !config.require_auth {
+        report.warn(
+            "authentication",
+            "disabled (anyone on the network can print and issue PDFs)".to_string(),
+        );
+        return;
+    }
+    if config.clients.is_empty() {
+        report.push(
+            "authentication",
+            Err(anyhow::anyhow!(
+                "no [clients] configured (set REQUIRE_AUTH=false to test without keys)"
+            )),
+        );
+        return;
+    }
+
+    let mut roles: std::collections::BTreeMap<&str, Vec<&str>> = std::collections::BTreeMap::new();
+    for (name, client) in &config.clients {
+        roles.entry(client.role.as_str()).or_default().push(name);
+        report.push(
+            format!("client {}", name),
+            check_client_key(config, name, client),
+        );
+    }
+    report.push(
+        "authentication",
+        Ok(roles
+            .iter()
+            .map(|(role, names)| format!("{}: {}", role, names.join(", ")))
+            .collect::<Vec<_>>()
+            .join("; ")),
+    );
+}
+
+/// 推測されにくく，ほかの端末と重ならない鍵か
+fn check_client_key(
+    config: &crate::config::Config,
+    name: &str,
+    client: &crate::config::ClientConfig,
+) -> anyhow::Result<String> {
+    if client.key.len() < MIN_CLIENT_KEY_LEN {
+        anyhow::bail!("key must be at least {} characters", MIN_CLIENT_KEY_LEN);
+    }
+    if let Some((other, _)) = config
+        .clients
+        .iter()
+        .find(|(other, other_client)| other.as_str() != name && other_client.key == client.key)
+    {
+        anyhow::bail!("same key as client {}", other);
+    }
+    Ok(client.role.as_str().to_string())
+}
+
+/// `scheme://host[:port]`の形か（ブラウザーが送る`Origin`と完全に一致したときだけ許可する）
+fn check_origin(origin: &str) -> anyhow::Result<String> {
+    let host = origin
+        .strip_prefix("http://")
+        .or_else(|| origin.strip_prefix("https://"))
+        .context(format!("must start with http:// or https://: {}", origin))?;
+    if host.is_empty() || host.contains('/') {
+        anyhow::bail!("must not have a path or trailing slash: {}", origin);
+    }
+    Ok("allowed".to_string())
+}
+
+/// 0秒ごとだとプリンターにDLE EOTを送り続けてしまう
+fn check_status_interval(secs: u64) -> anyhow::Result<String> {
+    if secs == 0 {
+        anyhow::bail!("PRINTER_STATUS_INTERVAL_SECS must be at least 1");
+    }
+    Ok(format!("{}s", secs))
+}
+
+/// 印字幅はプレビューやレイアウトで割る数に使う
+fn check_dot_width(width: u32) -> anyhow::Result<String> {
+    if width == 0 {
+        anyhow::bail!("PRINTER_DOT_WIDTH must be at least 1");
+    }
+    Ok(format!("{} dots", width))
+}
+
+fn check_bind_address(address: &str) -> anyhow::Result<String> {
+    std::net::ToSocketAddrs::to_socket_addrs(address)
+        .context(format!("invalid bind address: {}", address))?;
+    Ok(address.to_string())
+}
+
+fn check_public_base_url(url: &str) -> anyhow::Result<String> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        anyhow::bail!("must start with http:// or https://: {}", url);
+    }
+    Ok(url.to_string())
+}
+
+fn check_base_pdf(path: &str) -> anyhow::Result<String> {
+    if path.is_empty() {
+        anyhow::bail!("BASE_PDF_PATH is not set");
+    }
+    let pdf = std::fs::read(path).context(format!("failed to read {}", path))?;
+    if !pdf.starts_with(b"%PDF-") {
+        anyhow::bail!("not a PDF file: {}", path);
+    }
+    Ok(format!("{} ({} bytes)", path, pdf.len()))
+}
+
+/// 品目の画像がいくつまでの数量に対応しているか
+///
+/// 数量`n`は`line_{n}.png`で，`per_image`を超える分は次のディレクトリに続けて印刷する
+fn check_label_images(dirs: &[String], per_image: u32) -> anyhow::Result<String> {
+    let mut max_qty = 0;
+    for dir in dirs {
+        let available = (1..=per_image)
+            .take_while(|n| check_image(&format!("{}/line_{}.png", dir, n)).is_ok())
+            .count() as u32;
+        max_qty += available;
+        if available < per_image {
+            break;
+        }
+    }
+
+    if max_qty == 0 {
+        let first = dirs.first().map(String::as_str).unwrap_or_default();
+        anyhow::bail!("{}/line_1.png not found", first);
+    }
+    Ok(format!("up to {} per order", max_qty))
+}
+
+fn check_image(path: &str) -> anyhow::Result<String> {
+    let (width, height) =
+        image::image_dimensions(path).context(format!("failed to read image: {}", path))?;
+    Ok(format!("{}x{}", width, height))
+}
+
+/// ディレクトリを作り，書き込めるか確かめる
+fn check_writable_dir(dir: &str) -> anyhow::Result<String> {
+    std::fs::create_dir_all(dir).context(format!("failed to create directory: {}", dir))?;
+    let probe = std::path::Path::new(dir).join(".kawauso-self-check");
+    std::fs::write(&probe, b"").context(format!("directory is not writable: {}", dir))?;
+    std::fs::remove_file(&probe).ok();
+    Ok(dir.to_string())
+}
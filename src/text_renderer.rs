@@ -0,0 +1,268 @@
+use ab_glyph::{Font as _, ScaleFont as _};
+use anyhow::Context as _;
+
+/// テキスト描画のスタイル
+#[derive(Debug, Clone, Copy)]
+pub struct TextStyle {
+    /// 文字の高さ（ドット）
+    pub px_height: f32,
+    /// 行の寄せ
+    pub align: TextAlign,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            px_height: 32.0,
+            align: TextAlign::Left,
+        }
+    }
+}
+
+/// 印字幅の中での行の寄せ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+/// TrueType/OpenTypeフォントで任意のUTF-8文字列を単色ビットマップに描画する
+pub struct TextRenderer {
+    font: ab_glyph::FontArc,
+    dot_width: u32,
+}
+
+impl TextRenderer {
+    pub fn from_file(path: &str, dot_width: u32) -> anyhow::Result<Self> {
+        // ESC/POSのビットイメージは幅が8ドット単位
+        if dot_width == 0 || !dot_width.is_multiple_of(8) {
+            anyhow::bail!("printer dot width must be a multiple of 8: {}", dot_width);
+        }
+
+        let data = std::fs::read(path).context(format!("failed to read font: {}", path))?;
+        let font = ab_glyph::FontArc::try_from_vec(data)
+            .map_err(|e| anyhow::anyhow!("failed to parse font {}: {}", path, e))?;
+
+        Ok(Self { font, dot_width })
+    }
+
+    pub fn dot_width(&self) -> u32 {
+        self.dot_width
+    }
+
+    /// 文字列を印字幅いっぱいの単色画像に描画する（幅を超える行は折り返す）
+    pub fn render(&self, text: &str, style: TextStyle) -> image::GrayImage {
+        let scaled = self
+            .font
+            .as_scaled(ab_glyph::PxScale::from(style.px_height));
+        let line_height = (scaled.ascent() - scaled.descent() + scaled.line_gap()).ceil();
+        let lines = self.layout_lines(text, style.px_height);
+
+        let height = ((lines.len().max(1) as f32) * line_height).ceil() as u32;
+        let mut img =
+            image::GrayImage::from_pixel(self.dot_width, height.max(1), image::Luma([255]));
+
+        for (row, line) in lines.iter().enumerate() {
+            let baseline = row as f32 * line_height + scaled.ascent();
+
+            let free = (self.dot_width as f32 - self.line_width(line, style.px_height)).max(0.0);
+            let mut caret = match style.align {
+                TextAlign::Left => 0.0,
+                TextAlign::Center => (free / 2.0).floor(),
+                TextAlign::Right => free,
+            };
+            let mut prev: Option<ab_glyph::GlyphId> = None;
+            for ch in line.chars() {
+                let glyph_id = scaled.glyph_id(ch);
+                if let Some(prev) = prev {
+                    caret += scaled.kern(prev, glyph_id);
+                }
+                let glyph = glyph_id
+                    .with_scale_and_position(style.px_height, ab_glyph::point(caret, baseline));
+                caret += scaled.h_advance(glyph_id);
+                prev = Some(glyph_id);
+
+                let Some(outlined) = self.font.outline_glyph(glyph) else {
+                    continue;
+                };
+                let bounds = outlined.px_bounds();
+                outlined.draw(|gx, gy, coverage| {
+                    // 感熱紙は濃淡を出せないので閾値で二値化する
+                    if coverage < 0.5 {
+                        return;
+                    }
+                    let x = bounds.min.x as i32 + gx as i32;
+                    let y = bounds.min.y as i32 + gy as i32;
+                    if x >= 0 && y >= 0 && (x as u32) < img.width() && (y as u32) < img.height() {
+                        img.put_pixel(x as u32, y as u32, image::Luma([0]));
+                    }
+                });
+            }
+        }
+
+        img
+    }
+
+    /// escposのビットイメージとして渡せるPNGバイト列に描画する
+    pub fn render_png(&self, text: &str, style: TextStyle) -> anyhow::Result<Vec<u8>> {
+        let img = self.render(text, style);
+        let mut png = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut png, image::ImageFormat::Png)
+            .context("failed to encode rendered text as PNG")?;
+
+        Ok(png.into_inner())
+    }
+
+    /// プリンター内蔵フォントの1文字分のセル（`width`×`height`ドット）に文字を描画する（プレビュー用）
+    pub fn draw_cell(
+        &self,
+        img: &mut image::GrayImage,
+        ch: char,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) {
+        // 半角文字の幅は高さのおよそ半分なので，セルの縦横比に合わせて横に伸縮する
+        let scale = ab_glyph::PxScale {
+            x: width as f32 * 2.0,
+            y: height as f32,
+        };
+        let scaled = self.font.as_scaled(scale);
+        let baseline = y as f32 + height as f32 * 0.8;
+        let glyph = scaled
+            .glyph_id(ch)
+            .with_scale_and_position(scale, ab_glyph::point(x as f32, baseline));
+
+        let Some(outlined) = self.font.outline_glyph(glyph) else {
+            return;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|gx, gy, coverage| {
+            if coverage < 0.5 {
+                return;
+            }
+            let px = bounds.min.x as i32 + gx as i32;
+            let py = bounds.min.y as i32 + gy as i32;
+            if px >= 0 && py >= 0 && (px as u32) < img.width() && (py as u32) < img.height() {
+                img.put_pixel(px as u32, py as u32, image::Luma([0]));
+            }
+        });
+    }
+
+    fn line_width(&self, line: &str, px_height: f32) -> f32 {
+        let scaled = self.font.as_scaled(ab_glyph::PxScale::from(px_height));
+        let mut width = 0.0;
+        let mut prev: Option<ab_glyph::GlyphId> = None;
+        for ch in line.chars() {
+            let glyph_id = scaled.glyph_id(ch);
+            if let Some(prev) = prev {
+                width += scaled.kern(prev, glyph_id);
+            }
+            width += scaled.h_advance(glyph_id);
+            prev = Some(glyph_id);
+        }
+        width
+    }
+
+    // 日本語は単語の区切りがないので、文字単位で印字幅に収まるように折り返す
+    //
+    // 行の幅は1文字ずつ足していく（行全体を測り直すと長い文字列で遅くなる）．印字幅より広い1文字はそのまま1行にする
+    fn layout_lines(&self, text: &str, px_height: f32) -> Vec<String> {
+        let scaled = self.font.as_scaled(ab_glyph::PxScale::from(px_height));
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            let mut current = String::new();
+            let mut width = 0.0;
+            let mut prev: Option<ab_glyph::GlyphId> = None;
+            for ch in paragraph.chars() {
+                let glyph_id = scaled.glyph_id(ch);
+                let kern = prev.map_or(0.0, |prev| scaled.kern(prev, glyph_id));
+                let advance = scaled.h_advance(glyph_id);
+                if !current.is_empty() && width + kern + advance > self.dot_width as f32 {
+                    lines.push(std::mem::take(&mut current));
+                    width = advance;
+                } else {
+                    width += kern + advance;
+                }
+                current.push(ch);
+                prev = Some(glyph_id);
+            }
+            lines.push(current);
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn renderer(dot_width: u32) -> super::TextRenderer {
+        super::TextRenderer::from_file(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/DejaVuSansMono.ttf"),
+            dot_width,
+        )
+        .unwrap()
+    }
+
+    /// 黒い画素の左端と右端のx座標
+    fn ink_columns(img: &image::GrayImage) -> (u32, u32) {
+        let xs: Vec<u32> = img
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel.0[0] == 0)
+            .map(|(x, _, _)| x)
+            .collect();
+        (*xs.iter().min().unwrap(), *xs.iter().max().unwrap())
+    }
+
+    #[test]
+    fn wraps_lines_at_the_dot_width() {
+        let renderer = renderer(384);
+        let text = "0123456789".repeat(5);
+        let lines = renderer.layout_lines(&text, 32.0);
+
+        assert!(lines.len() > 1);
+        assert_eq!(lines.concat(), text);
+        for (i, line) in lines.iter().enumerate() {
+            assert!(renderer.line_width(line, 32.0) <= 384.0);
+            // 次の行の最初の文字までは入らなかった
+            if let Some(next) = lines.get(i + 1) {
+                let longer = format!("{}{}", line, next.chars().next().unwrap());
+                assert!(renderer.line_width(&longer, 32.0) > 384.0);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_explicit_newlines_and_empty_lines() {
+        let renderer = renderer(384);
+        assert_eq!(renderer.layout_lines("ab\n\ncd", 32.0), ["ab", "", "cd"]);
+        assert_eq!(renderer.layout_lines("", 32.0), [""]);
+
+        let one = renderer.render("ab", super::TextStyle::default());
+        let three = renderer.render("ab\n\ncd", super::TextStyle::default());
+        assert_eq!(three.height(), one.height() * 3);
+    }
+
+    #[test]
+    fn puts_glyphs_wider_than_the_line_on_their_own_lines() {
+        let renderer = renderer(8);
+        assert_eq!(renderer.layout_lines("WW", 64.0), ["W", "W"]);
+    }
+
+    #[test]
+    fn aligns_lines() {
+        let renderer = renderer(384);
+        let style = |align| super::TextStyle {
+            px_height: 32.0,
+            align,
+        };
+        let (left, _) = ink_columns(&renderer.render("i", style(super::TextAlign::Left)));
+        let (center, _) = ink_columns(&renderer.render("i", style(super::TextAlign::Center)));
+        let (right, right_end) = ink_columns(&renderer.render("i", style(super::TextAlign::Right)));
+
+        assert!(left < 32);
+        assert!((160..224).contains(&center));
+        assert!(right > 320 && right_end < 384);
+    }
+}